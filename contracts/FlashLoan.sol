@@ -1,20 +1,156 @@
+// SPDX-License-Identifier: MIT
 pragma solidity ^0.8.0;
 
-import "@aave/protocol-v2/contracts/flashloan/base/FlashLoanReceiverBase.sol";
-import "@aave/protocol-v2/contracts/interfaces/ILendingPoolAddressesProvider.sol";
+interface IERC20 {
+    function balanceOf(address account) external view returns (uint256);
+    function transfer(address recipient, uint256 amount) external returns (bool);
+    function approve(address spender, uint256 amount) external returns (bool);
+}
+
+interface ILendingPoolAddressesProvider {
+    function getLendingPool() external view returns (address);
+}
 
-contract FlashLoanExample is FlashLoanReceiverBase {
-    address constant DAI_ADDRESS = 0x6B175474E89094C44Da98b954EedeAC495271d0F;
+interface ILendingPool {
+    function flashLoan(
+        address receiverAddress,
+        address[] calldata assets,
+        uint256[] calldata amounts,
+        uint256[] calldata modes,
+        address onBehalfOf,
+        bytes calldata params,
+        uint16 referralCode
+    ) external;
+}
 
-    constructor(ILendingPoolAddressesProvider _addressProvider) FlashLoanReceiverBase(_addressProvider) {}
+interface IUniswapV2Pair {
+    function token0() external view returns (address);
+    function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
+    function swap(uint256 amount0Out, uint256 amount1Out, address to, bytes calldata data) external;
+}
 
-    function executeFlashLoan(uint256 amount) public {
-        // Get the LendingPool contract address
-        address lendingPool = ADDRESS_PROVIDER.getLendingPool();
+interface IBalancerVault {
+    enum SwapKind { GIVEN_IN, GIVEN_OUT }
+
+    struct SingleSwap {
+        bytes32 poolId;
+        SwapKind kind;
+        address assetIn;
+        address assetOut;
+        uint256 amount;
+        bytes userData;
+    }
+
+    struct FundManagement {
+        address sender;
+        bool fromInternalBalance;
+        address payable recipient;
+        bool toInternalBalance;
+    }
+
+    function swap(
+        SingleSwap memory singleSwap,
+        FundManagement memory funds,
+        uint256 limit,
+        uint256 deadline
+    ) external payable returns (uint256);
+
+    function flashLoan(
+        address recipient,
+        address[] memory tokens,
+        uint256[] memory amounts,
+        bytes memory userData
+    ) external;
+}
 
-        // Request the flash loan
-        bytes memory data = "";
-        ILendingPool(lendingPool).flashLoan(address(this), DAI_ADDRESS, amount, data);
+/// Borrows the first token of a route from Aave V2 or the Balancer vault,
+/// swaps it through every step of the route and repays the loan, reverting
+/// unless at least `minProfit` is left over. Two V2 pairs can also be
+/// arbitraged with a flash swap, borrowing from one pair and repaying it in
+/// the other token. Profits stay in the contract until withdrawn.
+contract FlashLoanArbitrage {
+    uint8 constant UNISWAP_V2 = 0;
+    uint8 constant BALANCER = 1;
+
+    // Matches `executor::RouteStep` on the Rust side
+    struct Step {
+        uint8 kind;
+        address pool;
+        // Balancer pool id, unused by V2 pairs
+        bytes32 poolId;
+        address tokenIn;
+        address tokenOut;
+        // V2 pair swap fee, unused by Balancer
+        uint16 feeBps;
+        uint256 minAmountOut;
+    }
+
+    struct Route {
+        Step[] steps;
+        uint256 minProfit;
+    }
+
+    // Matches `executor::FlashSwap` on the Rust side
+    struct FlashSwap {
+        address borrowPair;
+        uint16 borrowFeeBps;
+        address sellPair;
+        uint16 sellFeeBps;
+        address tokenBorrowed;
+        address tokenRepaid;
+        uint256 amountBorrowed;
+        uint256 minAmountOut;
+        uint256 minProfit;
+    }
+
+    ILendingPoolAddressesProvider public immutable ADDRESS_PROVIDER;
+    IBalancerVault public immutable VAULT;
+    address public immutable owner;
+    // Set while a Balancer loan this contract asked for is outstanding, as
+    // the vault doesn't say who initiated a loan
+    bool private balancerLoanPending;
+    // The pair a flash swap this contract started borrows from
+    address private flashSwapPair;
+
+    constructor(ILendingPoolAddressesProvider addressProvider, IBalancerVault vault) {
+        ADDRESS_PROVIDER = addressProvider;
+        VAULT = vault;
+        owner = msg.sender;
+    }
+
+    modifier onlyOwner() {
+        require(msg.sender == owner, "not owner");
+        _;
+    }
+
+    /// Flash borrows `amount` of `asset` and runs the route encoded in
+    /// `params`, which has to start and end with `asset`. Returns the `asset`
+    /// left over, so an `eth_call` shows what the route would make.
+    function executeArbitrage(address asset, uint256 amount, bytes calldata params) external onlyOwner returns (uint256 profit) {
+        uint256 balanceBefore = IERC20(asset).balanceOf(address(this));
+        address[] memory assets = new address[](1);
+        assets[0] = asset;
+        uint256[] memory amounts = new uint256[](1);
+        amounts[0] = amount;
+        // Mode 0 repays within the transaction instead of opening debt
+        uint256[] memory modes = new uint256[](1);
+        ILendingPool(ADDRESS_PROVIDER.getLendingPool()).flashLoan(
+            address(this), assets, amounts, modes, address(this), params, 0
+        );
+        profit = IERC20(asset).balanceOf(address(this)) - balanceBefore;
+    }
+
+    /// Like `executeArbitrage`, borrowing from the Balancer vault instead.
+    function executeBalancerArbitrage(address asset, uint256 amount, bytes calldata params) external onlyOwner returns (uint256 profit) {
+        uint256 balanceBefore = IERC20(asset).balanceOf(address(this));
+        address[] memory tokens = new address[](1);
+        tokens[0] = asset;
+        uint256[] memory amounts = new uint256[](1);
+        amounts[0] = amount;
+        balancerLoanPending = true;
+        VAULT.flashLoan(address(this), tokens, amounts, params);
+        balancerLoanPending = false;
+        profit = IERC20(asset).balanceOf(address(this)) - balanceBefore;
     }
 
     function executeOperation(
@@ -23,15 +159,152 @@ contract FlashLoanExample is FlashLoanReceiverBase {
         uint256[] calldata premiums,
         address initiator,
         bytes calldata params
-    ) external override returns (bool) {
-        // Logic for your use case
+    ) external returns (bool) {
+        address lendingPool = ADDRESS_PROVIDER.getLendingPool();
+        require(msg.sender == lendingPool, "not lending pool");
+        require(initiator == address(this), "not initiated here");
 
-        // Repay the loan and the fee
-        for (uint256 i = 0; i < assets.length; i++) {
-            uint256 amountOwing = amounts[i] + premiums[i];
-            ERC20(assets[i]).approve(address(LENDING_POOL), amountOwing);
+        uint256 owed = _runRoute(assets[0], amounts[0], premiums[0], params);
+        // Aave pulls the repayment
+        _safeApprove(assets[0], lendingPool, owed);
+        return true;
+    }
+
+    function receiveFlashLoan(
+        address[] memory tokens,
+        uint256[] memory amounts,
+        uint256[] memory feeAmounts,
+        bytes memory userData
+    ) external {
+        require(msg.sender == address(VAULT), "not vault");
+        require(balancerLoanPending, "not initiated here");
+
+        uint256 owed = _runRoute(tokens[0], amounts[0], feeAmounts[0], userData);
+        // The vault checks its balance went back up
+        _safeTransfer(tokens[0], address(VAULT), owed);
+    }
+
+    /// Borrows `amountBorrowed` from `borrowPair`, sells it on `sellPair` in
+    /// the `uniswapV2Call` callback and repays `borrowPair` in the token bought.
+    /// Returns the `tokenRepaid` left over.
+    function executeFlashSwap(bytes calldata params) external onlyOwner returns (uint256 profit) {
+        FlashSwap memory flash = abi.decode(params, (FlashSwap));
+        uint256 balanceBefore = IERC20(flash.tokenRepaid).balanceOf(address(this));
+        IUniswapV2Pair pair = IUniswapV2Pair(flash.borrowPair);
+        (uint256 amount0Out, uint256 amount1Out) = pair.token0() == flash.tokenBorrowed
+            ? (flash.amountBorrowed, uint256(0))
+            : (uint256(0), flash.amountBorrowed);
+        flashSwapPair = flash.borrowPair;
+        // Non-empty data makes the pair call back before checking its balances
+        pair.swap(amount0Out, amount1Out, address(this), params);
+        flashSwapPair = address(0);
+        profit = IERC20(flash.tokenRepaid).balanceOf(address(this)) - balanceBefore;
+    }
+
+    function uniswapV2Call(address sender, uint256, uint256, bytes calldata data) external {
+        require(msg.sender == flashSwapPair && sender == address(this), "not initiated here");
+        FlashSwap memory flash = abi.decode(data, (FlashSwap));
+
+        // The pair hasn't synced its reserves yet, so this prices the borrowed
+        // amount as a regular swap would
+        IUniswapV2Pair pair = IUniswapV2Pair(msg.sender);
+        (uint112 reserve0, uint112 reserve1,) = pair.getReserves();
+        (uint256 reserveIn, uint256 reserveOut) =
+            pair.token0() == flash.tokenRepaid ? (uint256(reserve0), uint256(reserve1)) : (uint256(reserve1), uint256(reserve0));
+        uint256 repayment = reserveIn * flash.amountBorrowed * 10000
+            / ((reserveOut - flash.amountBorrowed) * (10000 - flash.borrowFeeBps)) + 1;
+
+        uint256 amountOut = _swap(
+            Step(
+                UNISWAP_V2,
+                flash.sellPair,
+                bytes32(0),
+                flash.tokenBorrowed,
+                flash.tokenRepaid,
+                flash.sellFeeBps,
+                flash.minAmountOut
+            ),
+            flash.amountBorrowed
+        );
+        require(amountOut >= repayment + flash.minProfit, "unprofitable");
+        _safeTransfer(flash.tokenRepaid, msg.sender, repayment);
+    }
+
+    // Runs the route in `params` on `amount` borrowed `asset` and returns what
+    // the lender is owed, reverting unless the route leaves that plus the
+    // route's minimum profit
+    function _runRoute(address asset, uint256 amount, uint256 fee, bytes memory params) internal returns (uint256 owed) {
+        Route memory route = abi.decode(params, (Route));
+        require(route.steps.length > 0 && route.steps[0].tokenIn == asset, "bad route");
+        uint256 balanceBefore = IERC20(asset).balanceOf(address(this)) - amount;
+
+        uint256 amountOut = amount;
+        for (uint256 i = 0; i < route.steps.length; i++) {
+            amountOut = _swap(route.steps[i], amountOut);
         }
+        require(route.steps[route.steps.length - 1].tokenOut == asset, "route not closed");
 
-        return true;
+        owed = amount + fee;
+        uint256 balanceAfter = IERC20(asset).balanceOf(address(this));
+        require(balanceAfter >= balanceBefore + owed + route.minProfit, "unprofitable");
+    }
+
+    function withdraw(address token, uint256 amount) external onlyOwner {
+        _safeTransfer(token, owner, amount);
+    }
+
+    // Sells `amountIn` of the step's input token and returns what it bought
+    function _swap(Step memory step, uint256 amountIn) internal returns (uint256 amountOut) {
+        if (step.kind == UNISWAP_V2) {
+            amountOut = _swapUniswapV2(step, amountIn);
+        } else if (step.kind == BALANCER) {
+            _safeApprove(step.tokenIn, address(VAULT), amountIn);
+            amountOut = VAULT.swap(
+                IBalancerVault.SingleSwap(
+                    step.poolId, IBalancerVault.SwapKind.GIVEN_IN, step.tokenIn, step.tokenOut, amountIn, ""
+                ),
+                IBalancerVault.FundManagement(address(this), false, payable(address(this)), false),
+                step.minAmountOut,
+                block.timestamp
+            );
+        } else {
+            revert("unknown step");
+        }
+        require(amountOut >= step.minAmountOut, "slippage");
+    }
+
+    function _swapUniswapV2(Step memory step, uint256 amountIn) internal returns (uint256 amountOut) {
+        IUniswapV2Pair pair = IUniswapV2Pair(step.pool);
+        (uint112 reserve0, uint112 reserve1,) = pair.getReserves();
+        bool zeroForOne = pair.token0() == step.tokenIn;
+        (uint256 reserveIn, uint256 reserveOut) = zeroForOne ? (reserve0, reserve1) : (reserve1, reserve0);
+
+        uint256 amountInWithFee = amountIn * (10000 - step.feeBps);
+        amountOut = amountInWithFee * reserveOut / (reserveIn * 10000 + amountInWithFee);
+
+        _safeTransfer(step.tokenIn, step.pool, amountIn);
+        (uint256 amount0Out, uint256 amount1Out) = zeroForOne ? (uint256(0), amountOut) : (amountOut, uint256(0));
+        pair.swap(amount0Out, amount1Out, address(this), "");
+    }
+
+    function _safeTransfer(address token, address to, uint256 amount) internal {
+        _callToken(token, abi.encodeWithSelector(IERC20.transfer.selector, to, amount));
+    }
+
+    function _safeApprove(address token, address spender, uint256 amount) internal {
+        _callToken(token, abi.encodeWithSelector(IERC20.approve.selector, spender, amount));
+    }
+
+    // USDT and others return nothing from transfer and approve, so a call
+    // succeeds if it returned nothing or true. Nothing returned by an address
+    // without code isn't success though.
+    function _callToken(address token, bytes memory data) private {
+        (bool success, bytes memory returned) = token.call(data);
+        require(success, "token call reverted");
+        if (returned.length == 0) {
+            require(token.code.length > 0, "token has no code");
+        } else {
+            require(abi.decode(returned, (bool)), "token call failed");
+        }
     }
 }
@@ -1,31 +1,522 @@
+use crate::balancer_math::{
+    complement, div_down, div_up, mul_down, mul_up, stable_in_given_out, stable_out_given_in,
+    weighted_in_given_out, weighted_out_given_in, ONE,
+};
 use crate::config::BALANCER_VAULT_ADDRESS;
-use crate::contract_interfaces::{IBalancerPool, IBalancerVault};
-use crate::utils::bytes32_from_hex;
+use crate::contract_interfaces::{
+    IBalancerPool, IBalancerVault, PoolBalanceChangedFilter, SwapFilter,
+};
+use crate::exchange::{Exchange, PoolAdapter, PoolState};
+use crate::executor::StepKind;
+use crate::tokens::TokenRegistry;
+use crate::utils::decode;
+use async_trait::async_trait;
 use ethers::{
-    core::types::{Address, U256},
-    providers::{Http, Provider},
-    utils::hex::ToHex,
+    abi::{RawLog, Token},
+    contract::{EthEvent, Multicall, MulticallVersion},
+    core::types::{Address, Log, H256, I256, U256},
+    providers::Middleware,
 };
 use eyre::Result;
 use std::sync::Arc;
 
-pub async fn balancer_pair(
-    client: &Arc<Provider<Http>>,
-    contract_address: Address,
+pub struct Balancer;
+
+impl<M: Middleware + 'static> Exchange<M> for Balancer {
+    fn name(&self) -> &str {
+        "Balancer"
+    }
+
+    fn pool(&self, address: Address, _fee_bps: Option<u32>) -> Box<dyn PoolState<M>> {
+        Box::new(BalancerPool::new(address))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BalancerPoolKind {
+    // Normalized weights, 18 decimals, summing to 1e18
+    Weighted { weights: Vec<U256> },
+    // Amplification parameter, multiplied by AMP_PRECISION
+    Stable { amp: U256 },
+}
+
+#[derive(Debug, Clone)]
+pub struct BalancerPool {
+    address: Address,
+    pool_id: [u8; 32],
+    tokens: Vec<Address>,
+    balances: Vec<U256>,
+    kind: BalancerPoolKind,
+    // Swap fee, 18 decimals
+    swap_fee: U256,
+    // Per token 18 decimal factors that upscale balances to 18 decimals,
+    // including any rate provider
+    scaling_factors: Vec<U256>,
+    // Composable stable pools list their own BPT among the tokens
+    bpt_index: Option<usize>,
+}
+
+impl BalancerPool {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            pool_id: [0; 32],
+            tokens: vec![],
+            balances: vec![],
+            kind: BalancerPoolKind::Weighted { weights: vec![] },
+            swap_fee: U256::zero(),
+            scaling_factors: vec![],
+            bpt_index: None,
+        }
+    }
+
+    pub fn with_state(
+        address: Address,
+        tokens: Vec<Address>,
+        balances: Vec<U256>,
+        kind: BalancerPoolKind,
+        swap_fee: U256,
+        decimals: &[u8],
+        bpt_index: Option<usize>,
+    ) -> Self {
+        Self {
+            address,
+            pool_id: [0; 32],
+            tokens,
+            balances,
+            kind,
+            swap_fee,
+            scaling_factors: decimals
+                .iter()
+                .map(|d| scaling_factor(*d).expect("Balancer tokens have at most 18 decimals"))
+                .collect(),
+            bpt_index,
+        }
+    }
+
+    // Indices of a swappable token pair; the BPT itself can't be swapped here
+    fn indices(&self, token_in: Address, token_out: Address) -> Option<(usize, usize)> {
+        let index_of = |token: Address| self.tokens.iter().position(|t| *t == token);
+        let (i, o) = (index_of(token_in)?, index_of(token_out)?);
+        if i == o || self.bpt_index == Some(i) || self.bpt_index == Some(o) {
+            return None;
+        }
+        Some((i, o))
+    }
+
+    fn upscale(&self, index: usize, amount: U256) -> Option<U256> {
+        mul_down(amount, self.scaling_factors[index])
+    }
+
+    // Upscaled balances without the BPT, and the pair's indices within them
+    fn stable_balances(&self, i: usize, o: usize) -> Option<(Vec<U256>, usize, usize)> {
+        let mut balances = vec![];
+        for (index, balance) in self.balances.iter().enumerate() {
+            if self.bpt_index != Some(index) {
+                balances.push(self.upscale(index, *balance)?);
+            }
+        }
+        let shift = |index: usize| match self.bpt_index {
+            Some(bpt_index) if bpt_index < index => index - 1,
+            _ => index,
+        };
+        Some((balances, shift(i), shift(o)))
+    }
+
+    // Pool math on upscaled amounts, fees excluded
+    fn out_given_in(&self, i: usize, o: usize, amount_in: U256) -> Option<U256> {
+        match &self.kind {
+            BalancerPoolKind::Weighted { weights } => weighted_out_given_in(
+                self.upscale(i, self.balances[i])?,
+                weights[i],
+                self.upscale(o, self.balances[o])?,
+                weights[o],
+                amount_in,
+            ),
+            BalancerPoolKind::Stable { amp } => {
+                let (balances, i, o) = self.stable_balances(i, o)?;
+                stable_out_given_in(*amp, &balances, i, o, amount_in)
+            }
+        }
+    }
+
+    fn in_given_out(&self, i: usize, o: usize, amount_out: U256) -> Option<U256> {
+        match &self.kind {
+            BalancerPoolKind::Weighted { weights } => weighted_in_given_out(
+                self.upscale(i, self.balances[i])?,
+                weights[i],
+                self.upscale(o, self.balances[o])?,
+                weights[o],
+                amount_out,
+            ),
+            BalancerPoolKind::Stable { amp } => {
+                let (balances, i, o) = self.stable_balances(i, o)?;
+                stable_in_given_out(*amp, &balances, i, o, amount_out)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> PoolState<M> for BalancerPool {
+    async fn fetch_state(
+        &mut self,
+        client: &Arc<M>,
+        registry: &TokenRegistry,
+        block_number: u64,
+    ) -> Result<()> {
+        let pool_id = get_pool_id(client, self.address).await?;
+        let (tokens, balances) = balancer_pair(client, pool_id, block_number).await?;
+        let pool = IBalancerPool::new(self.address, client.clone());
+        let mut multicall: Multicall<M> = Multicall::new(client.clone(), None)
+            .await?
+            .version(MulticallVersion::Multicall3)
+            .block(block_number);
+
+        // Which of these succeed tells weighted and stable pools apart
+        multicall
+            .add_call(pool.get_swap_fee_percentage(), false)
+            .add_call(pool.get_normalized_weights(), true)
+            .add_call(pool.get_amplification_parameter(), true)
+            .add_call(pool.get_scaling_factors(), true)
+            .add_call(pool.get_bpt_index(), true);
+        let mut results = multicall.call_raw().await?.into_iter();
+        let mut next = || results.next().unwrap_or(Token::Bool(false));
+        let swap_fee: U256 =
+            decode(next()).ok_or_else(|| eyre::eyre!("Failed to fetch Balancer swap fee"))?;
+        let weights: Option<Vec<U256>> = decode(next());
+        let amp: Option<(U256, bool, U256)> = decode(next());
+        let scaling_factors: Option<Vec<U256>> = decode(next());
+        let bpt_index: Option<U256> = decode(next());
+
+        let kind = match (weights, amp) {
+            (Some(weights), _) => BalancerPoolKind::Weighted { weights },
+            (None, Some((amp, _, _))) => BalancerPoolKind::Stable { amp },
+            _ => return Err(eyre::eyre!("Unsupported Balancer pool {:?}", self.address)),
+        };
+
+        let scaling_factors = match scaling_factors {
+            Some(scaling_factors) => scaling_factors,
+            None => registry
+                .resolve(client, &tokens)
+                .await?
+                .iter()
+                .map(|token| scaling_factor(token.decimals))
+                .collect::<Option<_>>()
+                .ok_or_else(|| {
+                    eyre::eyre!(
+                        "Balancer pool {:?} has a token with more than 18 decimals",
+                        self.address
+                    )
+                })?,
+        };
+
+        self.pool_id = pool_id;
+        self.tokens = tokens;
+        self.balances = balances;
+        self.kind = kind;
+        self.swap_fee = swap_fee;
+        self.scaling_factors = scaling_factors;
+        // Only composable stable pools have getBptIndex
+        self.bpt_index = bpt_index.map(|index| index.as_usize());
+        Ok(())
+    }
+
+    fn add_state_calls(&self, client: &Arc<M>, multicall: &mut Multicall<M>) -> Result<usize> {
+        let vault = IBalancerVault::new(BALANCER_VAULT_ADDRESS.parse::<Address>()?, client.clone());
+        let pool = IBalancerPool::new(self.address, client.clone());
+        multicall
+            .add_call(vault.get_pool_tokens(self.pool_id), true)
+            .add_call(pool.get_swap_fee_percentage(), true)
+            .add_call(pool.get_amplification_parameter(), true)
+            .add_call(pool.get_scaling_factors(), true);
+        Ok(4)
+    }
+
+    fn apply_state(&mut self, results: &[Token]) -> Result<bool> {
+        let mut results = results.iter().cloned();
+        let mut next = || results.next().unwrap_or(Token::Bool(false));
+        let error = || eyre::eyre!("Failed to fetch state of {:?}", self.address);
+
+        let (tokens, balances, _): (Vec<Address>, Vec<U256>, U256) =
+            decode(next()).ok_or_else(error)?;
+        let swap_fee: U256 = decode(next()).ok_or_else(error)?;
+        let amp: Option<(U256, bool, U256)> = decode(next());
+        // Rate providers move the scaling factors of pools that have them
+        let scaling_factors: Option<Vec<U256>> = decode(next());
+        if tokens != self.tokens {
+            return Ok(false);
+        }
+
+        self.balances = balances;
+        self.swap_fee = swap_fee;
+        if let (BalancerPoolKind::Stable { amp }, Some((value, _, _))) = (&mut self.kind, amp) {
+            *amp = value;
+        }
+        if let Some(scaling_factors) = scaling_factors {
+            self.scaling_factors = scaling_factors;
+        }
+        Ok(true)
+    }
+
+    // Asset managed balances (PoolBalanceManaged) aren't followed; they're
+    // picked up when the pool is polled again
+    fn event_source(&self) -> Option<(Address, Vec<H256>)> {
+        let vault = BALANCER_VAULT_ADDRESS.parse::<Address>().ok()?;
+        let events = vec![
+            SwapFilter::signature(),
+            PoolBalanceChangedFilter::signature(),
+        ];
+        Some((vault, events))
+    }
+
+    fn apply_log(&mut self, log: &Log) -> Result<bool> {
+        if log.topics.get(1) != Some(&H256(self.pool_id)) {
+            return Ok(false);
+        }
+        let raw = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+        let index_of = |token: Address| {
+            self.tokens
+                .iter()
+                .position(|t| *t == token)
+                .ok_or_else(|| eyre::eyre!("{:?} isn't in pool {:?}", token, self.address))
+        };
+        let error = || eyre::eyre!("Balance of pool {:?} out of range", self.address);
+
+        match log.topics.first() {
+            Some(topic) if *topic == SwapFilter::signature() => {
+                let swap = SwapFilter::decode_log(&raw)?;
+                let (i, o) = (index_of(swap.token_in)?, index_of(swap.token_out)?);
+                self.balances[i] = self.balances[i]
+                    .checked_add(swap.amount_in)
+                    .ok_or_else(error)?;
+                self.balances[o] = self.balances[o]
+                    .checked_sub(swap.amount_out)
+                    .ok_or_else(error)?;
+            }
+            Some(topic) if *topic == PoolBalanceChangedFilter::signature() => {
+                let change = PoolBalanceChangedFilter::decode_log(&raw)?;
+                for ((token, delta), fee) in change
+                    .tokens
+                    .iter()
+                    .zip(&change.deltas)
+                    .zip(&change.protocol_fee_amounts)
+                {
+                    let index = index_of(*token)?;
+                    let balance = I256::from_raw(self.balances[index]) + *delta;
+                    self.balances[index] = balance
+                        .into_raw()
+                        .checked_sub(*fee)
+                        .filter(|_| !balance.is_negative())
+                        .ok_or_else(error)?;
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn restore_reserves(&mut self, reserves: Vec<U256>) {
+        self.balances = reserves;
+    }
+}
+
+impl PoolAdapter for BalancerPool {
+    fn exchange(&self) -> &str {
+        "Balancer"
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn tokens(&self) -> &[Address] {
+        &self.tokens
+    }
+
+    fn reserves(&self) -> &[U256] {
+        &self.balances
+    }
+
+    fn fee(&self) -> U256 {
+        self.swap_fee
+    }
+
+    fn executor_step(&self) -> Option<StepKind> {
+        Some(StepKind::Balancer {
+            pool_id: self.pool_id,
+        })
+    }
+
+    fn spot_price(&self, token_in: Address, token_out: Address) -> Option<U256> {
+        let (i, o) = self.indices(token_in, token_out)?;
+        match &self.kind {
+            BalancerPoolKind::Weighted { weights } => {
+                let denominator = self.balances[i] * weights[o];
+                if denominator.is_zero() {
+                    return None;
+                }
+                Some(self.balances[o] * weights[i] * U256::exp10(18) / denominator)
+            }
+            BalancerPoolKind::Stable { .. } => {
+                // Stable curves have no closed form; probe with a tiny trade
+                let probe = self.upscale(i, self.balances[i])? / 1_000_000;
+                if probe.is_zero() {
+                    return None;
+                }
+                let amount_out = self.out_given_in(i, o, probe)?;
+                let precision = U256::exp10(18);
+                let price = amount_out * precision / probe;
+                // Rescale from 18 decimal units back to raw token units
+                Some(price * self.scaling_factors[i] / self.scaling_factors[o])
+            }
+        }
+    }
+
+    fn quote_exact_in(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+    ) -> Option<U256> {
+        let (i, o) = self.indices(token_in, token_out)?;
+        let amount_in = amount_in - mul_up(amount_in, self.swap_fee)?;
+        let amount_out = self.out_given_in(i, o, self.upscale(i, amount_in)?)?;
+        div_down(amount_out, self.scaling_factors[o])
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_out: U256,
+    ) -> Option<U256> {
+        let (i, o) = self.indices(token_in, token_out)?;
+        let amount_in = self.in_given_out(i, o, self.upscale(o, amount_out)?)?;
+        let amount_in = div_up(amount_in, self.scaling_factors[i])?;
+        div_up(amount_in, complement(self.swap_fee))
+    }
+}
+
+// None for tokens with more than 18 decimals, which Balancer doesn't support
+fn scaling_factor(decimals: u8) -> Option<U256> {
+    Some(U256::from(ONE) * U256::exp10(18usize.checked_sub(decimals as usize)?))
+}
+
+pub async fn balancer_pair<M: Middleware + 'static>(
+    client: &Arc<M>,
+    pool_id: [u8; 32],
+    block_number: u64,
 ) -> Result<(Vec<Address>, Vec<U256>)> {
-    let pool_id = get_pool_id(client, contract_address).await?;
     let contract = IBalancerVault::new(BALANCER_VAULT_ADDRESS.parse::<Address>()?, client.clone());
     let pool_id_info: (Vec<Address>, Vec<U256>, U256) = contract
-        .get_pool_tokens(bytes32_from_hex(pool_id.as_str()))
+        .get_pool_tokens(pool_id)
+        .block(block_number)
         .call()
         .await?;
 
     Ok((pool_id_info.0, pool_id_info.1))
 }
 
-async fn get_pool_id(client: &Arc<Provider<Http>>, contract_address: Address) -> Result<String> {
+async fn get_pool_id<M: Middleware + 'static>(
+    client: &Arc<M>,
+    contract_address: Address,
+) -> Result<[u8; 32]> {
     let contract = IBalancerPool::new(contract_address, client.clone());
-    let pool_id: [u8; 32] = contract.get_pool_id().call().await?;
-    let hex_string: String = ToHex::encode_hex(&pool_id);
-    Ok(hex_string)
+    Ok(contract.get_pool_id().call().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    #[test]
+    fn test_scaling_factor_rejects_over_18_decimals() {
+        assert_eq!(scaling_factor(6), Some(U256::exp10(30)));
+        assert_eq!(scaling_factor(18), Some(U256::exp10(18)));
+        assert_eq!(scaling_factor(24), None);
+    }
+
+    #[test]
+    fn test_multi_token_weighted_pool_uses_pair_indices() {
+        let weight = U256::exp10(18) / 4;
+        let pool = BalancerPool::with_state(
+            Address::zero(),
+            (1..=4).map(address).collect(),
+            vec![
+                U256::exp10(24),
+                U256::exp10(20),
+                U256::exp10(12),
+                U256::exp10(22),
+            ],
+            BalancerPoolKind::Weighted {
+                weights: vec![weight; 4],
+            },
+            U256::zero(),
+            &[18, 18, 6, 18],
+            None,
+        );
+
+        // Equal weights price the pair by its own balances, 1e20 vs 1e12 (6 decimals)
+        let amount_out = pool
+            .quote_exact_in(address(2), address(3), U256::exp10(15))
+            .unwrap();
+        assert!(amount_out < U256::exp10(7));
+        assert!(amount_out > U256::exp10(7) * 99 / 100);
+        assert_eq!(
+            pool.quote_exact_in(address(2), address(5), U256::one()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_composable_stable_pool_skips_bpt() {
+        let pool = BalancerPool::with_state(
+            Address::zero(),
+            (1..=4).map(address).collect(),
+            vec![
+                U256::exp10(24),
+                U256::exp10(12),
+                U256::exp10(30),
+                U256::exp10(24),
+            ],
+            BalancerPoolKind::Stable {
+                amp: U256::from(100_000),
+            },
+            U256::zero(),
+            &[18, 6, 18, 18],
+            Some(2),
+        );
+
+        let amount_out = pool
+            .quote_exact_in(address(2), address(4), U256::exp10(6))
+            .unwrap();
+        assert!(amount_out < U256::exp10(18));
+        assert!(amount_out > U256::exp10(18) * 999 / 1000);
+        assert_eq!(
+            pool.quote_exact_in(address(3), address(4), U256::one()),
+            None
+        );
+
+        // Too little left to probe a price with
+        let drained = BalancerPool::with_state(
+            Address::zero(),
+            vec![address(1), address(2)],
+            vec![U256::from(999_999), U256::exp10(24)],
+            BalancerPoolKind::Stable {
+                amp: U256::from(100_000),
+            },
+            U256::zero(),
+            &[18, 18],
+            None,
+        );
+        assert_eq!(drained.spot_price(address(1), address(2)), None);
+    }
 }
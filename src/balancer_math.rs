@@ -0,0 +1,498 @@
+// Ports of Balancer V2's FixedPoint, LogExpMath, WeightedMath and StableMath libraries.
+// Every function mirrors the rounding of its Solidity counterpart so quotes
+// match the vault to the wei; `None` stands in for a `_require` revert.
+use ethers::core::types::{I256, U256};
+
+pub const ONE: u128 = 1_000_000_000_000_000_000;
+
+// Swaps may not move more than 30% of a balance in or out
+const MAX_IN_RATIO: u128 = 300_000_000_000_000_000;
+const MAX_OUT_RATIO: u128 = 300_000_000_000_000_000;
+// 1e-14 relative error allowed in pow
+const MAX_POW_RELATIVE_ERROR: u128 = 10_000;
+// Amplification parameters are stored multiplied by this
+pub const AMP_PRECISION: u64 = 1_000;
+
+fn one() -> U256 {
+    U256::from(ONE)
+}
+
+pub fn mul_down(a: U256, b: U256) -> Option<U256> {
+    Some(a.checked_mul(b)? / one())
+}
+
+pub fn mul_up(a: U256, b: U256) -> Option<U256> {
+    let product = a.checked_mul(b)?;
+    if product.is_zero() {
+        Some(product)
+    } else {
+        Some((product - 1) / one() + 1)
+    }
+}
+
+pub fn div_down(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        return None;
+    }
+    Some(a.checked_mul(one())? / b)
+}
+
+pub fn div_up(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        return None;
+    }
+    if a.is_zero() {
+        return Some(a);
+    }
+    Some((a.checked_mul(one())? - 1) / b + 1)
+}
+
+pub fn complement(x: U256) -> U256 {
+    if x < one() {
+        one() - x
+    } else {
+        U256::zero()
+    }
+}
+
+pub fn pow_up(x: U256, y: U256) -> Option<U256> {
+    if y == one() {
+        Some(x)
+    } else if y == one() * 2 {
+        mul_up(x, x)
+    } else if y == one() * 4 {
+        let square = mul_up(x, x)?;
+        mul_up(square, square)
+    } else {
+        let raw = pow(x, y)?;
+        let max_error = mul_up(raw, U256::from(MAX_POW_RELATIVE_ERROR))? + 1;
+        raw.checked_add(max_error)
+    }
+}
+
+// WeightedMath._calcOutGivenIn, all amounts upscaled to 18 decimals
+pub fn weighted_out_given_in(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_in: U256,
+) -> Option<U256> {
+    if amount_in > mul_down(balance_in, U256::from(MAX_IN_RATIO))? {
+        return None;
+    }
+    let denominator = balance_in.checked_add(amount_in)?;
+    let base = div_up(balance_in, denominator)?;
+    let exponent = div_down(weight_in, weight_out)?;
+    let power = pow_up(base, exponent)?;
+
+    mul_down(balance_out, complement(power))
+}
+
+// WeightedMath._calcInGivenOut, all amounts upscaled to 18 decimals
+pub fn weighted_in_given_out(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_out: U256,
+) -> Option<U256> {
+    if amount_out > mul_down(balance_out, U256::from(MAX_OUT_RATIO))? {
+        return None;
+    }
+    let base = div_up(balance_out, balance_out - amount_out)?;
+    let exponent = div_up(weight_out, weight_in)?;
+    let power = pow_up(base, exponent)?;
+    let ratio = power.checked_sub(one())?;
+
+    mul_up(balance_in, ratio)
+}
+
+// Math.divUp
+fn raw_div_up(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        return None;
+    }
+    if a.is_zero() {
+        return Some(a);
+    }
+    Some((a - 1) / b + 1)
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+// StableMath._calculateInvariant, `amp` includes AMP_PRECISION
+pub fn stable_invariant(amp: U256, balances: &[U256]) -> Option<U256> {
+    let num_tokens = U256::from(balances.len());
+    let precision = U256::from(AMP_PRECISION);
+    let sum = balances
+        .iter()
+        .try_fold(U256::zero(), |sum, balance| sum.checked_add(*balance))?;
+    if sum.is_zero() {
+        return Some(sum);
+    }
+
+    let amp_times_total = amp.checked_mul(num_tokens)?;
+    let mut invariant = sum;
+    for _ in 0..255 {
+        let mut d_p = invariant;
+        for balance in balances {
+            // An empty balance leaves no invariant to solve for
+            d_p = d_p
+                .checked_mul(invariant)?
+                .checked_div(balance.checked_mul(num_tokens)?)?;
+        }
+        let previous = invariant;
+        let numerator = (amp_times_total.checked_mul(sum)? / precision)
+            .checked_add(d_p.checked_mul(num_tokens)?)?
+            .checked_mul(invariant)?;
+        let denominator = (amp_times_total
+            .checked_sub(precision)?
+            .checked_mul(invariant)?
+            / precision)
+            .checked_add((num_tokens + 1).checked_mul(d_p)?)?;
+        if denominator.is_zero() {
+            return None;
+        }
+        invariant = numerator / denominator;
+        if abs_diff(invariant, previous) <= U256::one() {
+            return Some(invariant);
+        }
+    }
+    None
+}
+
+// StableMath._getTokenBalanceGivenInvariantAndAllOtherBalances
+fn stable_balance_given_invariant(
+    amp: U256,
+    balances: &[U256],
+    invariant: U256,
+    index: usize,
+) -> Option<U256> {
+    let num_tokens = U256::from(balances.len());
+    let precision = U256::from(AMP_PRECISION);
+    let amp_times_total = amp.checked_mul(num_tokens)?;
+
+    let mut sum = balances[0];
+    let mut p_d = balances[0].checked_mul(num_tokens)?;
+    for balance in &balances[1..] {
+        p_d = p_d
+            .checked_mul(*balance)?
+            .checked_mul(num_tokens)?
+            .checked_div(invariant)?;
+        sum = sum.checked_add(*balance)?;
+    }
+    sum -= balances[index];
+
+    let inv2 = invariant.checked_mul(invariant)?;
+    let c = raw_div_up(inv2, amp_times_total.checked_mul(p_d)?)?
+        .checked_mul(precision)?
+        .checked_mul(balances[index])?;
+    let b = sum.checked_add(
+        invariant
+            .checked_div(amp_times_total)?
+            .checked_mul(precision)?,
+    )?;
+
+    let mut token_balance = raw_div_up(inv2.checked_add(c)?, invariant.checked_add(b)?)?;
+    for _ in 0..255 {
+        let previous = token_balance;
+        let numerator = token_balance.checked_mul(token_balance)?.checked_add(c)?;
+        let denominator = token_balance
+            .checked_mul(U256::from(2))?
+            .checked_add(b)?
+            .checked_sub(invariant)?;
+        token_balance = raw_div_up(numerator, denominator)?;
+        if abs_diff(token_balance, previous) <= U256::one() {
+            return Some(token_balance);
+        }
+    }
+    None
+}
+
+// StableMath._calcOutGivenIn, all amounts upscaled to 18 decimals
+pub fn stable_out_given_in(
+    amp: U256,
+    balances: &[U256],
+    index_in: usize,
+    index_out: usize,
+    amount_in: U256,
+) -> Option<U256> {
+    let invariant = stable_invariant(amp, balances)?;
+    let mut balances = balances.to_vec();
+    balances[index_in] = balances[index_in].checked_add(amount_in)?;
+    let final_balance_out = stable_balance_given_invariant(amp, &balances, invariant, index_out)?;
+
+    balances[index_out]
+        .checked_sub(final_balance_out)?
+        .checked_sub(U256::one())
+}
+
+// StableMath._calcInGivenOut, all amounts upscaled to 18 decimals
+pub fn stable_in_given_out(
+    amp: U256,
+    balances: &[U256],
+    index_in: usize,
+    index_out: usize,
+    amount_out: U256,
+) -> Option<U256> {
+    let invariant = stable_invariant(amp, balances)?;
+    let mut balances = balances.to_vec();
+    balances[index_out] = balances[index_out].checked_sub(amount_out)?;
+    let final_balance_in = stable_balance_given_invariant(amp, &balances, invariant, index_in)?;
+
+    final_balance_in
+        .checked_sub(balances[index_in])?
+        .checked_add(U256::one())
+}
+
+fn int(value: &str) -> I256 {
+    I256::from_dec_str(value).unwrap()
+}
+
+fn one_18() -> I256 {
+    I256::exp10(18)
+}
+
+fn one_20() -> I256 {
+    I256::exp10(20)
+}
+
+fn one_36() -> I256 {
+    I256::exp10(36)
+}
+
+// (x_n, a_n = e^x_n) pairs used to decompose exponents; the first two have 18
+// decimals and no-decimal a_n, the rest have 20 decimals.
+fn exp_terms() -> [(I256, I256); 12] {
+    [
+        (
+            int("128000000000000000000"),
+            int("38877084059945950922200000000000000000000000000000000000"),
+        ),
+        (
+            int("64000000000000000000"),
+            int("6235149080811616882910000000"),
+        ),
+        (
+            int("3200000000000000000000"),
+            int("7896296018268069516100000000000000"),
+        ),
+        (
+            int("1600000000000000000000"),
+            int("888611052050787263676000000"),
+        ),
+        (
+            int("800000000000000000000"),
+            int("298095798704172827474000"),
+        ),
+        (int("400000000000000000000"), int("5459815003314423907810")),
+        (int("200000000000000000000"), int("738905609893065022723")),
+        (int("100000000000000000000"), int("271828182845904523536")),
+        (int("50000000000000000000"), int("164872127070012814685")),
+        (int("25000000000000000000"), int("128402541668774148407")),
+        (int("12500000000000000000"), int("113314845306682631683")),
+        (int("6250000000000000000"), int("106449445891785942956")),
+    ]
+}
+
+// LogExpMath.pow: x^y with both in 18 decimal fixed point
+pub fn pow(x: U256, y: U256) -> Option<U256> {
+    if y.is_zero() {
+        return Some(one());
+    }
+    if x.is_zero() {
+        return Some(U256::zero());
+    }
+    if x.bit(255) {
+        return None;
+    }
+    let mild_exponent_bound = (U256::one() << 254) / U256::exp10(20);
+    if y >= mild_exponent_bound {
+        return None;
+    }
+    let x = I256::from_raw(x);
+    let y = I256::from_raw(y);
+
+    let ln_36_lower_bound = one_18() - I256::exp10(17);
+    let ln_36_upper_bound = one_18() + I256::exp10(17);
+    let mut logx_times_y = if ln_36_lower_bound < x && x < ln_36_upper_bound {
+        let ln_36_x = ln_36(x);
+        (ln_36_x / one_18()) * y + ((ln_36_x % one_18()) * y) / one_18()
+    } else {
+        ln(x) * y
+    };
+    logx_times_y /= one_18();
+
+    let min_natural_exponent = int("-41000000000000000000");
+    let max_natural_exponent = int("130000000000000000000");
+    if logx_times_y < min_natural_exponent || logx_times_y > max_natural_exponent {
+        return None;
+    }
+    Some(exp(logx_times_y).into_raw())
+}
+
+// LogExpMath.exp, x within the natural exponent bounds
+fn exp(x: I256) -> I256 {
+    if x.is_negative() {
+        return (one_18() * one_18()) / exp(-x);
+    }
+    let terms = exp_terms();
+
+    let mut x = x;
+    let first_an = if x >= terms[0].0 {
+        x -= terms[0].0;
+        terms[0].1
+    } else if x >= terms[1].0 {
+        x -= terms[1].0;
+        terms[1].1
+    } else {
+        I256::one()
+    };
+
+    x *= I256::from(100);
+    let mut product = one_20();
+    for (x_n, a_n) in &terms[2..10] {
+        if x >= *x_n {
+            x -= *x_n;
+            product = (product * *a_n) / one_20();
+        }
+    }
+
+    let mut series_sum = one_20();
+    let mut term = x;
+    series_sum += term;
+    for n in 2..=12 {
+        term = ((term * x) / one_20()) / I256::from(n);
+        series_sum += term;
+    }
+
+    (((product * series_sum) / one_20()) * first_an) / I256::from(100)
+}
+
+// LogExpMath._ln
+fn ln(a: I256) -> I256 {
+    if a < one_18() {
+        return -ln((one_18() * one_18()) / a);
+    }
+    let terms = exp_terms();
+
+    let mut a = a;
+    let mut sum = I256::zero();
+    for (x_n, a_n) in &terms[..2] {
+        if a >= *a_n * one_18() {
+            a /= *a_n;
+            sum += *x_n;
+        }
+    }
+
+    sum *= I256::from(100);
+    a *= I256::from(100);
+    for (x_n, a_n) in &terms[2..] {
+        if a >= *a_n {
+            a = (a * one_20()) / *a_n;
+            sum += *x_n;
+        }
+    }
+
+    let z = ((a - one_20()) * one_20()) / (a + one_20());
+    let z_squared = (z * z) / one_20();
+    let mut num = z;
+    let mut series_sum = num;
+    for n in [3, 5, 7, 9, 11] {
+        num = (num * z_squared) / one_20();
+        series_sum += num / I256::from(n);
+    }
+    series_sum *= I256::from(2);
+
+    (sum + series_sum) / I256::from(100)
+}
+
+// LogExpMath._ln_36, for x close to one
+fn ln_36(x: I256) -> I256 {
+    let x = x * one_18();
+    let z = ((x - one_36()) * one_36()) / (x + one_36());
+    let z_squared = (z * z) / one_36();
+    let mut num = z;
+    let mut series_sum = num;
+    for n in [3, 5, 7, 9, 11, 13, 15] {
+        num = (num * z_squared) / one_36();
+        series_sum += num / I256::from(n);
+    }
+
+    series_sum * I256::from(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fp(value: f64) -> U256 {
+        U256::from((value * 1e18) as u128)
+    }
+
+    fn to_f64(value: U256) -> f64 {
+        value.as_u128() as f64 / 1e18
+    }
+
+    #[test]
+    fn test_pow() {
+        assert_eq!(pow(fp(2.0), U256::zero()), Some(one()));
+        assert!((to_f64(pow(fp(2.0), fp(0.5)).unwrap()) - 2f64.sqrt()).abs() < 1e-12);
+        assert!((to_f64(pow(fp(0.95), fp(4.0)).unwrap()) - 0.95f64.powi(4)).abs() < 1e-12);
+        assert!((to_f64(pow(fp(3.0), fp(2.5)).unwrap()) - 3f64.powf(2.5)).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_weighted_swaps() {
+        let balance = fp(1000.0);
+        let half = fp(0.5);
+
+        // 50/50 pools behave like x*y=k
+        let out = weighted_out_given_in(balance, half, balance, half, fp(10.0)).unwrap();
+        assert!((to_f64(out) - 1000.0 * 10.0 / 1010.0).abs() < 1e-9);
+
+        // An 80/20 pool prices the heavy token four times higher per unit of balance
+        let out = weighted_out_given_in(balance, fp(0.8), balance, fp(0.2), fp(0.001)).unwrap();
+        assert!((to_f64(out) - 0.004).abs() < 1e-8);
+
+        let amount_in = weighted_in_given_out(balance, half, balance, half, out).unwrap();
+        assert!(weighted_out_given_in(balance, half, balance, half, amount_in).unwrap() >= out);
+
+        assert_eq!(
+            weighted_out_given_in(balance, half, balance, half, fp(301.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stable_swaps() {
+        let amp = U256::from(200 * AMP_PRECISION);
+        let balances = vec![fp(1_000_000.0), fp(1_000_000.0), fp(1_000_000.0)];
+
+        // A balanced stable pool trades close to 1:1
+        let out = stable_out_given_in(amp, &balances, 0, 2, fp(1000.0)).unwrap();
+        assert!(out < fp(1000.0));
+        assert!(out > fp(999.9));
+
+        let amount_in = stable_in_given_out(amp, &balances, 0, 2, out).unwrap();
+        assert!(abs_diff(amount_in, fp(1000.0)) <= U256::from(1000));
+
+        let invariant = stable_invariant(amp, &balances).unwrap();
+        assert!(abs_diff(invariant, fp(3_000_000.0)) <= U256::one());
+
+        // Drained pools can't be quoted
+        let drained = vec![fp(1_000_000.0), U256::zero(), fp(1_000_000.0)];
+        assert_eq!(stable_invariant(amp, &drained), None);
+        assert_eq!(stable_out_given_in(amp, &drained, 0, 2, fp(1.0)), None);
+        let empty = vec![U256::zero(); 3];
+        assert_eq!(stable_invariant(amp, &empty), Some(U256::zero()));
+        assert_eq!(stable_out_given_in(amp, &empty, 0, 2, fp(1.0)), None);
+    }
+}
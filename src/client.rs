@@ -1,63 +1,137 @@
-use crate::utils::Pair;
+use crate::exchange::{ExchangeRegistry, PoolAdapter, PoolState};
+use crate::report::{print_mismatch, print_warning};
+use crate::rpc::{FailoverClient, QuorumReads};
+use crate::tokens::TokenRegistry;
+use crate::utils::{match_pair, Pair};
+use async_trait::async_trait;
 use ethers::{
     core::types::Address,
-    providers::{Http, Provider},
+    providers::{Http, Ipc, JsonRpcClient, Middleware, Provider, Ws},
 };
 use eyre::Result;
-use std::collections::HashMap;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::Read;
 use std::sync::Arc;
 
-pub struct ArbClient {
-    pub client: Arc<Provider<Http>>,
-    pub network: u64,
-    pub exchanges: Vec<String>,
+/// Pools of every exchange that lists the token pair, keyed by the pair's
+/// addresses, lowest first. Pools are indices into `get_all_pools`.
+pub type CommonPairs = HashMap<(Address, Address), Vec<usize>>;
+
+/// A config entry whose declared pair isn't two of its pool's tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairMismatch {
+    pub exchange: String,
+    pub address: Address,
+    pub declared: Vec<String>,
+    // Symbols of the pool's tokens
+    pub on_chain: Vec<String>,
+}
+
+impl fmt::Display for PairMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} pool {:?} is listed as {} but holds {}",
+            self.exchange,
+            self.address,
+            self.declared.join("/"),
+            self.on_chain.join("/")
+        )
+    }
 }
 
-impl ArbClient {
-    pub async fn new(network: u64, exchanges: Vec<String>) -> Result<Self> {
-        let client = create_client().await?;
+pub struct ArbClient<M: Middleware> {
+    pub client: Arc<M>,
+    pub network: u64,
+    pub exchanges: ExchangeRegistry<M>,
+}
 
-        Ok(Self {
+impl<M: Middleware + 'static> ArbClient<M> {
+    pub fn new(client: Arc<M>, network: u64, exchanges: ExchangeRegistry<M>) -> Self {
+        Self {
             client,
             network,
             exchanges,
-        })
+        }
     }
 
-    pub fn get_common_pairs(
-        &self,
-        exchanges: &[&str],
-    ) -> Result<HashMap<(String, String), Vec<(String, Address)>>, Box<dyn std::error::Error>> {
-        let mut all_pairs: Vec<HashMap<(String, String), Address>> = Vec::new();
+    fn config_path(&self, exchange: &str) -> String {
         let network = self.network;
-        for exchange in exchanges {
-            let mut file = File::open(format!("./src/data/{network}/{exchange}.config.json"))?;
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
-            let data: Vec<Pair> = serde_json::from_str(&contents)?;
-            let pairs_map = data
-                .into_iter()
-                .map(|pair_data| {
-                    (
-                        (pair_data.pair[0].clone(), pair_data.pair[1].clone()),
-                        pair_data.address,
-                    )
-                })
-                .collect();
-            all_pairs.push(pairs_map);
-        }
+        format!("./src/data/{network}/{exchange}.config.json")
+    }
+
+    // Pairs listed in each exchange's config file
+    fn read_pairs(&self, exchange: &str) -> Result<Vec<Pair>> {
+        let mut file = File::open(self.config_path(exchange))?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Replaces an exchange's config file with `pairs`.
+    pub fn write_pairs(&self, exchange: &str, pairs: &[Pair]) -> Result<()> {
+        let contents = serde_json::to_string_pretty(pairs)? + "\n";
+        fs::write(self.config_path(exchange), contents)?;
+        Ok(())
+    }
 
-        let mut common_pairs: HashMap<(String, String), Vec<(String, Address)>> = HashMap::new();
+    // Finds the pair a config entry declares among its loaded pool's tokens
+    async fn match_entry(
+        &self,
+        exchange: &str,
+        pair_data: &Pair,
+        pool: &dyn PoolAdapter,
+        tokens: &TokenRegistry,
+    ) -> Result<Result<(Address, Address), PairMismatch>> {
+        let pool_tokens = tokens.resolve(&self.client, pool.tokens()).await?;
+        Ok(
+            match_pair(&pair_data.pair, &pool_tokens).ok_or_else(|| PairMismatch {
+                exchange: exchange.to_string(),
+                address: pair_data.address,
+                declared: pair_data.pair.clone(),
+                on_chain: pool_tokens.into_iter().map(|token| token.symbol).collect(),
+            }),
+        )
+    }
 
-        for (exchange, pairs) in exchanges.iter().zip(all_pairs.iter()) {
-            for (symbol_pair, address) in pairs {
-                common_pairs
-                    .entry(symbol_pair.clone())
-                    .or_insert_with(Vec::new)
-                    .push((exchange.to_string(), *address));
+    /// Groups the loaded `pools` of `get_all_pools` by the token pair their
+    /// config entries declare, leaving out pairs only one pool lists.
+    /// Entries whose declared pair doesn't match the pool are reported and
+    /// skipped, as are pools that failed to load.
+    pub async fn get_common_pairs(
+        &self,
+        pools: &[Box<dyn PoolState<M>>],
+        tokens: &TokenRegistry,
+    ) -> Result<CommonPairs> {
+        let mut common_pairs: CommonPairs = HashMap::new();
+        for exchange in self.exchanges.iter() {
+            for pair_data in self.read_pairs(exchange.name())? {
+                let Some(index) = pools
+                    .iter()
+                    .position(|pool| pool.address() == pair_data.address)
+                else {
+                    continue;
+                };
+                let pool = pools[index].as_ref() as &dyn PoolAdapter;
+                if pool.tokens().is_empty() {
+                    continue;
+                }
+                match self
+                    .match_entry(exchange.name(), &pair_data, pool, tokens)
+                    .await
+                {
+                    Ok(Ok(pair)) => common_pairs.entry(pair).or_default().push(index),
+                    Ok(Err(mismatch)) => print_mismatch(&mismatch),
+                    Err(error) => print_warning(format!(
+                        "Skipping {} pool {:?}: {error}",
+                        exchange.name(),
+                        pair_data.address
+                    )),
+                }
             }
         }
 
@@ -65,12 +139,220 @@ impl ArbClient {
 
         Ok(common_pairs)
     }
+
+    /// Config entries whose declared pair doesn't match the pool's tokens,
+    /// read at the latest block. Pools that fail to load are reported and
+    /// skipped.
+    pub async fn validate_pairs(&self, tokens: &TokenRegistry) -> Result<Vec<PairMismatch>> {
+        let block_number = self.client.get_block_number().await?.as_u64();
+        let mut mismatches = vec![];
+        for exchange in self.exchanges.iter() {
+            for pair_data in self.read_pairs(exchange.name())? {
+                let mut pool = exchange.pool(pair_data.address, pair_data.fee_bps);
+                let matched = match pool.fetch_state(&self.client, tokens, block_number).await {
+                    Ok(()) => {
+                        self.match_entry(exchange.name(), &pair_data, pool.as_ref(), tokens)
+                            .await
+                    }
+                    Err(error) => Err(error),
+                };
+                match matched {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(mismatch)) => mismatches.push(mismatch),
+                    Err(error) => print_warning(format!(
+                        "Skipping {} pool {:?}: {error}",
+                        exchange.name(),
+                        pair_data.address
+                    )),
+                }
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Every configured pool of every exchange, for multi-hop routing. Pools
+    /// listed under several pairs (multi-token pools) are only included once.
+    pub fn get_all_pools(&self) -> Result<Vec<Box<dyn PoolState<M>>>> {
+        let mut pools = vec![];
+        let mut seen = HashSet::new();
+        for exchange in self.exchanges.iter() {
+            for pair_data in self.read_pairs(exchange.name())? {
+                if seen.insert(pair_data.address) {
+                    pools.push(exchange.pool(pair_data.address, pair_data.fee_bps));
+                }
+            }
+        }
+        Ok(pools)
+    }
+}
+
+/// How to reach the node. A single `ETHEREUM_RPC_URL` picks its transport
+/// by scheme: `http(s)://` and `ws(s)://` URLs, anything else is an IPC
+/// socket path. A comma separated `ETHEREUM_RPC_URLS` list of HTTP endpoints
+/// fails over between them. `ETHEREUM_RPC_QUORUM=n` also needs `n` of them to
+/// agree on the pool state read each block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Http(String),
+    Ws(String),
+    Ipc(String),
+    Failover(Vec<String>),
+    Quorum(Vec<String>, usize),
 }
 
-async fn create_client() -> Result<Arc<Provider<Http>>> {
-    let rpc_url = &env::var("ETHEREUM_RPC_URL").expect("ETHEREUM_RPC_URL must be set");
-    let provider = Provider::<Http>::try_from(rpc_url)?;
-    let client = Arc::new(provider);
+impl Transport {
+    pub fn from_url(url: &str) -> Self {
+        let url = url.to_string();
+        if url.starts_with("ws://") || url.starts_with("wss://") {
+            Self::Ws(url)
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            Self::Http(url)
+        } else {
+            Self::Ipc(url)
+        }
+    }
 
-    Ok(client)
+    pub fn from_urls(urls: &[String], quorum: Option<usize>) -> Result<Self> {
+        match (urls, quorum) {
+            ([], _) => Err(eyre::eyre!(
+                "No RPC endpoint configured: set ETHEREUM_RPC_URL or ETHEREUM_RPC_URLS"
+            )),
+            ([url], None) => Ok(Self::from_url(url)),
+            (urls, quorum) => {
+                if let Some(url) = urls
+                    .iter()
+                    .find(|url| !matches!(Self::from_url(url), Self::Http(_)))
+                {
+                    return Err(eyre::eyre!(
+                        "{url} is not an HTTP endpoint; failover and quorum need HTTP endpoints"
+                    ));
+                }
+                match quorum {
+                    Some(quorum) if quorum == 0 || quorum > urls.len() => Err(eyre::eyre!(
+                        "ETHEREUM_RPC_QUORUM must be between 1 and the {} configured endpoints",
+                        urls.len()
+                    )),
+                    Some(quorum) => Ok(Self::Quorum(urls.to_vec(), quorum)),
+                    None => Ok(Self::Failover(urls.to_vec())),
+                }
+            }
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let urls: Vec<String> = env::var("ETHEREUM_RPC_URLS")
+            .or_else(|_| env::var("ETHEREUM_RPC_URL"))
+            .unwrap_or_default()
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
+        let quorum = match env::var("ETHEREUM_RPC_QUORUM") {
+            Ok(quorum) => Some(quorum.trim().parse::<usize>().map_err(|_| {
+                eyre::eyre!("ETHEREUM_RPC_QUORUM must be a number of endpoints, got {quorum}")
+            })?),
+            Err(_) => None,
+        };
+        Self::from_urls(&urls, quorum)
+    }
+}
+
+/// New block numbers as they arrive: polled over HTTP, a `newHeads`
+/// subscription over WS and IPC.
+#[async_trait]
+pub trait BlockSource: Middleware {
+    async fn new_blocks<'a>(&'a self) -> Result<BoxStream<'a, u64>>;
+}
+
+// Polls eth_blockNumber rather than a block filter, which wouldn't survive
+// failing over to another node
+fn poll_new_blocks<P: JsonRpcClient>(provider: &Provider<P>) -> BoxStream<'_, u64> {
+    stream::unfold(0, move |last_block| async move {
+        loop {
+            tokio::time::sleep(provider.get_interval()).await;
+            if let Ok(block_number) = provider.get_block_number().await {
+                let block_number = block_number.as_u64();
+                if block_number > last_block {
+                    return Some((block_number, block_number));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+#[async_trait]
+impl BlockSource for Provider<Http> {
+    async fn new_blocks<'a>(&'a self) -> Result<BoxStream<'a, u64>> {
+        Ok(poll_new_blocks(self))
+    }
+}
+
+#[async_trait]
+impl BlockSource for Provider<FailoverClient> {
+    async fn new_blocks<'a>(&'a self) -> Result<BoxStream<'a, u64>> {
+        Ok(poll_new_blocks(self))
+    }
+}
+
+#[async_trait]
+impl BlockSource for Provider<QuorumReads> {
+    async fn new_blocks<'a>(&'a self) -> Result<BoxStream<'a, u64>> {
+        Ok(poll_new_blocks(self))
+    }
+}
+
+#[async_trait]
+impl BlockSource for Provider<Ws> {
+    async fn new_blocks<'a>(&'a self) -> Result<BoxStream<'a, u64>> {
+        let blocks = self.subscribe_blocks().await?;
+        Ok(blocks
+            .filter_map(|block| async move { Some(block.number?.as_u64()) })
+            .boxed())
+    }
+}
+
+#[async_trait]
+impl BlockSource for Provider<Ipc> {
+    async fn new_blocks<'a>(&'a self) -> Result<BoxStream<'a, u64>> {
+        let blocks = self.subscribe_blocks().await?;
+        Ok(blocks
+            .filter_map(|block| async move { Some(block.number?.as_u64()) })
+            .boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transport_from_url() {
+        let http = "https://eth.example.com";
+        assert_eq!(Transport::from_url(http), Transport::Http(http.to_string()));
+        let ws = "wss://eth.example.com";
+        assert_eq!(Transport::from_url(ws), Transport::Ws(ws.to_string()));
+        let ipc = "/var/run/geth.ipc";
+        assert_eq!(Transport::from_url(ipc), Transport::Ipc(ipc.to_string()));
+    }
+
+    #[test]
+    fn test_transport_from_urls() {
+        let urls: Vec<String> = ["https://a.example.com", "https://b.example.com"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            Transport::from_urls(&urls, None).unwrap(),
+            Transport::Failover(urls.clone())
+        );
+        assert_eq!(
+            Transport::from_urls(&urls, Some(2)).unwrap(),
+            Transport::Quorum(urls.clone(), 2)
+        );
+        assert!(Transport::from_urls(&urls, Some(3)).is_err());
+        assert!(Transport::from_urls(&[], None).is_err());
+
+        let mixed = vec![urls[0].clone(), "wss://c.example.com".to_string()];
+        assert!(Transport::from_urls(&mixed, None).is_err());
+    }
 }
@@ -4,28 +4,39 @@ pub const MAINNET_USDC_ADDRESS: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB
 pub const MAINNET_LINK_ADDRESS: &str = "0x514910771AF9Ca656af840dff83E8264EcF986CA";
 
 // Sushiswap Addresses
+pub const SUSHISWAP_FACTORY_ADDRESS: &str = "0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac";
 pub const SUSHI_LINK_SDL_ADDRESS: &str = "0xd27b7D42D24d8F7C1CF5C46cCD3b986C396FdE17";
 pub const SUSHI_ETH_USDC_ADDRESS: &str = "0x397FF1542f962076d0BFE58eA045FfA2d347ACa0";
 pub const SUSHI_LINK_ETH_ADDRESS: &str = "0xC40D16476380e4037e6b1A2594cAF6a6cc8Da967";
 pub const SUSHI_LDO_ETH_ADDRESS: &str = "0xc558f600b34a5f69dd2f0d06cb8a88d829b7420a";
 
 // Pancakeswap Addresses
+pub const PANCAKESWAP_FACTORY_ADDRESS: &str = "0x1097053Fd2ea711dad45caCcc45EfF7548fCB362";
 pub const PANCAKESWAP_ETH_USDC_ADDRESS: &str = "0x2E8135bE71230c6B1B4045696d41C09Db0414226";
 
 // Curve Addresses
 pub const CURVE_ETH_LINK_ADDRESS: &str = "0x0d4a11d5EEaaC28EC3F61d100daF4d40471f1852";
+pub const CURVE_TRICRYPTO2_ADDRESS: &str = "0xD51a44d3FaE010294C616388b506AcdA1bfAAE46";
+
+// Chainlink Addresses
+pub const CHAINLINK_ETH_USD_ADDRESS: &str = "0x5f4eC3Df9cbd43714FE2740f5E3616155c5b8419";
 
 // Compound Addresses
 
 // Balancer Addresses
 pub const BALANCER_VAULT_ADDRESS: &str = "0xBA12222222228d8Ba445958a75a0704d566BF2C8";
+pub const BALANCER_VAULT_DEPLOYMENT_BLOCK: u64 = 12272146;
 
 // Uniswap V2 Addresses
+pub const UNISWAP_V2_FACTORY_ADDRESS: &str = "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f";
 pub const UNISWAP_LINK_ETH_ADDRESS: &str = "0xa2107fa5b38d9bbd2c461d6edf11b11a50f6b974";
 pub const UNISWAP_USDC_ETH_ADDRESS: &str = "0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc";
 pub const UNISWAP_LDO_ETH_ADDRESS: &str = "0x454f11d58e27858926d7a4ece8bfea2c33e97b13";
 
 // Aave Addresses
+pub const AAVE_V2_ADDRESSES_PROVIDER_ADDRESS: &str = "0xB53C1a33016B2DC2fF3653530bfF1848a515c8c5";
+pub const AAVE_V2_LENDING_POOL_ADDRESS: &str = "0x7d2768dE32b0b80b7a3454c06BdAc94A69DDc7A9";
+pub const AAVE_V2_DATA_PROVIDER_ADDRESS: &str = "0x057835Ad21a177dbdd3090bB1CAE03EaCF78Fc6d";
 
 // Kyber Addresses
 
@@ -22,6 +22,17 @@ abigen!(
         function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast)
         function price0CumulativeLast() external view returns (uint)
         function price1CumulativeLast() external view returns (uint)
+        event Sync(uint112 reserve0, uint112 reserve1)
+    ]"#,
+);
+
+abigen!(
+    IUniswapV2Factory,
+    r#"[
+        function allPairsLength() external view returns (uint256)
+        function allPairs(uint256) external view returns (address pair)
+        function getPair(address tokenA, address tokenB) external view returns (address pair)
+        event PairCreated(address indexed token0, address indexed token1, address pair, uint256)
     ]"#,
 );
 
@@ -30,6 +41,10 @@ abigen!(
     r#"[
         function getPool(bytes32 poolId) external view returns (address pair, uint8 tokens)
         function getPoolTokens(bytes32 poolId) external view returns (address[] memory tokens, uint256[] memory balances, uint256 lastChangeBlock)
+        function getProtocolFeesCollector() external view returns (address)
+        event Swap(bytes32 indexed poolId, address indexed tokenIn, address indexed tokenOut, uint256 amountIn, uint256 amountOut)
+        event PoolBalanceChanged(bytes32 indexed poolId, address indexed liquidityProvider, address[] tokens, int256[] deltas, uint256[] protocolFeeAmounts)
+        event PoolRegistered(bytes32 indexed poolId, address indexed poolAddress, uint8 specialization)
     ]"#,
 );
 
@@ -37,5 +52,114 @@ abigen!(
     IBalancerPool,
     r#"[
         function getPoolId() external view returns (bytes32)
+        function getNormalizedWeights() external view returns (uint256[] memory)
+        function getSwapFeePercentage() external view returns (uint256)
+        function getAmplificationParameter() external view returns (uint256 value, bool isUpdating, uint256 precision)
+        function getScalingFactors() external view returns (uint256[] memory)
+        function getBptIndex() external view returns (uint256)
+    ]"#,
+);
+
+abigen!(
+    IUniswapV3Pool,
+    r#"[
+        function token0() external view returns (address)
+        function token1() external view returns (address)
+        function fee() external view returns (uint24)
+        function tickSpacing() external view returns (int24)
+        function liquidity() external view returns (uint128)
+        function slot0() external view returns (uint160 sqrtPriceX96, int24 tick, uint16 observationIndex, uint16 observationCardinality, uint16 observationCardinalityNext, uint8 feeProtocol, bool unlocked)
+        function tickBitmap(int16 wordPosition) external view returns (uint256)
+        function ticks(int24 tick) external view returns (uint128 liquidityGross, int128 liquidityNet, uint256 feeGrowthOutside0X128, uint256 feeGrowthOutside1X128, int56 tickCumulativeOutside, uint160 secondsPerLiquidityOutsideX128, uint32 secondsOutside, bool initialized)
+    ]"#,
+);
+
+abigen!(
+    ICurvePool,
+    r#"[
+        function coins(uint256 i) external view returns (address)
+        function balances(uint256 i) external view returns (uint256)
+        function underlying_coins(uint256 i) external view returns (address)
+        function get_dy(int128 i, int128 j, uint256 dx) external view returns (uint256)
+        function A() external view returns (uint256)
+        function A_precise() external view returns (uint256)
+        function fee() external view returns (uint256)
+        function offpeg_fee_multiplier() external view returns (uint256)
+    ]"#,
+);
+
+abigen!(
+    ICurvePoolLegacy,
+    r#"[
+        function coins(int128 i) external view returns (address)
+        function balances(int128 i) external view returns (uint256)
+        function underlying_coins(int128 i) external view returns (address)
+    ]"#,
+);
+
+abigen!(
+    ICurveCryptoPool,
+    r#"[
+        function get_dy(uint256 i, uint256 j, uint256 dx) external view returns (uint256)
+        function gamma() external view returns (uint256)
+        function D() external view returns (uint256)
+        function mid_fee() external view returns (uint256)
+        function out_fee() external view returns (uint256)
+        function fee_gamma() external view returns (uint256)
+        function price_scale() external view returns (uint256)
+    ]"#,
+);
+
+abigen!(
+    ICurveTricryptoPool,
+    r#"[
+        function price_scale(uint256 k) external view returns (uint256)
+    ]"#,
+);
+
+abigen!(
+    IChainlinkAggregator,
+    r#"[
+        function decimals() external view returns (uint8)
+        function latestRoundData() external view returns (uint80 roundId, int256 answer, uint256 startedAt, uint256 updatedAt, uint80 answeredInRound)
+    ]"#,
+);
+
+abigen!(
+    IBalancerProtocolFeesCollector,
+    r#"[
+        function getFlashLoanFeePercentage() external view returns (uint256)
+    ]"#,
+);
+
+abigen!(
+    IAaveLendingPool,
+    r#"[
+        function FLASHLOAN_PREMIUM_TOTAL() external view returns (uint256)
+    ]"#,
+);
+
+abigen!(
+    IAaveProtocolDataProvider,
+    r#"[
+        function getReserveTokensAddresses(address asset) external view returns (address aTokenAddress, address stableDebtTokenAddress, address variableDebtTokenAddress)
+    ]"#,
+);
+
+abigen!(
+    IFlashLoanArbitrage,
+    r#"[
+        function executeArbitrage(address asset, uint256 amount, bytes params) external returns (uint256 profit)
+        function executeBalancerArbitrage(address asset, uint256 amount, bytes params) external returns (uint256 profit)
+        function executeFlashSwap(bytes params) external returns (uint256 profit)
+        function withdraw(address token, uint256 amount) external
+        function owner() external view returns (address)
+    ]"#,
+);
+
+abigen!(
+    ICompoundToken,
+    r#"[
+        function exchangeRateStored() external view returns (uint256)
     ]"#,
 );
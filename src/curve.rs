@@ -0,0 +1,728 @@
+use crate::contract_interfaces::{
+    ICompoundToken, ICurveCryptoPool, ICurvePool, ICurvePoolLegacy, ICurveTricryptoPool,
+};
+use crate::curve_math::{crypto_fee, crypto_newton_y, dynamic_fee, stable_get_y, FEE_DENOMINATOR};
+use crate::exchange::{Exchange, PoolAdapter, PoolState};
+use crate::tokens::TokenRegistry;
+use crate::utils::decode;
+use async_trait::async_trait;
+use ethers::{
+    abi::Token,
+    contract::{Multicall, MulticallVersion},
+    core::types::{Address, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+use std::sync::Arc;
+
+// Curve pools hold at most 8 coins
+const MAX_COINS: usize = 8;
+
+pub struct Curve;
+
+impl<M: Middleware + 'static> Exchange<M> for Curve {
+    fn name(&self) -> &str {
+        "Curve"
+    }
+
+    fn pool(&self, address: Address, _fee_bps: Option<u32>) -> Box<dyn PoolState<M>> {
+        Box::new(CurvePool::new(address))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CurvePoolKind {
+    // StableSwap. `amp` is A * a_precision, `rates` scale each balance to
+    // 18 decimals (10**(36 - decimals) for plain coins).
+    Plain {
+        amp: U256,
+        a_precision: U256,
+        rates: Vec<U256>,
+    },
+    // StableSwap over interest bearing coins (Compound, Aave, ...). Rates
+    // include the exchange rate; Aave style pools raise the fee off peg.
+    Lending {
+        amp: U256,
+        a_precision: U256,
+        rates: Vec<U256>,
+        offpeg_fee_multiplier: U256,
+    },
+    // CryptoSwap (tricrypto and two coin crypto pools). `ann` is
+    // A * N**N * 10000, `price_scale` prices coins 1.. in coin 0 and
+    // `precisions` are the 10**(18 - decimals) multipliers.
+    Crypto {
+        ann: U256,
+        gamma: U256,
+        d: U256,
+        price_scale: Vec<U256>,
+        precisions: Vec<U256>,
+        mid_fee: U256,
+        out_fee: U256,
+        fee_gamma: U256,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct CurvePool {
+    address: Address,
+    tokens: Vec<Address>,
+    balances: Vec<U256>,
+    kind: CurvePoolKind,
+    // Swap fee over FEE_DENOMINATOR (4e6 = 0.04%)
+    fee: U256,
+    // Coins and balances are indexed by int128
+    legacy: bool,
+    // 10**(18 - decimals) of the underlying coins of cToken pools, whose
+    // rates follow exchangeRateStored()
+    compound_precisions: Option<Vec<U256>>,
+}
+
+impl CurvePool {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            tokens: vec![],
+            balances: vec![],
+            kind: CurvePoolKind::Plain {
+                amp: U256::zero(),
+                a_precision: U256::one(),
+                rates: vec![],
+            },
+            fee: U256::zero(),
+            legacy: false,
+            compound_precisions: None,
+        }
+    }
+
+    pub fn with_state(
+        address: Address,
+        tokens: Vec<Address>,
+        balances: Vec<U256>,
+        kind: CurvePoolKind,
+        fee: U256,
+    ) -> Self {
+        Self {
+            address,
+            tokens,
+            balances,
+            kind,
+            fee,
+            legacy: false,
+            compound_precisions: None,
+        }
+    }
+
+    fn indices(&self, token_in: Address, token_out: Address) -> Option<(usize, usize)> {
+        let index_of = |token: Address| self.tokens.iter().position(|t| *t == token);
+        let (i, j) = (index_of(token_in)?, index_of(token_out)?);
+        if i == j {
+            return None;
+        }
+        Some((i, j))
+    }
+
+    // Mirrors the pools' get_dy views
+    fn get_dy(&self, i: usize, j: usize, dx: U256) -> Option<U256> {
+        let precision = U256::exp10(18);
+        let fee_denominator = U256::from(FEE_DENOMINATOR);
+        match &self.kind {
+            CurvePoolKind::Plain {
+                amp,
+                a_precision,
+                rates,
+            } => {
+                let xp = xp(&self.balances, rates)?;
+                let x = xp[i].checked_add(dx.checked_mul(rates[i])? / precision)?;
+                let y = stable_get_y(i, j, x, &xp, *amp, *a_precision)?;
+                let dy = xp[j].checked_sub(y)?.checked_sub(U256::one())?;
+                let fee = self.fee * dy / fee_denominator;
+                Some((dy - fee) * precision / rates[j])
+            }
+            CurvePoolKind::Lending {
+                amp,
+                a_precision,
+                rates,
+                offpeg_fee_multiplier,
+            } => {
+                let xp = xp(&self.balances, rates)?;
+                let x = xp[i].checked_add(dx.checked_mul(rates[i])? / precision)?;
+                let y = stable_get_y(i, j, x, &xp, *amp, *a_precision)?;
+                let dy = xp[j].checked_sub(y)? * precision / rates[j];
+                let fee = dynamic_fee(
+                    (xp[i] + x) / 2,
+                    (xp[j] + y) / 2,
+                    self.fee,
+                    *offpeg_fee_multiplier,
+                );
+                Some(dy - fee * dy / fee_denominator)
+            }
+            CurvePoolKind::Crypto {
+                ann,
+                gamma,
+                d,
+                price_scale,
+                precisions,
+                mid_fee,
+                out_fee,
+                fee_gamma,
+            } => {
+                let mut xp = self.balances.clone();
+                xp[i] = xp[i].checked_add(dx)?;
+                xp[0] = xp[0].checked_mul(precisions[0])?;
+                for k in 1..xp.len() {
+                    xp[k] = xp[k]
+                        .checked_mul(price_scale[k - 1])?
+                        .checked_mul(precisions[k])?
+                        / precision;
+                }
+                let y = crypto_newton_y(*ann, *gamma, &xp, *d, j)?;
+                let mut dy = xp[j].checked_sub(y)?.checked_sub(U256::one())?;
+                xp[j] = y;
+                if j > 0 {
+                    dy = dy * precision / price_scale[j - 1];
+                }
+                dy /= precisions[j];
+                let fee = crypto_fee(&xp, *mid_fee, *out_fee, *fee_gamma);
+                Some(dy - fee * dy / fee_denominator)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> PoolState<M> for CurvePool {
+    async fn fetch_state(
+        &mut self,
+        client: &Arc<M>,
+        registry: &TokenRegistry,
+        block_number: u64,
+    ) -> Result<()> {
+        let pool = ICurvePool::new(self.address, client.clone());
+        let legacy = ICurvePoolLegacy::new(self.address, client.clone());
+        let crypto = ICurveCryptoPool::new(self.address, client.clone());
+        let tricrypto = ICurveTricryptoPool::new(self.address, client.clone());
+        let mut multicall: Multicall<M> = Multicall::new(client.clone(), None)
+            .await?
+            .version(MulticallVersion::Multicall3)
+            .block(block_number);
+
+        // Pools don't expose their coin count and older ones index coins by
+        // int128, so probe both until coins(i) reverts
+        for k in 0..MAX_COINS {
+            multicall
+                .add_call(pool.coins(U256::from(k)), true)
+                .add_call(legacy.coins(k as i128), true);
+        }
+        let coins: Vec<Option<Address>> = multicall
+            .call_raw()
+            .await?
+            .into_iter()
+            .map(decode)
+            .collect();
+        let modern: Vec<Address> = coins.iter().step_by(2).map_while(|coin| *coin).collect();
+        let old: Vec<Address> = coins
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map_while(|coin| *coin)
+            .collect();
+        let is_legacy = old.len() > modern.len();
+        let tokens = if is_legacy { old } else { modern };
+        if tokens.len() < 2 {
+            return Err(eyre::eyre!("Unsupported Curve pool {:?}", self.address));
+        }
+        let n = tokens.len();
+
+        multicall.clear_calls();
+        for k in 0..n {
+            if is_legacy {
+                multicall
+                    .add_call(legacy.balances(k as i128), false)
+                    .add_call(legacy.underlying_coins(k as i128), true);
+            } else {
+                multicall
+                    .add_call(pool.balances(U256::from(k)), false)
+                    .add_call(pool.underlying_coins(U256::from(k)), true);
+            }
+        }
+        // Which of these succeed tells the pool variants apart
+        multicall
+            .add_call(pool.fee(), false)
+            .add_call(pool.a(), true)
+            .add_call(pool.a_precise(), true)
+            .add_call(pool.offpeg_fee_multiplier(), true)
+            .add_call(crypto.gamma(), true)
+            .add_call(crypto.d(), true)
+            .add_call(crypto.mid_fee(), true)
+            .add_call(crypto.out_fee(), true)
+            .add_call(crypto.fee_gamma(), true)
+            .add_call(crypto.price_scale(), true);
+        for k in 0..n - 1 {
+            multicall.add_call(tricrypto.price_scale(U256::from(k)), true);
+        }
+        let mut results = multicall.call_raw().await?.into_iter();
+        let mut next = || results.next().unwrap_or(Token::Bool(false));
+
+        let mut balances = vec![];
+        let mut underlying = vec![];
+        for _ in 0..n {
+            let balance: U256 =
+                decode(next()).ok_or_else(|| eyre::eyre!("Failed to fetch Curve balances"))?;
+            balances.push(balance);
+            underlying.push(decode::<Address>(next()));
+        }
+        let decimals: Vec<u8> = registry
+            .resolve(client, &tokens)
+            .await?
+            .iter()
+            .map(|token| token.decimals)
+            .collect();
+        let fee: U256 =
+            decode(next()).ok_or_else(|| eyre::eyre!("Failed to fetch Curve swap fee"))?;
+        let a: Option<U256> = decode(next());
+        let a_precise: Option<U256> = decode(next());
+        let offpeg_fee_multiplier: Option<U256> = decode(next());
+        let gamma: Option<U256> = decode(next());
+        let d: Option<U256> = decode(next());
+        let mid_fee: Option<U256> = decode(next());
+        let out_fee: Option<U256> = decode(next());
+        let fee_gamma: Option<U256> = decode(next());
+        let price_scale: Option<U256> = decode(next());
+        let price_scales: Option<Vec<U256>> = (0..n - 1).map(|_| decode(next())).collect();
+
+        let mut compound_precisions = None;
+        let kind = if let Some(gamma) = gamma {
+            // Two coin crypto pools have a single price_scale()
+            let price_scale = if n == 2 {
+                price_scale.map(|price_scale| vec![price_scale])
+            } else {
+                price_scales
+            };
+            match (a, d, price_scale, mid_fee, out_fee, fee_gamma) {
+                (
+                    Some(ann),
+                    Some(d),
+                    Some(price_scale),
+                    Some(mid_fee),
+                    Some(out_fee),
+                    Some(fee_gamma),
+                ) => CurvePoolKind::Crypto {
+                    ann,
+                    gamma,
+                    d,
+                    price_scale,
+                    precisions: decimals
+                        .iter()
+                        .map(|d| precision(*d, 18))
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| too_many_decimals(self.address))?,
+                    mid_fee,
+                    out_fee,
+                    fee_gamma,
+                },
+                _ => {
+                    return Err(eyre::eyre!(
+                        "Unsupported Curve crypto pool {:?}",
+                        self.address
+                    ))
+                }
+            }
+        } else {
+            let (amp, a_precision) = match (a_precise, a) {
+                (Some(amp), _) => (amp, U256::from(100)),
+                (None, Some(amp)) => (amp, U256::one()),
+                _ => {
+                    return Err(eyre::eyre!(
+                        "Failed to fetch Curve A for {:?}",
+                        self.address
+                    ))
+                }
+            };
+            let plain_rates: Vec<U256> = decimals
+                .iter()
+                .map(|d| precision(*d, 36))
+                .collect::<Option<_>>()
+                .ok_or_else(|| too_many_decimals(self.address))?;
+            let underlying: Option<Vec<Address>> = underlying.into_iter().collect();
+            match (underlying, offpeg_fee_multiplier) {
+                (None, None) => CurvePoolKind::Plain {
+                    amp,
+                    a_precision,
+                    rates: plain_rates,
+                },
+                (underlying, offpeg_fee_multiplier) => {
+                    let compound = match underlying {
+                        Some(underlying) => {
+                            compound_rates(client, registry, &tokens, &underlying, block_number)
+                                .await?
+                        }
+                        None => None,
+                    };
+                    let rates = match compound {
+                        Some((rates, precisions)) => {
+                            compound_precisions = Some(precisions);
+                            rates
+                        }
+                        None => plain_rates,
+                    };
+                    CurvePoolKind::Lending {
+                        amp,
+                        a_precision,
+                        rates,
+                        offpeg_fee_multiplier: offpeg_fee_multiplier.unwrap_or_default(),
+                    }
+                }
+            }
+        };
+
+        self.tokens = tokens;
+        self.balances = balances;
+        self.kind = kind;
+        self.fee = fee;
+        self.legacy = is_legacy;
+        self.compound_precisions = compound_precisions;
+        Ok(())
+    }
+
+    fn add_state_calls(&self, client: &Arc<M>, multicall: &mut Multicall<M>) -> Result<usize> {
+        let pool = ICurvePool::new(self.address, client.clone());
+        let legacy = ICurvePoolLegacy::new(self.address, client.clone());
+        let crypto = ICurveCryptoPool::new(self.address, client.clone());
+        let tricrypto = ICurveTricryptoPool::new(self.address, client.clone());
+        let n = self.tokens.len();
+
+        for k in 0..n {
+            if self.legacy {
+                multicall.add_call(legacy.balances(k as i128), true);
+            } else {
+                multicall.add_call(pool.balances(U256::from(k)), true);
+            }
+        }
+        multicall.add_call(pool.fee(), true);
+        let mut calls = n + 1;
+        match &self.kind {
+            CurvePoolKind::Plain { a_precision, .. }
+            | CurvePoolKind::Lending { a_precision, .. } => {
+                // A_precise() only exists where amp carries a precision
+                if *a_precision == U256::one() {
+                    multicall.add_call(pool.a(), true);
+                } else {
+                    multicall.add_call(pool.a_precise(), true);
+                }
+                calls += 1;
+            }
+            CurvePoolKind::Crypto { price_scale, .. } => {
+                multicall
+                    .add_call(pool.a(), true)
+                    .add_call(crypto.gamma(), true)
+                    .add_call(crypto.d(), true)
+                    .add_call(crypto.mid_fee(), true)
+                    .add_call(crypto.out_fee(), true)
+                    .add_call(crypto.fee_gamma(), true);
+                // Two coin crypto pools have a single price_scale()
+                if n == 2 {
+                    multicall.add_call(crypto.price_scale(), true);
+                } else {
+                    for k in 0..n - 1 {
+                        multicall.add_call(tricrypto.price_scale(U256::from(k)), true);
+                    }
+                }
+                calls += 6 + price_scale.len();
+            }
+        }
+        if let CurvePoolKind::Lending { .. } = self.kind {
+            multicall.add_call(pool.offpeg_fee_multiplier(), true);
+            calls += 1;
+            if self.compound_precisions.is_some() {
+                for token in &self.tokens {
+                    let contract = ICompoundToken::new(*token, client.clone());
+                    multicall.add_call(contract.exchange_rate_stored(), true);
+                }
+                calls += n;
+            }
+        }
+        Ok(calls)
+    }
+
+    fn apply_state(&mut self, results: &[Token]) -> Result<bool> {
+        let mut results = results.iter().cloned();
+        let mut next = || results.next().unwrap_or(Token::Bool(false));
+        let error = || eyre::eyre!("Failed to fetch state of {:?}", self.address);
+        let n = self.tokens.len();
+
+        let mut balances = vec![];
+        for _ in 0..n {
+            balances.push(decode::<U256>(next()).ok_or_else(error)?);
+        }
+        let fee: U256 = decode(next()).ok_or_else(error)?;
+        match &mut self.kind {
+            CurvePoolKind::Plain { amp, .. } => {
+                *amp = decode(next()).ok_or_else(error)?;
+            }
+            CurvePoolKind::Lending {
+                amp,
+                rates,
+                offpeg_fee_multiplier,
+                ..
+            } => {
+                *amp = decode(next()).ok_or_else(error)?;
+                *offpeg_fee_multiplier = decode(next()).unwrap_or_default();
+                if let Some(precisions) = &self.compound_precisions {
+                    for (rate, precision) in rates.iter_mut().zip(precisions) {
+                        let stored: U256 = decode(next()).ok_or_else(error)?;
+                        *rate = stored * precision;
+                    }
+                }
+            }
+            CurvePoolKind::Crypto {
+                ann,
+                gamma,
+                d,
+                price_scale,
+                mid_fee,
+                out_fee,
+                fee_gamma,
+                ..
+            } => {
+                *ann = decode(next()).ok_or_else(error)?;
+                *gamma = decode(next()).ok_or_else(error)?;
+                *d = decode(next()).ok_or_else(error)?;
+                *mid_fee = decode(next()).ok_or_else(error)?;
+                *out_fee = decode(next()).ok_or_else(error)?;
+                *fee_gamma = decode(next()).ok_or_else(error)?;
+                for scale in price_scale.iter_mut() {
+                    *scale = decode(next()).ok_or_else(error)?;
+                }
+            }
+        }
+        self.balances = balances;
+        self.fee = fee;
+        Ok(true)
+    }
+}
+
+impl PoolAdapter for CurvePool {
+    fn exchange(&self) -> &str {
+        "Curve"
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn tokens(&self) -> &[Address] {
+        &self.tokens
+    }
+
+    fn reserves(&self) -> &[U256] {
+        &self.balances
+    }
+
+    fn fee(&self) -> U256 {
+        // 1e10 denominator to 18 decimals
+        self.fee * U256::exp10(8)
+    }
+
+    fn spot_price(&self, token_in: Address, token_out: Address) -> Option<U256> {
+        let (i, j) = self.indices(token_in, token_out)?;
+        // No closed form; probe with a tiny trade and add the fee back
+        let probe = self.balances[i] / 1_000_000;
+        if probe.is_zero() {
+            return None;
+        }
+        let amount_out = self.get_dy(i, j, probe)?;
+        let fee_denominator = U256::from(FEE_DENOMINATOR);
+        let price = amount_out * U256::exp10(18) / probe;
+        Some(price * fee_denominator / (fee_denominator - self.fee))
+    }
+
+    fn quote_exact_in(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+    ) -> Option<U256> {
+        let (i, j) = self.indices(token_in, token_out)?;
+        self.get_dy(i, j, amount_in)
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_out: U256,
+    ) -> Option<U256> {
+        let (i, j) = self.indices(token_in, token_out)?;
+        if amount_out >= self.balances[j] {
+            return None;
+        }
+        // Curve has no get_dx, so search for the smallest input that is enough
+        let mut high = amount_out.max(U256::one());
+        while self.get_dy(i, j, high)? < amount_out {
+            high = high.checked_mul(U256::from(2))?;
+        }
+        let mut low = U256::zero();
+        while high - low > U256::one() {
+            let mid = (low + high) / 2;
+            match self.get_dy(i, j, mid) {
+                Some(dy) if dy >= amount_out => high = mid,
+                _ => low = mid,
+            }
+        }
+        Some(high)
+    }
+}
+
+// Balances scaled to 18 decimals by their rates
+fn xp(balances: &[U256], rates: &[U256]) -> Option<Vec<U256>> {
+    let precision = U256::exp10(18);
+    balances
+        .iter()
+        .zip(rates)
+        .map(|(balance, rate)| Some(balance.checked_mul(*rate)? / precision))
+        .collect()
+}
+
+// 10**(scale - decimals), bringing a coin's amounts to `scale` decimals. None
+// for coins with more decimals than that
+fn precision(decimals: u8, scale: usize) -> Option<U256> {
+    Some(U256::exp10(scale.checked_sub(decimals as usize)?))
+}
+
+fn too_many_decimals(pool: Address) -> eyre::Report {
+    eyre::eyre!("Curve pool {pool:?} has a coin with more than 18 decimals")
+}
+
+// Compound style pools price their cTokens by the stored exchange rate. The
+// rates and the 10**(18 - decimals) of the underlying coins they scale by, or
+// None when the coins aren't cTokens, e.g. Aave's 1:1 aTokens.
+async fn compound_rates<M: Middleware + 'static>(
+    client: &Arc<M>,
+    registry: &TokenRegistry,
+    tokens: &[Address],
+    underlying: &[Address],
+    block_number: u64,
+) -> Result<Option<(Vec<U256>, Vec<U256>)>> {
+    let mut multicall: Multicall<M> = Multicall::new(client.clone(), None)
+        .await?
+        .version(MulticallVersion::Multicall3)
+        .block(block_number);
+    for token in tokens {
+        multicall.add_call(
+            ICompoundToken::new(*token, client.clone()).exchange_rate_stored(),
+            true,
+        );
+    }
+    let results = multicall.call_raw().await?;
+    let underlying = registry.resolve(client, underlying).await?;
+
+    let mut rates = vec![];
+    let mut precisions = vec![];
+    for (token, result) in underlying.iter().zip(results) {
+        let rate: Option<U256> = decode(result);
+        let precision = precision(token.decimals, 18)
+            .ok_or_else(|| eyre::eyre!("{} has more than 18 decimals", token.symbol))?;
+        match rate {
+            Some(rate) => rates.push(rate * precision),
+            None => return Ok(None),
+        }
+        precisions.push(precision);
+    }
+    Ok(Some((rates, precisions)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    fn units(value: u64, decimals: usize) -> U256 {
+        U256::from(value) * U256::exp10(decimals)
+    }
+
+    #[test]
+    fn test_plain_pool_mixed_decimals() {
+        // 3pool shaped: DAI, USDC, USDT
+        let a_precision = U256::from(100);
+        let pool = CurvePool::with_state(
+            Address::zero(),
+            (1..=3).map(address).collect(),
+            vec![
+                units(1_000_000, 18),
+                units(1_000_000, 6),
+                units(1_000_000, 6),
+            ],
+            CurvePoolKind::Plain {
+                amp: U256::from(2000) * a_precision,
+                a_precision,
+                rates: vec![U256::exp10(18), U256::exp10(30), U256::exp10(30)],
+            },
+            U256::from(1_000_000),
+        );
+
+        let amount_out = pool
+            .quote_exact_in(address(2), address(1), units(1000, 6))
+            .unwrap();
+        assert!(amount_out < units(1000, 18));
+        assert!(amount_out > units(999, 18));
+
+        // Exact out is the smallest input that buys the requested amount
+        let wanted = units(1000, 18);
+        let amount_in = pool
+            .quote_exact_out(address(2), address(1), wanted)
+            .unwrap();
+        assert!(
+            pool.quote_exact_in(address(2), address(1), amount_in)
+                .unwrap()
+                >= wanted
+        );
+        assert!(
+            pool.quote_exact_in(address(2), address(1), amount_in - 1)
+                .unwrap()
+                < wanted
+        );
+
+        let price = pool.spot_price(address(2), address(1)).unwrap();
+        assert!(price > units(1, 29) && price < units(1, 30) * 101 / 100);
+
+        // The same rates as from decimals, and none past 18 decimals
+        assert_eq!(precision(6, 36), Some(U256::exp10(30)));
+        assert_eq!(precision(24, 18), None);
+    }
+
+    #[test]
+    fn test_tricrypto_pool() {
+        // USDT, WBTC, WETH balanced at 30000 and 1500 USDT
+        let pool = CurvePool::with_state(
+            Address::zero(),
+            (1..=3).map(address).collect(),
+            vec![units(30_000_000, 6), units(1000, 8), units(20_000, 18)],
+            CurvePoolKind::Crypto {
+                ann: U256::from(1707629),
+                gamma: U256::from(11809167828997u64),
+                d: units(90_000_000, 18),
+                price_scale: vec![units(30_000, 18), units(1500, 18)],
+                precisions: vec![U256::exp10(12), U256::exp10(10), U256::one()],
+                mid_fee: U256::from(3_000_000),
+                out_fee: U256::from(30_000_000),
+                fee_gamma: U256::from(500_000_000_000_000u64),
+            },
+            U256::from(3_000_000),
+        );
+
+        let usdt = pool
+            .quote_exact_in(address(2), address(1), units(1, 8))
+            .unwrap();
+        assert!(usdt < units(30_000, 6));
+        assert!(usdt > units(29_900, 6));
+
+        let weth = pool
+            .quote_exact_in(address(2), address(3), units(1, 8))
+            .unwrap();
+        assert!(weth < units(20, 18));
+        assert!(weth > units(199, 17));
+    }
+}
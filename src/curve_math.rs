@@ -0,0 +1,285 @@
+// Ports of the Curve StableSwap and CryptoSwap invariant solvers. Rounding
+// follows the Vyper sources; `None` stands in for a revert.
+use ethers::core::types::U256;
+
+pub const FEE_DENOMINATOR: u64 = 10_000_000_000;
+// CryptoSwap A is stored as A * N**N * A_MULTIPLIER
+const A_MULTIPLIER: u64 = 10_000;
+
+fn precision() -> U256 {
+    U256::exp10(18)
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+// StableSwap get_D. `amp` is A * a_precision; legacy pools use an
+// a_precision of 1, newer ones 100.
+pub fn stable_get_d(xp: &[U256], amp: U256, a_precision: U256) -> Option<U256> {
+    let n = U256::from(xp.len());
+    let sum = xp
+        .iter()
+        .try_fold(U256::zero(), |sum, x| sum.checked_add(*x))?;
+    if sum.is_zero() {
+        return Some(sum);
+    }
+
+    let ann = amp.checked_mul(n)?;
+    let mut d = sum;
+    for _ in 0..255 {
+        let mut d_p = d;
+        for x in xp {
+            d_p = d_p.checked_mul(d)?.checked_div(x.checked_mul(n)?)?;
+        }
+        let previous = d;
+        let numerator = (ann.checked_mul(sum)? / a_precision)
+            .checked_add(d_p.checked_mul(n)?)?
+            .checked_mul(d)?;
+        let denominator = (ann.checked_sub(a_precision)?.checked_mul(d)? / a_precision)
+            .checked_add((n + 1).checked_mul(d_p)?)?;
+        d = numerator.checked_div(denominator)?;
+        if abs_diff(d, previous) <= U256::one() {
+            return Some(d);
+        }
+    }
+    None
+}
+
+// StableSwap get_y: the balance of coin j once coin i's balance becomes x
+pub fn stable_get_y(
+    i: usize,
+    j: usize,
+    x: U256,
+    xp: &[U256],
+    amp: U256,
+    a_precision: U256,
+) -> Option<U256> {
+    if i == j || i >= xp.len() || j >= xp.len() {
+        return None;
+    }
+    let n = U256::from(xp.len());
+    let d = stable_get_d(xp, amp, a_precision)?;
+    let ann = amp.checked_mul(n)?;
+
+    let mut c = d;
+    let mut s = U256::zero();
+    for (k, balance) in xp.iter().enumerate() {
+        let x_k = if k == i {
+            x
+        } else if k != j {
+            *balance
+        } else {
+            continue;
+        };
+        s = s.checked_add(x_k)?;
+        c = c.checked_mul(d)?.checked_div(x_k.checked_mul(n)?)?;
+    }
+    c = c
+        .checked_mul(d)?
+        .checked_mul(a_precision)?
+        .checked_div(ann.checked_mul(n)?)?;
+    let b = s.checked_add(d.checked_mul(a_precision)?.checked_div(ann)?)?;
+
+    let mut y = d;
+    for _ in 0..255 {
+        let previous = y;
+        let denominator = y
+            .checked_mul(U256::from(2))?
+            .checked_add(b)?
+            .checked_sub(d)?;
+        y = y.checked_mul(y)?.checked_add(c)?.checked_div(denominator)?;
+        if abs_diff(y, previous) <= U256::one() {
+            return Some(y);
+        }
+    }
+    None
+}
+
+// Aave style pools raise the fee as the pair moves off peg
+pub fn dynamic_fee(xpi: U256, xpj: U256, fee: U256, fee_multiplier: U256) -> U256 {
+    let fee_denominator = U256::from(FEE_DENOMINATOR);
+    if fee_multiplier <= fee_denominator {
+        return fee;
+    }
+    let xps2 = (xpi + xpj) * (xpi + xpj);
+    if xps2.is_zero() {
+        return fee;
+    }
+    fee_multiplier * fee
+        / ((fee_multiplier - fee_denominator) * 4 * xpi * xpj / xps2 + fee_denominator)
+}
+
+// CryptoSwap newton_y: the balance of coin i that keeps invariant D, for any
+// number of coins. `ann` is A * N**N * A_MULTIPLIER.
+pub fn crypto_newton_y(ann: U256, gamma: U256, x: &[U256], d: U256, i: usize) -> Option<U256> {
+    let n = x.len();
+    let n_coins = U256::from(n);
+    let precision = precision();
+    if i >= n || d.is_zero() || gamma.is_zero() || ann.is_zero() {
+        return None;
+    }
+
+    // The other balances, largest first
+    let mut x_sorted = x.to_vec();
+    x_sorted[i] = U256::zero();
+    x_sorted.sort_by(|a, b| b.cmp(a));
+    let convergence_limit = (x_sorted[0] / U256::exp10(14))
+        .max(d / U256::exp10(14))
+        .max(U256::from(100));
+
+    let mut y = d / n_coins;
+    let mut k0_i = precision;
+    let mut s_i = U256::zero();
+    for j in 2..=n {
+        let x_j = x_sorted[n - j];
+        y = y.checked_mul(d)?.checked_div(x_j.checked_mul(n_coins)?)?;
+        s_i = s_i.checked_add(x_j)?;
+    }
+    for x_j in &x_sorted[..n - 1] {
+        k0_i = k0_i.checked_mul(*x_j)?.checked_mul(n_coins)? / d;
+    }
+
+    for _ in 0..255 {
+        let previous = y;
+        let k0 = k0_i.checked_mul(y)?.checked_mul(n_coins)? / d;
+        let s = s_i.checked_add(y)?;
+
+        let mut g1k0 = gamma + precision;
+        g1k0 = if g1k0 > k0 {
+            g1k0 - k0 + 1
+        } else {
+            k0 - g1k0 + 1
+        };
+
+        // D / (A * N**N) * g1k0**2 / gamma**2
+        let mul1 = (precision.checked_mul(d)? / gamma)
+            .checked_mul(g1k0)?
+            .checked_div(gamma)?
+            .checked_mul(g1k0)?
+            .checked_mul(U256::from(A_MULTIPLIER))?
+            / ann;
+        // 2 * K0 / g1k0
+        let mul2 = precision.checked_add((precision * U256::from(2)).checked_mul(k0)? / g1k0)?;
+
+        let mut yfprime = precision
+            .checked_mul(y)?
+            .checked_add(s.checked_mul(mul2)?)?
+            .checked_add(mul1)?;
+        let dyfprime = d.checked_mul(mul2)?;
+        if yfprime < dyfprime {
+            y = previous / 2;
+            continue;
+        }
+        yfprime -= dyfprime;
+        let fprime = yfprime.checked_div(y)?;
+        if fprime.is_zero() || k0.is_zero() {
+            return None;
+        }
+
+        let mut y_minus = mul1 / fprime;
+        let y_plus = (yfprime.checked_add(precision.checked_mul(d)?)? / fprime)
+            .checked_add(y_minus.checked_mul(precision)? / k0)?;
+        y_minus = y_minus.checked_add(precision.checked_mul(s)? / fprime)?;
+
+        y = if y_plus < y_minus {
+            previous / 2
+        } else {
+            y_plus - y_minus
+        };
+
+        if abs_diff(y, previous) < convergence_limit.max(y / U256::exp10(14)) {
+            let frac = y.checked_mul(precision)? / d;
+            if frac < U256::exp10(16) || frac > U256::exp10(20) {
+                return None;
+            }
+            return Some(y);
+        }
+    }
+    None
+}
+
+// CryptoSwap _fee: slides from mid_fee to out_fee as balances leave equilibrium
+pub fn crypto_fee(xp: &[U256], mid_fee: U256, out_fee: U256, fee_gamma: U256) -> U256 {
+    let precision = precision();
+    let n = U256::from(xp.len());
+    let s = xp.iter().fold(U256::zero(), |s, x| s + *x);
+    if s.is_zero() {
+        return out_fee;
+    }
+    let mut k = precision;
+    for x in xp {
+        k = k * n * *x / s;
+    }
+    if !fee_gamma.is_zero() {
+        k = fee_gamma * precision / (fee_gamma + precision - k);
+    }
+    (mid_fee * k + out_fee * (precision - k)) / precision
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fp(value: u64) -> U256 {
+        U256::from(value) * precision()
+    }
+
+    #[test]
+    fn test_stable_invariant_and_swap() {
+        let xp = vec![fp(1_000_000); 3];
+        let a_precision = U256::from(100);
+        let amp = U256::from(2000) * a_precision;
+
+        let d = stable_get_d(&xp, amp, a_precision).unwrap();
+        assert!(abs_diff(d, fp(3_000_000)) <= U256::one());
+
+        // Balanced pools swap almost 1:1
+        let y = stable_get_y(0, 1, xp[0] + fp(1000), &xp, amp, a_precision).unwrap();
+        let dy = xp[1] - y;
+        assert!(dy < fp(1000));
+        assert!(dy > fp(999));
+
+        // Legacy pools without A precision agree
+        let legacy = stable_get_y(0, 1, xp[0] + fp(1000), &xp, U256::from(2000), U256::one());
+        assert!(abs_diff(legacy.unwrap(), y) <= U256::from(2));
+    }
+
+    #[test]
+    fn test_dynamic_fee() {
+        let fee = U256::from(4_000_000);
+        let multiplier = U256::from(2 * FEE_DENOMINATOR);
+        // Balanced reserves pay the base fee, imbalanced ones more
+        assert_eq!(dynamic_fee(fp(100), fp(100), fee, multiplier), fee);
+        assert!(dynamic_fee(fp(150), fp(50), fee, multiplier) > fee);
+        assert_eq!(dynamic_fee(fp(150), fp(50), fee, U256::zero()), fee);
+    }
+
+    #[test]
+    fn test_crypto_newton_y() {
+        let ann = U256::from(1707629);
+        let gamma = U256::from(11809167828997u64);
+        let xp = vec![fp(1_000_000); 3];
+        let d = fp(3_000_000);
+
+        // At equilibrium the solved balance is the current one
+        let y = crypto_newton_y(ann, gamma, &xp, d, 2).unwrap();
+        assert!(abs_diff(y, fp(1_000_000)) < fp(1) / 1000);
+
+        // Adding coin 0 lowers the balance coin 2 has to keep
+        let mut moved = xp.clone();
+        moved[0] += fp(1000);
+        let y = crypto_newton_y(ann, gamma, &moved, d, 2).unwrap();
+        assert!(y < fp(1_000_000));
+        assert!(fp(1_000_000) - y < fp(1000));
+
+        let mid_fee = U256::from(3_000_000);
+        let out_fee = U256::from(30_000_000);
+        let fee_gamma = U256::from(500_000_000_000_000u64);
+        assert_eq!(crypto_fee(&xp, mid_fee, out_fee, fee_gamma), mid_fee);
+    }
+}
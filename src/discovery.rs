@@ -0,0 +1,408 @@
+use crate::config::{
+    BALANCER_VAULT_ADDRESS, BALANCER_VAULT_DEPLOYMENT_BLOCK, MAINNET_WETH_ADDRESS,
+    PANCAKESWAP_FACTORY_ADDRESS, SUSHISWAP_FACTORY_ADDRESS, UNISWAP_V2_FACTORY_ADDRESS,
+};
+use crate::contract_interfaces::{
+    IBalancerPool, IBalancerVault, IUniswapV2Factory, IUniswapV2Pair, PoolRegisteredFilter,
+};
+use crate::fetcher::MAX_CALLS_PER_BATCH;
+use crate::report::print_warning;
+use crate::tokens::TokenRegistry;
+use crate::utils::{decode, Pair, Token};
+use ethers::{
+    abi::{RawLog, Token as AbiToken},
+    contract::{EthEvent, Multicall, MulticallVersion},
+    core::types::{Address, Filter, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Tokens looked up per `TokenRegistry::resolve`
+const MAX_TOKENS_PER_BATCH: usize = 200;
+// Blocks per eth_getLogs when collecting PoolRegistered events, which are
+// rare enough to stay under every provider's result limit
+const MAX_LOG_RANGE: u64 = 100_000;
+
+/// Where a venue lists its pools on chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolSource {
+    /// A Uniswap V2 style factory, enumerated through `allPairs`.
+    Factory(Address),
+    /// The Balancer vault's `PoolRegistered` events since `from_block`.
+    BalancerVault { from_block: u64 },
+}
+
+/// The exchanges whose config files `discover` can regenerate on mainnet.
+pub fn mainnet_sources() -> Result<Vec<(&'static str, PoolSource)>> {
+    Ok(vec![
+        (
+            "UniswapV2",
+            PoolSource::Factory(UNISWAP_V2_FACTORY_ADDRESS.parse()?),
+        ),
+        (
+            "Sushiswap",
+            PoolSource::Factory(SUSHISWAP_FACTORY_ADDRESS.parse()?),
+        ),
+        (
+            "Pancakeswap",
+            PoolSource::Factory(PANCAKESWAP_FACTORY_ADDRESS.parse()?),
+        ),
+        (
+            "Balancer",
+            PoolSource::BalancerVault {
+                from_block: BALANCER_VAULT_DEPLOYMENT_BLOCK,
+            },
+        ),
+    ])
+}
+
+// A pool trading two tokens, WETH second if it's one of them, and its
+// balance of each
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Candidate {
+    address: Address,
+    tokens: [Address; 2],
+    reserves: [U256; 2],
+}
+
+/// Lists the pools of a venue that trade against WETH, or between two tokens
+/// already in the registry, and hold at least `min_liquidity` worth of WETH,
+/// most liquid first, as config file entries. Multi-token pools get an entry
+/// for each such pair of their tokens.
+pub struct Discovery<M: Middleware> {
+    client: Arc<M>,
+    tokens: Arc<TokenRegistry>,
+    weth: Address,
+    min_liquidity: U256,
+    // Looked up once rather than by every Multicall::new
+    chain_id: U256,
+}
+
+impl<M: Middleware + 'static> Discovery<M> {
+    pub async fn new(
+        client: Arc<M>,
+        tokens: Arc<TokenRegistry>,
+        min_liquidity: U256,
+    ) -> Result<Self> {
+        let chain_id = client.get_chainid().await?;
+        Ok(Self {
+            client,
+            tokens,
+            weth: MAINNET_WETH_ADDRESS.parse()?,
+            min_liquidity,
+            chain_id,
+        })
+    }
+
+    pub async fn discover(&self, source: PoolSource) -> Result<Vec<Pair>> {
+        let candidates = match source {
+            PoolSource::Factory(factory) => self.factory_candidates(factory).await?,
+            PoolSource::BalancerVault { from_block } => self.vault_candidates(from_block).await?,
+        };
+        self.pairs(self.liquid(candidates)).await
+    }
+
+    async fn factory_candidates(&self, factory: Address) -> Result<Vec<Candidate>> {
+        let factory = IUniswapV2Factory::new(factory, self.client.clone());
+        let count = factory.all_pairs_length().call().await?.as_usize();
+
+        let indices: Vec<usize> = (0..count).collect();
+        let results = self
+            .batch_call(&indices, 1, |multicall, index| {
+                multicall.add_call(factory.all_pairs(U256::from(*index)), true);
+            })
+            .await?;
+        let pairs: Vec<Address> = results
+            .into_iter()
+            .filter_map(|results| decode(results[0].clone()))
+            .collect();
+
+        let results = self
+            .batch_call(&pairs, 3, |multicall, pair| {
+                let contract = IUniswapV2Pair::new(*pair, self.client.clone());
+                multicall
+                    .add_call(contract.token_0(), true)
+                    .add_call(contract.token_1(), true)
+                    .add_call(contract.get_reserves(), true);
+            })
+            .await?;
+        let mut candidates = vec![];
+        for (pair, results) in pairs.iter().zip(results) {
+            let token0: Option<Address> = decode(results[0].clone());
+            let token1: Option<Address> = decode(results[1].clone());
+            let reserves: Option<(u128, u128, u32)> = decode(results[2].clone());
+            let (Some(token0), Some(token1), Some((reserve0, reserve1, _))) =
+                (token0, token1, reserves)
+            else {
+                continue;
+            };
+            candidates.extend(self.candidates(
+                *pair,
+                &[token0, token1],
+                &[U256::from(reserve0), U256::from(reserve1)],
+            ));
+        }
+        Ok(candidates)
+    }
+
+    async fn vault_candidates(&self, from_block: u64) -> Result<Vec<Candidate>> {
+        let vault_address = BALANCER_VAULT_ADDRESS.parse::<Address>()?;
+        let latest = self.client.get_block_number().await?.as_u64();
+        let mut registered = vec![];
+        let mut start = from_block;
+        while start <= latest {
+            let end = (start + MAX_LOG_RANGE - 1).min(latest);
+            let filter = Filter::new()
+                .address(vault_address)
+                .topic0(PoolRegisteredFilter::signature())
+                .from_block(start)
+                .to_block(end);
+            for log in self.client.get_logs(&filter).await? {
+                let event = PoolRegisteredFilter::decode_log(&RawLog {
+                    topics: log.topics,
+                    data: log.data.to_vec(),
+                })?;
+                registered.push((event.pool_id, event.pool_address));
+            }
+            start = end + 1;
+        }
+
+        // Only weighted and stable pools can be quoted; the other calls tell
+        // them apart the same way `BalancerPool::fetch_state` does
+        let vault = IBalancerVault::new(vault_address, self.client.clone());
+        let results = self
+            .batch_call(&registered, 3, |multicall, (pool_id, address)| {
+                let pool = IBalancerPool::new(*address, self.client.clone());
+                multicall
+                    .add_call(vault.get_pool_tokens(*pool_id), true)
+                    .add_call(pool.get_normalized_weights(), true)
+                    .add_call(pool.get_amplification_parameter(), true);
+            })
+            .await?;
+        let mut candidates = vec![];
+        for ((_, address), results) in registered.iter().zip(results) {
+            let pool_tokens: Option<(Vec<Address>, Vec<U256>, U256)> = decode(results[0].clone());
+            let weights: Option<Vec<U256>> = decode(results[1].clone());
+            let amp: Option<(U256, bool, U256)> = decode(results[2].clone());
+            let Some((tokens, balances, _)) = pool_tokens else {
+                continue;
+            };
+            if weights.is_some() || amp.is_some() {
+                candidates.extend(self.candidates(*address, &tokens, &balances));
+            }
+        }
+        Ok(candidates)
+    }
+
+    // The pool's pairs against WETH, and between tokens already in the
+    // registry. Composable pools list their own BPT, which isn't a pair.
+    fn candidates(
+        &self,
+        address: Address,
+        tokens: &[Address],
+        reserves: &[U256],
+    ) -> Vec<Candidate> {
+        let known = |token: Address| token == self.weth || self.tokens.get(token).is_some();
+        let held: Vec<(Address, U256)> = tokens
+            .iter()
+            .copied()
+            .zip(reserves.iter().copied())
+            .filter(|(token, _)| *token != address)
+            .collect();
+        let mut candidates = vec![];
+        for (i, a) in held.iter().enumerate() {
+            for b in &held[i + 1..] {
+                let (a, b) = if a.0 == self.weth { (b, a) } else { (a, b) };
+                if b.0 == self.weth || (known(a.0) && known(b.0)) {
+                    candidates.push(Candidate {
+                        address,
+                        tokens: [a.0, b.0],
+                        reserves: [a.1, b.1],
+                    });
+                }
+            }
+        }
+        candidates
+    }
+
+    // The candidates holding at least `min_liquidity`, with their liquidity:
+    // their WETH balance, or else their first token's balance valued at its
+    // price in its deepest WETH pool. Pools whose tokens have no WETH pool
+    // can't be valued and are left out.
+    fn liquid(&self, candidates: Vec<Candidate>) -> Vec<(Candidate, U256)> {
+        // WETH and token balances of each token's deepest WETH pool
+        let mut prices: HashMap<Address, (U256, U256)> = HashMap::new();
+        for candidate in &candidates {
+            let [token, weth] = candidate.tokens;
+            let [token_reserve, weth_reserve] = candidate.reserves;
+            if weth == self.weth
+                && prices
+                    .get(&token)
+                    .is_none_or(|(deepest, _)| weth_reserve > *deepest)
+            {
+                prices.insert(token, (weth_reserve, token_reserve));
+            }
+        }
+        candidates
+            .into_iter()
+            .filter_map(|candidate| {
+                let liquidity = if candidate.tokens[1] == self.weth {
+                    candidate.reserves[1]
+                } else {
+                    let (weth_reserve, token_reserve) = prices.get(&candidate.tokens[0])?;
+                    candidate.reserves[0]
+                        .checked_mul(*weth_reserve)?
+                        .checked_div(*token_reserve)?
+                };
+                (liquidity >= self.min_liquidity).then_some((candidate, liquidity))
+            })
+            .collect()
+    }
+
+    // Config entries for `candidates`, most liquid first. Tokens whose
+    // metadata can't be read are left out.
+    async fn pairs(&self, mut candidates: Vec<(Candidate, U256)>) -> Result<Vec<Pair>> {
+        let mut addresses: Vec<Address> = candidates
+            .iter()
+            .flat_map(|(candidate, _)| candidate.tokens)
+            .filter(|token| *token != self.weth)
+            .collect();
+        addresses.sort();
+        addresses.dedup();
+        let mut tokens: HashMap<Address, Token> = HashMap::new();
+        for chunk in addresses.chunks(MAX_TOKENS_PER_BATCH) {
+            for token in self.resolve(chunk).await {
+                tokens.insert(token.address, token);
+            }
+        }
+
+        // Config files list ETH where pools hold WETH
+        let symbol = |token: Address| {
+            if token == self.weth {
+                Some("ETH".to_string())
+            } else {
+                tokens.get(&token).map(|token| token.symbol.clone())
+            }
+        };
+        candidates.sort_by_key(|(_, liquidity)| std::cmp::Reverse(*liquidity));
+        Ok(candidates
+            .iter()
+            .filter_map(|(candidate, _)| {
+                Some(Pair {
+                    pair: vec![symbol(candidate.tokens[0])?, symbol(candidate.tokens[1])?],
+                    address: candidate.address,
+                    fee_bps: None,
+                })
+            })
+            .collect())
+    }
+
+    // One bad token fails a whole `resolve`, so a failed batch is retried a
+    // token at a time
+    async fn resolve(&self, addresses: &[Address]) -> Vec<Token> {
+        if let Ok(tokens) = self.tokens.resolve(&self.client, addresses).await {
+            return tokens;
+        }
+        let mut tokens = vec![];
+        for address in addresses {
+            match self.tokens.resolve(&self.client, &[*address]).await {
+                Ok(token) => tokens.extend(token),
+                Err(error) => print_warning(format!("Skipping token {address:?}: {error}")),
+            }
+        }
+        tokens
+    }
+
+    // Adds `calls_per_item` calls for each item and returns each item's
+    // results, in order, batching as many items per aggregate as fit
+    async fn batch_call<T>(
+        &self,
+        items: &[T],
+        calls_per_item: usize,
+        add_calls: impl Fn(&mut Multicall<M>, &T),
+    ) -> Result<Vec<Vec<AbiToken>>> {
+        let mut multicall =
+            Multicall::new_with_chain_id(self.client.clone(), None, Some(self.chain_id))?
+                .version(MulticallVersion::Multicall3);
+        let mut results = vec![];
+        for chunk in items.chunks((MAX_CALLS_PER_BATCH / calls_per_item).max(1)) {
+            multicall.clear_calls();
+            for item in chunk {
+                add_calls(&mut multicall, item);
+            }
+            let tokens = multicall.call_raw().await?;
+            if tokens.len() != chunk.len() * calls_per_item {
+                return Err(eyre::eyre!("Multicall returned too few results"));
+            }
+            results.extend(tokens.chunks(calls_per_item).map(|calls| calls.to_vec()));
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::Provider;
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    #[test]
+    fn test_candidates_need_enough_weth() {
+        let (provider, _mock) = Provider::mocked();
+        let eth = U256::exp10(18);
+        let weth: Address = MAINNET_WETH_ADDRESS.parse().unwrap();
+        let token = |n: u64, symbol: &str| Token {
+            symbol: symbol.to_string(),
+            address: address(n),
+            decimals: 18,
+        };
+        let mut discovery = Discovery {
+            client: Arc::new(provider),
+            tokens: Arc::new(TokenRegistry::new()),
+            weth,
+            min_liquidity: eth * 10,
+            chain_id: U256::one(),
+        };
+
+        // A composable pool holding WETH, two tokens and its own BPT
+        let pool = address(9);
+        let balances = [eth; 4].map(|r| r * 20);
+        let tokens = [address(1), weth, pool, address(2)];
+        let pairs = |candidates: Vec<Candidate>| -> Vec<[Address; 2]> {
+            candidates.iter().map(|c| c.tokens).collect()
+        };
+        assert_eq!(
+            pairs(discovery.candidates(pool, &tokens, &balances)),
+            vec![[address(1), weth], [address(2), weth]]
+        );
+
+        // Pairs of known tokens are kept too, valued at their WETH price
+        discovery.tokens =
+            Arc::new(TokenRegistry::new().with_tokens([token(1, "USDC"), token(2, "DAI")]));
+        let candidates = discovery.candidates(pool, &tokens, &balances);
+        assert_eq!(
+            pairs(candidates.clone()),
+            vec![
+                [address(1), weth],
+                [address(1), address(2)],
+                [address(2), weth]
+            ]
+        );
+        let liquid = discovery.liquid(candidates);
+        assert!(liquid.iter().all(|(_, liquidity)| *liquidity == eth * 20));
+        assert_eq!(liquid.len(), 3);
+
+        // Shallow WETH pools, and pools whose tokens have no WETH pool, are out
+        assert!(discovery
+            .liquid(discovery.candidates(pool, &[address(1), weth], &[eth * 100, eth * 5]))
+            .is_empty());
+        assert!(discovery
+            .liquid(discovery.candidates(pool, &[address(1), address(2)], &[eth * 100; 2]))
+            .is_empty());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::exchange::PoolState;
+use crate::report::print_warning;
+use ethers::{
+    core::types::{Address, Filter, H256, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+// Blocks of undo history kept; deeper reorgs re-seed every pool
+const MAX_REORG_DEPTH: usize = 64;
+// Longest gap caught up on with one eth_getLogs; longer ones re-seed
+const MAX_LOG_RANGE: u64 = 1000;
+// Blocks between re-seeds, which pick up what events don't carry (fee and
+// amp changes, rate providers, ...)
+const RESEED_INTERVAL: u64 = 300;
+
+// A synced block and the reserves each pool had before its logs applied
+#[derive(Debug)]
+struct BlockRecord {
+    number: u64,
+    hash: H256,
+    undo: Vec<(usize, Vec<U256>)>,
+}
+
+/// Keeps pools with an `event_source` up to date from their logs instead of
+/// polling them, once they've been seeded with a full fetch. Blocks that get
+/// reorged out are rolled back before the new chain's logs are applied.
+///
+/// Pools are referred to by their index in the slice passed to `sync`, which
+/// must list the same pools in the same order every time.
+#[derive(Debug, Default)]
+pub struct EventTracker {
+    // Recent synced blocks, oldest first
+    history: VecDeque<BlockRecord>,
+    seeded_at: Option<u64>,
+}
+
+impl EventTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that every pool was just fetched at `block_number`.
+    pub async fn seed<M: Middleware + 'static>(
+        &mut self,
+        client: &Arc<M>,
+        block_number: u64,
+    ) -> Result<()> {
+        let hash = block_hash(client, block_number).await?;
+        self.history.clear();
+        self.push(BlockRecord {
+            number: block_number,
+            hash,
+            undo: vec![],
+        });
+        self.seeded_at = Some(block_number);
+        Ok(())
+    }
+
+    /// Applies the tracked pools' logs from the last synced block up to
+    /// `block_number`. Returns false if the pools have to be seeded again
+    /// instead: nothing was seeded yet, the reorg was deeper than the kept
+    /// history, the gap was too long, a log couldn't be applied or a periodic
+    /// re-seed is due. Logs applied before finding out are rolled back.
+    pub async fn sync<M: Middleware + 'static>(
+        &mut self,
+        client: &Arc<M>,
+        pools: &mut [&mut Box<dyn PoolState<M>>],
+        block_number: u64,
+    ) -> Result<bool> {
+        match self.seeded_at {
+            Some(seeded_at) if block_number < seeded_at + RESEED_INTERVAL => {}
+            _ => return Ok(false),
+        }
+        let tip = client
+            .get_block(block_number)
+            .await?
+            .ok_or_else(|| eyre::eyre!("Block {block_number} not found"))?;
+        let tip_hash = tip
+            .hash
+            .ok_or_else(|| eyre::eyre!("Block {block_number} is pending"))?;
+
+        // Undo blocks that are no longer on the canonical chain
+        while let Some(record) = self.history.back() {
+            let canonical = if record.number + 1 == block_number {
+                tip.parent_hash
+            } else if record.number == block_number {
+                tip_hash
+            } else {
+                block_hash(client, record.number).await?
+            };
+            if canonical == record.hash {
+                break;
+            }
+            let record = self.history.pop_back().expect("checked above");
+            for (index, reserves) in record.undo.into_iter().rev() {
+                pools[index].restore_reserves(reserves);
+            }
+        }
+        let Some(last) = self.history.back() else {
+            return Ok(false);
+        };
+        if last.number >= block_number {
+            return Ok(true);
+        }
+        if block_number - last.number > MAX_LOG_RANGE {
+            return Ok(false);
+        }
+
+        let mut sources: HashMap<Address, Vec<usize>> = HashMap::new();
+        let mut topics = vec![];
+        for (index, pool) in pools.iter().enumerate() {
+            if let Some((address, events)) = pool.event_source() {
+                sources.entry(address).or_default().push(index);
+                topics.extend(events);
+            }
+        }
+        topics.sort();
+        topics.dedup();
+
+        let mut logs = vec![];
+        if !sources.is_empty() {
+            let filter = Filter::new()
+                .address(sources.keys().copied().collect::<Vec<_>>())
+                .topic0(topics)
+                .from_block(last.number + 1)
+                .to_block(block_number);
+            logs = client.get_logs(&filter).await?;
+        }
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+
+        let mut records: Vec<BlockRecord> = vec![];
+        for log in logs {
+            let (Some(number), Some(hash)) = (log.block_number, log.block_hash) else {
+                continue;
+            };
+            let number = number.as_u64();
+            // The logs came from another fork than the tip's
+            if number == block_number && hash != tip_hash {
+                roll_back(pools, records);
+                return Ok(false);
+            }
+            if records.last().map(|record| record.number) != Some(number) {
+                records.push(BlockRecord {
+                    number,
+                    hash,
+                    undo: vec![],
+                });
+            }
+            let record = records.last_mut().expect("pushed above");
+            for index in sources.get(&log.address).into_iter().flatten() {
+                let reserves = pools[*index].reserves().to_vec();
+                let applied = match pools[*index].apply_log(&log) {
+                    Ok(applied) => applied,
+                    // Erroring out would leave the logs applied so far out of
+                    // the history, so the next sync would apply them again
+                    Err(error) => {
+                        print_warning(format!("Can't apply log of {:?}: {error}", log.address));
+                        pools[*index].restore_reserves(reserves);
+                        roll_back(pools, records);
+                        return Ok(false);
+                    }
+                };
+                if applied && !record.undo.iter().any(|(undone, _)| undone == index) {
+                    record.undo.push((*index, reserves));
+                }
+            }
+        }
+        if records.last().map(|record| record.number) != Some(block_number) {
+            records.push(BlockRecord {
+                number: block_number,
+                hash: tip_hash,
+                undo: vec![],
+            });
+        }
+        for record in records {
+            self.push(record);
+        }
+        Ok(true)
+    }
+
+    fn push(&mut self, record: BlockRecord) {
+        self.history.push_back(record);
+        if self.history.len() > MAX_REORG_DEPTH {
+            self.history.pop_front();
+        }
+    }
+}
+
+// Undoes `records` that never made it into the history, latest first
+fn roll_back<M: Middleware>(pools: &mut [&mut Box<dyn PoolState<M>>], records: Vec<BlockRecord>) {
+    for record in records.into_iter().rev() {
+        for (index, reserves) in record.undo.into_iter().rev() {
+            pools[index].restore_reserves(reserves);
+        }
+    }
+}
+
+async fn block_hash<M: Middleware + 'static>(client: &Arc<M>, block_number: u64) -> Result<H256> {
+    client
+        .get_block(block_number)
+        .await?
+        .and_then(|block| block.hash)
+        .ok_or_else(|| eyre::eyre!("Block {block_number} not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract_interfaces::SyncFilter;
+    use crate::uniswap_v2::UniswapV2Pool;
+    use ethers::{
+        abi::{encode, Token},
+        contract::EthEvent,
+        core::types::{Block, Log, TxHash, U64},
+        providers::{MockProvider, Provider},
+    };
+
+    fn block(number: u64, hash: u64, parent: u64) -> Block<TxHash> {
+        Block {
+            number: Some(U64::from(number)),
+            hash: Some(H256::from_low_u64_be(hash)),
+            parent_hash: H256::from_low_u64_be(parent),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_rolls_back_reorged_blocks() {
+        let (provider, mock) = Provider::mocked();
+        let client = Arc::new(provider);
+        let address = Address::from_low_u64_be(1);
+        let tokens = vec![Address::from_low_u64_be(2), Address::from_low_u64_be(3)];
+        let reserves = vec![U256::from(100), U256::from(200)];
+        let mut pool: Box<dyn PoolState<Provider<MockProvider>>> = Box::new(
+            UniswapV2Pool::with_state("UniswapV2", address, tokens, reserves.clone(), 30),
+        );
+        let mut tracker = EventTracker::new();
+
+        mock.push(block(10, 0xa, 0x9)).unwrap();
+        tracker.seed(&client, 10).await.unwrap();
+
+        // Block 11 syncs the pool to new reserves
+        let sync = Log {
+            address,
+            topics: vec![SyncFilter::signature()],
+            data: encode(&[Token::Uint(150.into()), Token::Uint(140.into())]).into(),
+            block_number: Some(U64::from(11)),
+            block_hash: Some(H256::from_low_u64_be(0xb)),
+            log_index: Some(U256::zero()),
+            ..Default::default()
+        };
+        mock.push::<Vec<Log>, _>(vec![sync.clone()]).unwrap();
+        mock.push(block(11, 0xb, 0xa)).unwrap();
+        assert!(tracker.sync(&client, &mut [&mut pool], 11).await.unwrap());
+        assert_eq!(pool.reserves(), [U256::from(150), U256::from(140)]);
+
+        // Block 11 is replaced by one without the swap
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(block(11, 0xc, 0xa)).unwrap();
+        assert!(tracker.sync(&client, &mut [&mut pool], 11).await.unwrap());
+        assert_eq!(pool.reserves(), reserves);
+
+        // A log that can't be decoded undoes the ones before it in the batch
+        let mut garbled = sync.clone();
+        garbled.data = Default::default();
+        garbled.log_index = Some(U256::one());
+        mock.push::<Vec<Log>, _>(vec![sync, garbled]).unwrap();
+        mock.push(block(11, 0xb, 0xa)).unwrap();
+        assert!(!tracker.sync(&client, &mut [&mut pool], 11).await.unwrap());
+        assert_eq!(pool.reserves(), reserves);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::balancer::Balancer;
+use crate::curve::Curve;
+use crate::executor::StepKind;
+use crate::tokens::TokenRegistry;
+use crate::uniswap_v2::UniswapV2;
+use crate::uniswap_v3::UniswapV3;
+use async_trait::async_trait;
+use ethers::{
+    abi::Token,
+    contract::Multicall,
+    core::types::{Address, Log, H256, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+use std::sync::Arc;
+
+/// A trading venue. Its name doubles as the config file name under
+/// `src/data/{network}/{name}.config.json`.
+pub trait Exchange<M: Middleware>: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Builds an adapter for one of the venue's pools. No state is loaded
+    /// until `PoolAdapter::fetch_state` is called. `fee_bps` overrides the
+    /// venue's default fee; venues that read fees on chain ignore it.
+    fn pool(&self, address: Address, fee_bps: Option<u32>) -> Box<dyn PoolState<M>>;
+}
+
+/// A pool's state and swap math. Quoting never touches the network.
+pub trait PoolAdapter: Send + Sync {
+    fn exchange(&self) -> &str;
+
+    fn address(&self) -> Address;
+
+    fn tokens(&self) -> &[Address];
+
+    /// Balances of `tokens()`, in the same order.
+    fn reserves(&self) -> &[U256];
+
+    /// Swap fee as an 18 decimal fraction (3e15 = 0.3%).
+    fn fee(&self) -> U256;
+
+    /// Marginal price of `token_in` in `token_out` before fees, as an 18
+    /// decimal fixed point number of raw units.
+    fn spot_price(&self, token_in: Address, token_out: Address) -> Option<U256> {
+        let index = |token: Address| self.tokens().iter().position(|t| *t == token);
+        let reserve_in = *self.reserves().get(index(token_in)?)?;
+        let reserve_out = *self.reserves().get(index(token_out)?)?;
+        if reserve_in.is_zero() {
+            return None;
+        }
+        Some(reserve_out * U256::exp10(18) / reserve_in)
+    }
+
+    /// Fee in basis points of a plain x*y=k pool, which lets trades be sized
+    /// in closed form. None for every other curve.
+    fn constant_product_fee_bps(&self) -> Option<u32> {
+        None
+    }
+
+    /// How the flash loan executor contract swaps through the pool. None for
+    /// venues it can't trade on.
+    fn executor_step(&self) -> Option<StepKind> {
+        None
+    }
+
+    /// Amount of `token_out` received for selling `amount_in` of `token_in`.
+    fn quote_exact_in(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+    ) -> Option<U256>;
+
+    /// Amount of `token_in` required to receive `amount_out` of `token_out`.
+    fn quote_exact_out(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_out: U256,
+    ) -> Option<U256>;
+}
+
+/// Loads a `PoolAdapter`'s state through any provider.
+#[async_trait]
+pub trait PoolState<M: Middleware>: PoolAdapter {
+    /// Loads the pool's on-chain state at `block_number`, including what
+    /// never changes (tokens, pool kind, ...). Token decimals come from
+    /// `tokens`.
+    async fn fetch_state(
+        &mut self,
+        client: &Arc<M>,
+        tokens: &TokenRegistry,
+        block_number: u64,
+    ) -> Result<()>;
+
+    /// Adds the calls that refresh a loaded pool's changing state to a
+    /// shared batch and returns how many were added.
+    fn add_state_calls(&self, client: &Arc<M>, multicall: &mut Multicall<M>) -> Result<usize>;
+
+    /// Updates the pool from the Multicall3 results of its `add_state_calls`,
+    /// in order. Returns false if the pool changed shape and has to be
+    /// loaded again with `fetch_state`.
+    fn apply_state(&mut self, results: &[Token]) -> Result<bool>;
+
+    /// Contract and event signatures (topic0) of the logs that move the
+    /// pool's reserves. None for pools that can only be polled.
+    fn event_source(&self) -> Option<(Address, Vec<H256>)> {
+        None
+    }
+
+    /// Updates the reserves from one of the `event_source` logs. Returns false
+    /// if the log belongs to another pool.
+    fn apply_log(&mut self, _log: &Log) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Puts back `reserves()` as they were before a reorged out block.
+    fn restore_reserves(&mut self, _reserves: Vec<U256>) {}
+}
+
+pub struct ExchangeRegistry<M: Middleware> {
+    exchanges: Vec<Arc<dyn Exchange<M>>>,
+}
+
+impl<M: Middleware + 'static> ExchangeRegistry<M> {
+    pub fn new() -> Self {
+        Self { exchanges: vec![] }
+    }
+
+    pub fn register(mut self, exchange: impl Exchange<M> + 'static) -> Self {
+        self.exchanges.push(Arc::new(exchange));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Exchange<M>>> {
+        self.exchanges
+            .iter()
+            .find(|exchange| exchange.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn Exchange<M>>> {
+        self.exchanges.iter()
+    }
+}
+
+impl<M: Middleware + 'static> Default for ExchangeRegistry<M> {
+    fn default() -> Self {
+        Self::new()
+            .register(UniswapV2::new("Sushiswap", 30))
+            .register(UniswapV2::new("UniswapV2", 30))
+            .register(UniswapV2::new("Pancakeswap", 25))
+            .register(UniswapV3::new("UniswapV3"))
+            .register(Balancer)
+            .register(Curve)
+    }
+}
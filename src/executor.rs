@@ -0,0 +1,454 @@
+use crate::contract_interfaces::IFlashLoanArbitrage;
+use crate::exchange::PoolAdapter;
+use crate::funding::Lender;
+use crate::graph::Cycle;
+use crate::utils::ArbitrageOpportunity;
+use ethers::{
+    abi::{encode, Token},
+    contract::builders::ContractCall,
+    core::types::{Address, Bytes, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+use std::sync::Arc;
+
+// Step kinds as numbered in contracts/FlashLoan.sol
+const UNISWAP_V2: u8 = 0;
+const BALANCER: u8 = 1;
+
+/// How the executor contract swaps through a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    /// Transfer in and `swap` on a V2 style pair, pricing the output with
+    /// the pair's fee.
+    UniswapV2 { fee_bps: u32 },
+    /// `swap` through the Balancer vault.
+    Balancer { pool_id: [u8; 32] },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteStep {
+    pub kind: StepKind,
+    pub pool: Address,
+    pub token_in: Address,
+    pub token_out: Address,
+    // The step reverts if it buys less
+    pub min_amount_out: U256,
+}
+
+impl RouteStep {
+    fn into_token(self) -> Token {
+        let (kind, pool_id, fee_bps) = match self.kind {
+            StepKind::UniswapV2 { fee_bps } => (UNISWAP_V2, [0; 32], fee_bps),
+            StepKind::Balancer { pool_id } => (BALANCER, pool_id, 0),
+        };
+        Token::Tuple(vec![
+            Token::Uint(kind.into()),
+            Token::Address(self.pool),
+            Token::FixedBytes(pool_id.to_vec()),
+            Token::Address(self.token_in),
+            Token::Address(self.token_out),
+            Token::Uint(fee_bps.into()),
+            Token::Uint(self.min_amount_out),
+        ])
+    }
+}
+
+/// Swaps the executor runs with a flash loan of the first step's input
+/// token, which the last step has to buy back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub steps: Vec<RouteStep>,
+    // Left over after repaying the loan, or the transaction reverts
+    pub min_profit: U256,
+}
+
+impl Route {
+    /// Route through `hops` of (pool, token in, token out, expected amount
+    /// out), accepting `slippage_bps` less than expected from each.
+    pub fn new(
+        hops: &[(&dyn PoolAdapter, Address, Address, U256)],
+        slippage_bps: u32,
+        min_profit: U256,
+    ) -> Result<Self> {
+        let mut steps = vec![];
+        for (pool, token_in, token_out, amount_out) in hops {
+            let kind = pool.executor_step().ok_or_else(|| {
+                eyre::eyre!(
+                    "The executor can't trade on {} pool {:?}",
+                    pool.exchange(),
+                    pool.address()
+                )
+            })?;
+            steps.push(RouteStep {
+                kind,
+                pool: pool.address(),
+                token_in: *token_in,
+                token_out: *token_out,
+                min_amount_out: *amount_out * (10000 - slippage_bps) / 10000,
+            });
+        }
+        Ok(Self { steps, min_profit })
+    }
+
+    /// The two swaps of an opportunity. `pools` has to include its buy and
+    /// sell pools.
+    pub fn from_opportunity(
+        opportunity: &ArbitrageOpportunity,
+        pools: &[&dyn PoolAdapter],
+        slippage_bps: u32,
+        min_profit: U256,
+    ) -> Result<Self> {
+        let find = |address: Address| {
+            pools
+                .iter()
+                .find(|pool| pool.address() == address)
+                .copied()
+                .ok_or_else(|| eyre::eyre!("Unknown pool {address:?}"))
+        };
+        let (token_in, token_out) = (opportunity.token_in.address, opportunity.token_out.address);
+        let hops = [
+            (
+                find(opportunity.buy_pool)?,
+                token_in,
+                token_out,
+                opportunity.amount_intermediate,
+            ),
+            (
+                find(opportunity.sell_pool)?,
+                token_out,
+                token_in,
+                opportunity.amount_out,
+            ),
+        ];
+        Self::new(&hops, slippage_bps, min_profit)
+    }
+
+    /// Every hop of a cycle, selling `amount_in` of its start token.
+    pub fn from_cycle(
+        cycle: &Cycle,
+        pools: &[&dyn PoolAdapter],
+        amount_in: U256,
+        slippage_bps: u32,
+        min_profit: U256,
+    ) -> Result<Self> {
+        let mut hops = vec![];
+        let mut amount = amount_in;
+        for hop in &cycle.hops {
+            let pool = pools[hop.pool];
+            amount = pool
+                .quote_exact_in(hop.token_in, hop.token_out, amount)
+                .ok_or_else(|| eyre::eyre!("Pool {:?} can't quote the cycle", pool.address()))?;
+            hops.push((pool, hop.token_in, hop.token_out, amount));
+        }
+        Self::new(&hops, slippage_bps, min_profit)
+    }
+
+    /// The token borrowed and repaid.
+    pub fn asset(&self) -> Option<Address> {
+        self.steps.first().map(|step| step.token_in)
+    }
+
+    /// The `params` `executeOperation` decodes as a `Route`.
+    pub fn encode(&self) -> Bytes {
+        let steps = self.steps.iter().cloned().map(RouteStep::into_token);
+        let route = Token::Tuple(vec![
+            Token::Array(steps.collect()),
+            Token::Uint(self.min_profit),
+        ]);
+        encode(&[route]).into()
+    }
+}
+
+/// Arbitrage between two V2 pairs without a lender: the executor borrows
+/// `amount_borrowed` of `token_borrowed` from `borrow_pair` with a flash
+/// swap, sells it on `sell_pair` inside the pair's `uniswapV2Call` and repays
+/// `borrow_pair` in `token_repaid`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashSwap {
+    pub borrow_pair: Address,
+    pub borrow_fee_bps: u32,
+    pub sell_pair: Address,
+    pub sell_fee_bps: u32,
+    pub token_borrowed: Address,
+    pub token_repaid: Address,
+    pub amount_borrowed: U256,
+    // The sale reverts if it buys less
+    pub min_amount_out: U256,
+    // Left over after repaying `borrow_pair`, or the transaction reverts
+    pub min_profit: U256,
+    // Left over at the pairs' current reserves; not sent to the contract
+    pub expected_profit: U256,
+}
+
+impl FlashSwap {
+    /// Whether buying on `buy` and selling on `sell` can be a flash swap,
+    /// which needs both to be V2 pairs.
+    pub fn supports(buy: &dyn PoolAdapter, sell: &dyn PoolAdapter) -> bool {
+        [buy, sell]
+            .iter()
+            .all(|pool| matches!(pool.executor_step(), Some(StepKind::UniswapV2 { .. })))
+    }
+
+    /// Flash swap version of an opportunity whose buy and sell pools are both
+    /// V2 pairs among `pools`: borrows what the buy pool would have sold.
+    pub fn from_opportunity(
+        opportunity: &ArbitrageOpportunity,
+        pools: &[&dyn PoolAdapter],
+        slippage_bps: u32,
+        min_profit: U256,
+    ) -> Result<Self> {
+        let pair = |address: Address| {
+            let pool = pools
+                .iter()
+                .find(|pool| pool.address() == address)
+                .ok_or_else(|| eyre::eyre!("Unknown pool {address:?}"))?;
+            match pool.executor_step() {
+                Some(StepKind::UniswapV2 { fee_bps }) => Ok((*pool, fee_bps)),
+                _ => Err(eyre::eyre!(
+                    "{} pool {address:?} can't flash swap",
+                    pool.exchange()
+                )),
+            }
+        };
+        let (borrow_pool, borrow_fee_bps) = pair(opportunity.buy_pool)?;
+        let (_, sell_fee_bps) = pair(opportunity.sell_pool)?;
+        let mut flash_swap = Self {
+            borrow_pair: opportunity.buy_pool,
+            borrow_fee_bps,
+            sell_pair: opportunity.sell_pool,
+            sell_fee_bps,
+            token_borrowed: opportunity.token_out.address,
+            token_repaid: opportunity.token_in.address,
+            amount_borrowed: opportunity.amount_intermediate,
+            min_amount_out: opportunity.amount_out * (10000 - slippage_bps) / 10000,
+            min_profit,
+            expected_profit: U256::zero(),
+        };
+        let repayment = flash_swap
+            .repayment(borrow_pool)
+            .ok_or_else(|| eyre::eyre!("Can't borrow {}", opportunity.amount_intermediate))?;
+        if flash_swap.min_amount_out < repayment + min_profit {
+            return Err(eyre::eyre!("Flash swap leaves less than {min_profit}"));
+        }
+        flash_swap.expected_profit = opportunity.amount_out - repayment;
+        Ok(flash_swap)
+    }
+
+    /// `token_repaid` owed to the borrow pair, whose current reserves are
+    /// `borrow_pool`'s: what a regular swap would take to buy the borrowed
+    /// amount.
+    pub fn repayment(&self, borrow_pool: &dyn PoolAdapter) -> Option<U256> {
+        borrow_pool.quote_exact_out(self.token_repaid, self.token_borrowed, self.amount_borrowed)
+    }
+
+    /// The `params` `executeFlashSwap` decodes as a `FlashSwap`.
+    pub fn encode(&self) -> Bytes {
+        let flash_swap = Token::Tuple(vec![
+            Token::Address(self.borrow_pair),
+            Token::Uint(self.borrow_fee_bps.into()),
+            Token::Address(self.sell_pair),
+            Token::Uint(self.sell_fee_bps.into()),
+            Token::Address(self.token_borrowed),
+            Token::Address(self.token_repaid),
+            Token::Uint(self.amount_borrowed),
+            Token::Uint(self.min_amount_out),
+            Token::Uint(self.min_profit),
+        ]);
+        encode(&[flash_swap]).into()
+    }
+}
+
+/// A deployed `FlashLoanArbitrage` contract. Its calls return the profit
+/// they leave, in the token borrowed or repaid.
+pub struct Executor<M> {
+    contract: IFlashLoanArbitrage<M>,
+    // The only account allowed to call it
+    owner: Address,
+}
+
+impl<M: Middleware + 'static> Executor<M> {
+    pub async fn new(address: Address, client: Arc<M>) -> Result<Self> {
+        let contract = IFlashLoanArbitrage::new(address, client);
+        let owner = contract.owner().call().await?;
+        Ok(Self { contract, owner })
+    }
+
+    pub fn address(&self) -> Address {
+        self.contract.address()
+    }
+
+    pub fn owner(&self) -> Address {
+        self.owner
+    }
+
+    /// Call that flash borrows `amount_in` of the route's first token from
+    /// `lender` and runs the route with it.
+    pub fn execute(
+        &self,
+        route: &Route,
+        amount_in: U256,
+        lender: Lender,
+    ) -> Result<ContractCall<M, U256>> {
+        let asset = route.asset().ok_or_else(|| eyre::eyre!("Empty route"))?;
+        let params = route.encode();
+        Ok(match lender {
+            Lender::AaveV2 => self.contract.execute_arbitrage(asset, amount_in, params),
+            Lender::Balancer => self
+                .contract
+                .execute_balancer_arbitrage(asset, amount_in, params),
+        })
+    }
+
+    /// Call that runs `flash_swap`.
+    pub fn flash_swap(&self, flash_swap: &FlashSwap) -> ContractCall<M, U256> {
+        self.contract.execute_flash_swap(flash_swap.encode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balancer::{BalancerPool, BalancerPoolKind};
+    use crate::uniswap_v2::UniswapV2Pool;
+    use ethers::abi::{decode, ParamType};
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    #[test]
+    fn test_encode_route() {
+        let eth = U256::exp10(18);
+        let v2 = UniswapV2Pool::with_state(
+            "Sushiswap",
+            address(10),
+            vec![address(1), address(2)],
+            vec![eth, eth],
+            30,
+        );
+        let balancer = BalancerPool::with_state(
+            address(11),
+            vec![address(1), address(2)],
+            vec![eth, eth],
+            BalancerPoolKind::Weighted {
+                weights: vec![eth / 2, eth / 2],
+            },
+            U256::zero(),
+            &[18, 18],
+            None,
+        );
+        let hops: [(&dyn PoolAdapter, Address, Address, U256); 2] = [
+            (&v2, address(1), address(2), U256::from(10_000)),
+            (&balancer, address(2), address(1), U256::from(20_000)),
+        ];
+        let route = Route::new(&hops, 50, U256::from(7)).unwrap();
+        assert_eq!(route.asset(), Some(address(1)));
+
+        let step = ParamType::Tuple(vec![
+            ParamType::Uint(8),
+            ParamType::Address,
+            ParamType::FixedBytes(32),
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Uint(16),
+            ParamType::Uint(256),
+        ]);
+        let route_type =
+            ParamType::Tuple(vec![ParamType::Array(Box::new(step)), ParamType::Uint(256)]);
+        let decoded = decode(&[route_type], &route.encode()).unwrap();
+        let Token::Tuple(fields) = &decoded[0] else {
+            panic!("not a tuple");
+        };
+        assert_eq!(fields[1], Token::Uint(U256::from(7)));
+        let Token::Array(steps) = &fields[0] else {
+            panic!("not an array");
+        };
+        assert_eq!(
+            steps[0],
+            Token::Tuple(vec![
+                Token::Uint(UNISWAP_V2.into()),
+                Token::Address(address(10)),
+                Token::FixedBytes(vec![0; 32]),
+                Token::Address(address(1)),
+                Token::Address(address(2)),
+                Token::Uint(30.into()),
+                Token::Uint(U256::from(9_950)),
+            ])
+        );
+        let Token::Tuple(balancer_step) = &steps[1] else {
+            panic!("not a tuple");
+        };
+        assert_eq!(balancer_step[0], Token::Uint(BALANCER.into()));
+        assert_eq!(balancer_step[6], Token::Uint(U256::from(19_900)));
+    }
+
+    #[test]
+    fn test_flash_swap_repays_less_than_sold() {
+        let (weth, link) = (address(1), address(2));
+        let eth = U256::exp10(18);
+        let pair = |exchange: &str, address: Address, reserves: (u64, u64)| {
+            UniswapV2Pool::with_state(
+                exchange,
+                address,
+                vec![weth, link],
+                vec![eth * reserves.0, eth * reserves.1],
+                30,
+            )
+        };
+        // LINK is cheaper on Sushiswap
+        let sushi = pair("Sushiswap", address(10), (200, 4_000));
+        let uni = pair("UniswapV2", address(11), (100, 1_800));
+        let pools: Vec<&dyn PoolAdapter> = vec![&sushi, &uni];
+        let token = |address: Address, symbol: &str| crate::utils::Token {
+            symbol: symbol.to_string(),
+            address,
+            decimals: 18,
+        };
+        let trade = crate::optimizer::best_trade(&pools, weth, link).unwrap();
+        let opportunity = crate::utils::check_arbitrage_opportunity(
+            (&token(weth, "WETH"), &token(link, "LINK")),
+            &trade,
+            Default::default(),
+            U256::zero(),
+            true,
+            1,
+        )
+        .unwrap();
+
+        assert!(FlashSwap::supports(&sushi, &uni));
+        let flash_swap =
+            FlashSwap::from_opportunity(&opportunity, &pools, 0, U256::zero()).unwrap();
+        assert_eq!(flash_swap.borrow_pair, address(10));
+        assert_eq!(flash_swap.token_borrowed, link);
+        // Buying back what one WETH bought costs at most one WETH
+        let repayment = flash_swap.repayment(&sushi).unwrap();
+        assert!(repayment <= opportunity.amount_in);
+        assert_eq!(
+            flash_swap.expected_profit,
+            opportunity.amount_out - repayment
+        );
+        assert!(flash_swap.expected_profit >= opportunity.gross_profit);
+
+        let fields = ParamType::Tuple(vec![
+            ParamType::Address,
+            ParamType::Uint(16),
+            ParamType::Address,
+            ParamType::Uint(16),
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+        ]);
+        let decoded = decode(&[fields], &flash_swap.encode()).unwrap();
+        let Token::Tuple(fields) = &decoded[0] else {
+            panic!("not a tuple");
+        };
+        assert_eq!(fields[6], Token::Uint(opportunity.amount_intermediate));
+
+        // Too little left to clear the minimum profit
+        let min_profit = opportunity.gross_profit * 2;
+        assert!(FlashSwap::from_opportunity(&opportunity, &pools, 0, min_profit).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::exchange::PoolState;
+use crate::report::print_warning;
+use crate::rpc::with_quorum;
+use crate::tokens::TokenRegistry;
+use ethers::{
+    contract::{Multicall, MulticallVersion},
+    core::types::{Address, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+use futures::future::try_join_all;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::sync::Arc;
+
+// Calls per aggregate, keeping each eth_call well inside node gas and
+// response size limits
+pub(crate) const MAX_CALLS_PER_BATCH: usize = 500;
+
+/// Refreshes the state of every tracked pool with one Multicall3 aggregate
+/// (a few when there are many pools), pinned to a single block.
+pub struct StateFetcher<M: Middleware> {
+    client: Arc<M>,
+    tokens: Arc<TokenRegistry>,
+    // Looked up once rather than by every Multicall::new
+    chain_id: U256,
+}
+
+impl<M: Middleware + 'static> StateFetcher<M> {
+    pub async fn new(client: Arc<M>, tokens: Arc<TokenRegistry>) -> Result<Self> {
+        let chain_id = client.get_chainid().await?;
+        Ok(Self {
+            client,
+            tokens,
+            chain_id,
+        })
+    }
+
+    /// Brings every pool to its state at `block_number`. Pools seen for the
+    /// first time, or whose shape changed, are loaded with `fetch_state`.
+    /// Returns the pools that failed to load or refresh, after warning about
+    /// each: they're left as they were and tried again next block.
+    pub async fn refresh(
+        &self,
+        pools: &mut [&mut Box<dyn PoolState<M>>],
+        block_number: u64,
+    ) -> Result<HashSet<Address>> {
+        let client = &self.client;
+        let mut failed = HashSet::new();
+        for pool in pools.iter_mut() {
+            if pool.tokens().is_empty() {
+                if let Err(error) = pool.fetch_state(client, &self.tokens, block_number).await {
+                    skip(&mut failed, pool.as_ref(), "load", error);
+                }
+            }
+        }
+
+        // Each batch lists its pools and how many calls each added; a pool's
+        // calls are never split across batches
+        let mut batches = vec![];
+        let mut multicall = self.batch(block_number)?;
+        let mut members = vec![];
+        let mut calls = 0;
+        for (index, pool) in pools.iter().enumerate() {
+            if failed.contains(&pool.address()) {
+                continue;
+            }
+            let count = pool.add_state_calls(client, &mut multicall)?;
+            members.push((index, count));
+            calls += count;
+            if calls >= MAX_CALLS_PER_BATCH {
+                batches.push((multicall, members));
+                multicall = self.batch(block_number)?;
+                members = vec![];
+                calls = 0;
+            }
+        }
+        if !members.is_empty() {
+            batches.push((multicall, members));
+        }
+
+        // The reserves every trade is priced from are worth a quorum
+        let results = with_quorum(try_join_all(
+            batches.iter().map(|(multicall, _)| multicall.call_raw()),
+        ))
+        .await?;
+        let mut reload = vec![];
+        for ((_, members), results) in batches.iter().zip(results) {
+            let mut offset = 0;
+            for (index, count) in members {
+                let results = results
+                    .get(offset..offset + count)
+                    .ok_or_else(|| eyre::eyre!("Multicall returned too few results"))?;
+                match pools[*index].apply_state(results) {
+                    Ok(true) => {}
+                    Ok(false) => reload.push(*index),
+                    Err(error) => skip(&mut failed, pools[*index].as_ref(), "refresh", error),
+                }
+                offset += count;
+            }
+        }
+        for index in reload {
+            let pool = &mut pools[index];
+            if let Err(error) = pool.fetch_state(client, &self.tokens, block_number).await {
+                skip(&mut failed, pool.as_ref(), "reload", error);
+            }
+        }
+        Ok(failed)
+    }
+
+    fn batch(&self, block_number: u64) -> Result<Multicall<M>> {
+        let multicall =
+            Multicall::new_with_chain_id(self.client.clone(), None, Some(self.chain_id))?
+                .version(MulticallVersion::Multicall3)
+                .block(block_number);
+        Ok(multicall)
+    }
+}
+
+// Warns about a pool that couldn't be brought up to date and marks it failed
+fn skip<M: Middleware>(
+    failed: &mut HashSet<Address>,
+    pool: &dyn PoolState<M>,
+    action: &str,
+    error: impl Display,
+) {
+    print_warning(format!(
+        "Skipping {} pool {:?}: failed to {action} its state: {error}",
+        pool.exchange(),
+        pool.address()
+    ));
+    failed.insert(pool.address());
+}
@@ -0,0 +1,320 @@
+use crate::balancer_math::mul_up;
+use crate::config::{
+    AAVE_V2_DATA_PROVIDER_ADDRESS, AAVE_V2_LENDING_POOL_ADDRESS, BALANCER_VAULT_ADDRESS,
+};
+use crate::contract_interfaces::{
+    IAaveLendingPool, IAaveProtocolDataProvider, IBalancerProtocolFeesCollector, IBalancerVault,
+    IERC20,
+};
+use crate::fetcher::MAX_CALLS_PER_BATCH;
+use crate::utils::decode;
+use async_trait::async_trait;
+use ethers::{
+    abi::Token as AbiToken,
+    contract::{Multicall, MulticallVersion},
+    core::types::{Address, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Where the executor contract borrows a route's first token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lender {
+    AaveV2,
+    Balancer,
+}
+
+impl fmt::Display for Lender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AaveV2 => write!(f, "Aave V2"),
+            Self::Balancer => write!(f, "Balancer"),
+        }
+    }
+}
+
+/// A flash loan provider as of its last refresh.
+pub trait FundingSource: Send + Sync {
+    fn lender(&self) -> Lender;
+
+    /// Fee for borrowing `amount` of `token`, None if the source can't lend
+    /// that much of it.
+    fn fee(&self, token: Address, amount: U256) -> Option<U256>;
+}
+
+/// Reloads what a `FundingSource` can lend and charges.
+#[async_trait]
+pub trait FundingState: FundingSource {
+    /// Fetches the fee and the amount of each of `tokens` available at
+    /// `block_number`.
+    async fn refresh(&mut self, tokens: &[Address], block_number: u64) -> Result<()>;
+}
+
+/// The source among `sources` lending `amount` of `token` for the lowest
+/// fee, with that fee. None if none of them can lend it.
+pub fn cheapest(
+    sources: &[&dyn FundingSource],
+    token: Address,
+    amount: U256,
+) -> Option<(Lender, U256)> {
+    sources
+        .iter()
+        .filter_map(|source| Some((source.lender(), source.fee(token, amount)?)))
+        .min_by_key(|(_, fee)| *fee)
+}
+
+/// Aave V2 flash loans, which charge a premium in bps of the amount and lend
+/// up to the balance each reserve's aToken holds.
+pub struct AaveV2<M> {
+    client: Arc<M>,
+    chain_id: U256,
+    lending_pool: IAaveLendingPool<M>,
+    data_provider: IAaveProtocolDataProvider<M>,
+    premium_bps: U256,
+    // Each token's aToken, None if Aave doesn't list the token
+    a_tokens: HashMap<Address, Option<Address>>,
+    liquidity: HashMap<Address, U256>,
+}
+
+impl<M: Middleware + 'static> AaveV2<M> {
+    pub async fn new(client: Arc<M>) -> Result<Self> {
+        let chain_id = client.get_chainid().await?;
+        Ok(Self {
+            lending_pool: IAaveLendingPool::new(
+                AAVE_V2_LENDING_POOL_ADDRESS.parse::<Address>()?,
+                client.clone(),
+            ),
+            data_provider: IAaveProtocolDataProvider::new(
+                AAVE_V2_DATA_PROVIDER_ADDRESS.parse::<Address>()?,
+                client.clone(),
+            ),
+            client,
+            chain_id,
+            premium_bps: U256::zero(),
+            a_tokens: HashMap::new(),
+            liquidity: HashMap::new(),
+        })
+    }
+}
+
+impl<M: Middleware + 'static> FundingSource for AaveV2<M> {
+    fn lender(&self) -> Lender {
+        Lender::AaveV2
+    }
+
+    fn fee(&self, token: Address, amount: U256) -> Option<U256> {
+        if amount > *self.liquidity.get(&token)? {
+            return None;
+        }
+        Some(amount.checked_mul(self.premium_bps)? / 10000)
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> FundingState for AaveV2<M> {
+    async fn refresh(&mut self, tokens: &[Address], block_number: u64) -> Result<()> {
+        self.premium_bps = self
+            .lending_pool
+            .flashloan_premium_total()
+            .block(block_number)
+            .call()
+            .await?;
+
+        // Reserves are listed rarely enough to look each token up once
+        let unknown: Vec<Address> = tokens
+            .iter()
+            .filter(|token| !self.a_tokens.contains_key(token))
+            .copied()
+            .collect();
+        let data_provider = &self.data_provider;
+        let results = batch_call(
+            &self.client,
+            self.chain_id,
+            block_number,
+            &unknown,
+            |multicall, token| {
+                multicall.add_call(data_provider.get_reserve_tokens_addresses(*token), true);
+            },
+        )
+        .await?;
+        for (token, result) in unknown.into_iter().zip(results) {
+            let a_token = decode::<(Address, Address, Address)>(result)
+                .map(|(a_token, _, _)| a_token)
+                .filter(|a_token| !a_token.is_zero());
+            self.a_tokens.insert(token, a_token);
+        }
+
+        let reserves: Vec<(Address, Address)> = tokens
+            .iter()
+            .filter_map(|token| Some((*token, (*self.a_tokens.get(token)?)?)))
+            .collect();
+        self.liquidity = balances(&self.client, self.chain_id, block_number, &reserves).await?;
+        Ok(())
+    }
+}
+
+/// Balancer vault flash loans, which lend anything the vault holds for the
+/// protocol's flash loan fee, zero at the time of writing.
+pub struct BalancerLender<M> {
+    client: Arc<M>,
+    chain_id: U256,
+    vault: IBalancerVault<M>,
+    // 18 decimal fraction of the amount
+    fee_percentage: U256,
+    liquidity: HashMap<Address, U256>,
+}
+
+impl<M: Middleware + 'static> BalancerLender<M> {
+    pub async fn new(client: Arc<M>) -> Result<Self> {
+        let chain_id = client.get_chainid().await?;
+        Ok(Self {
+            vault: IBalancerVault::new(BALANCER_VAULT_ADDRESS.parse::<Address>()?, client.clone()),
+            client,
+            chain_id,
+            fee_percentage: U256::zero(),
+            liquidity: HashMap::new(),
+        })
+    }
+}
+
+impl<M: Middleware + 'static> FundingSource for BalancerLender<M> {
+    fn lender(&self) -> Lender {
+        Lender::Balancer
+    }
+
+    fn fee(&self, token: Address, amount: U256) -> Option<U256> {
+        if amount > *self.liquidity.get(&token)? {
+            return None;
+        }
+        mul_up(amount, self.fee_percentage)
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> FundingState for BalancerLender<M> {
+    async fn refresh(&mut self, tokens: &[Address], block_number: u64) -> Result<()> {
+        let collector = self
+            .vault
+            .get_protocol_fees_collector()
+            .block(block_number)
+            .call()
+            .await?;
+        self.fee_percentage = IBalancerProtocolFeesCollector::new(collector, self.client.clone())
+            .get_flash_loan_fee_percentage()
+            .block(block_number)
+            .call()
+            .await?;
+
+        let vault = self.vault.address();
+        let holders: Vec<(Address, Address)> = tokens.iter().map(|token| (*token, vault)).collect();
+        self.liquidity = balances(&self.client, self.chain_id, block_number, &holders).await?;
+        Ok(())
+    }
+}
+
+// How much of each token its holder has, skipping tokens whose balanceOf
+// reverts
+async fn balances<M: Middleware + 'static>(
+    client: &Arc<M>,
+    chain_id: U256,
+    block_number: u64,
+    holders: &[(Address, Address)],
+) -> Result<HashMap<Address, U256>> {
+    let results = batch_call(
+        client,
+        chain_id,
+        block_number,
+        holders,
+        |multicall, (token, holder)| {
+            multicall.add_call(
+                IERC20::new(*token, client.clone()).balance_of(*holder),
+                true,
+            );
+        },
+    )
+    .await?;
+    Ok(holders
+        .iter()
+        .zip(results)
+        .filter_map(|((token, _), result)| Some((*token, decode::<U256>(result)?)))
+        .collect())
+}
+
+// Adds one call per item and returns the results in order, as many items per
+// aggregate as fit
+async fn batch_call<M: Middleware + 'static, T>(
+    client: &Arc<M>,
+    chain_id: U256,
+    block_number: u64,
+    items: &[T],
+    add_call: impl Fn(&mut Multicall<M>, &T),
+) -> Result<Vec<AbiToken>> {
+    let mut multicall = Multicall::new_with_chain_id(client.clone(), None, Some(chain_id))?
+        .version(MulticallVersion::Multicall3)
+        .block(block_number);
+    let mut results = vec![];
+    for chunk in items.chunks(MAX_CALLS_PER_BATCH) {
+        multicall.clear_calls();
+        for item in chunk {
+            add_call(&mut multicall, item);
+        }
+        let tokens = multicall.call_raw().await?;
+        if tokens.len() != chunk.len() {
+            return Err(eyre::eyre!("Multicall returned too few results"));
+        }
+        results.extend(tokens);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed {
+        lender: Lender,
+        fee_bps: u64,
+        liquidity: U256,
+    }
+
+    impl FundingSource for Fixed {
+        fn lender(&self) -> Lender {
+            self.lender
+        }
+
+        fn fee(&self, _token: Address, amount: U256) -> Option<U256> {
+            (amount <= self.liquidity).then(|| amount * self.fee_bps / 10000)
+        }
+    }
+
+    #[test]
+    fn test_cheapest_source_that_can_lend() {
+        let eth = U256::exp10(18);
+        let aave = Fixed {
+            lender: Lender::AaveV2,
+            fee_bps: 9,
+            liquidity: eth * 1000,
+        };
+        let balancer = Fixed {
+            lender: Lender::Balancer,
+            fee_bps: 0,
+            liquidity: eth * 10,
+        };
+        let sources: Vec<&dyn FundingSource> = vec![&aave, &balancer];
+        let token = Address::zero();
+
+        assert_eq!(
+            cheapest(&sources, token, eth),
+            Some((Lender::Balancer, U256::zero()))
+        );
+        // Only Aave holds enough, for its 0.09% premium
+        assert_eq!(
+            cheapest(&sources, token, eth * 100),
+            Some((Lender::AaveV2, eth * 9 / 100))
+        );
+        assert_eq!(cheapest(&sources, token, eth * 10_000), None);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::exchange::PoolAdapter;
+use crate::optimizer::reserve_of;
+use ethers::{
+    core::types::{Address, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+use std::sync::Arc;
+
+// Intrinsic transaction cost plus the executor's own bookkeeping
+const ROUTE_OVERHEAD_GAS: u64 = 60_000;
+// One swap, averaged over the venues we trade on; V2 swaps are cheaper,
+// V3 swaps crossing ticks dearer
+const SWAP_GAS: u64 = 100_000;
+// Borrowing and repaying a flash loan
+const FLASH_LOAN_GAS: u64 = 80_000;
+// The borrow pair's callback into the executor and the repayment transfer
+const FLASH_SWAP_GAS: u64 = 40_000;
+
+/// How a trade is executed, which decides the gas it burns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteKind {
+    /// Two swaps paid for from the bot's own balance.
+    TwoSwap,
+    /// Two swaps funded by a flash loan.
+    FlashLoan,
+    /// Two V2 swaps, the first lending the second what it sells, for free.
+    FlashSwap,
+    /// A cycle through `hops` pools, funded by a flash loan or not.
+    MultiHop { hops: usize, flash_loan: bool },
+}
+
+impl RouteKind {
+    pub fn gas_units(&self) -> u64 {
+        match self {
+            Self::TwoSwap => ROUTE_OVERHEAD_GAS + 2 * SWAP_GAS,
+            Self::FlashLoan => ROUTE_OVERHEAD_GAS + 2 * SWAP_GAS + FLASH_LOAN_GAS,
+            Self::FlashSwap => ROUTE_OVERHEAD_GAS + 2 * SWAP_GAS + FLASH_SWAP_GAS,
+            Self::MultiHop { hops, flash_loan } => {
+                let loan = if *flash_loan { FLASH_LOAN_GAS } else { 0 };
+                ROUTE_OVERHEAD_GAS + *hops as u64 * SWAP_GAS + loan
+            }
+        }
+    }
+}
+
+/// What a unit of gas costs in a block, in wei.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasPrice {
+    pub base_fee: U256,
+    pub priority_fee: U256,
+}
+
+impl GasPrice {
+    /// Base fee of `block_number` and the priority fee recent blocks paid.
+    pub async fn fetch<M: Middleware + 'static>(
+        client: &Arc<M>,
+        block_number: u64,
+    ) -> Result<Self> {
+        let base_fee = client
+            .get_block(block_number)
+            .await?
+            .ok_or_else(|| eyre::eyre!("Block {block_number} not found"))?
+            .base_fee_per_gas
+            .unwrap_or_default();
+        let (_, priority_fee) = client.estimate_eip1559_fees(None).await?;
+        Ok(Self {
+            base_fee,
+            priority_fee,
+        })
+    }
+
+    pub fn per_gas(&self) -> U256 {
+        self.base_fee + self.priority_fee
+    }
+
+    /// EIP-1559 fee cap: the priority fee on top of twice the base fee, so a
+    /// transaction stays includable through several full blocks.
+    pub fn max_fee_per_gas(&self) -> U256 {
+        self.base_fee * 2 + self.priority_fee
+    }
+
+    /// Cost of executing `route`, in wei.
+    pub fn route_cost(&self, route: RouteKind) -> U256 {
+        self.per_gas() * route.gas_units()
+    }
+}
+
+// The pool among `pools` holding the most WETH against `token`
+pub(crate) fn reference_pool<'a>(
+    token: Address,
+    weth: Address,
+    pools: &[&'a dyn PoolAdapter],
+) -> Option<&'a dyn PoolAdapter> {
+    pools
+        .iter()
+        .filter(|pool| pool.tokens().contains(&token))
+        .filter_map(|pool| Some((reserve_of(*pool, weth)?, *pool)))
+        .max_by_key(|(liquidity, _)| *liquidity)
+        .map(|(_, pool)| pool)
+}
+
+/// Converts `amount` wei into `token` at the spot price of the pool among
+/// `pools` holding the most WETH against it. None if no pool pairs the two.
+pub fn eth_to_token(
+    amount: U256,
+    token: Address,
+    weth: Address,
+    pools: &[&dyn PoolAdapter],
+) -> Option<U256> {
+    if token == weth {
+        return Some(amount);
+    }
+    let price = reference_pool(token, weth, pools)?.spot_price(weth, token)?;
+    amount
+        .checked_mul(price)
+        .map(|value| value / U256::exp10(18))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uniswap_v2::UniswapV2Pool;
+
+    #[test]
+    fn test_eth_to_token_uses_deepest_pool() {
+        let weth = Address::from_low_u64_be(1);
+        let usdc = Address::from_low_u64_be(2);
+        let eth = U256::exp10(18);
+        let pool = |reserves: (U256, U256)| {
+            UniswapV2Pool::with_state(
+                "UniswapV2",
+                Address::zero(),
+                vec![usdc, weth],
+                vec![reserves.0, reserves.1],
+                30,
+            )
+        };
+        // 2000 USDC per ETH in the deep pool, 1000 in the shallow one
+        let deep = pool((U256::from(2_000_000) * U256::exp10(6), eth * 1000));
+        let shallow = pool((U256::from(1_000) * U256::exp10(6), eth));
+        let pools: Vec<&dyn PoolAdapter> = vec![&shallow, &deep];
+
+        let gas = GasPrice {
+            base_fee: U256::from(20_000_000_000u64),
+            priority_fee: U256::from(1_000_000_000u64),
+        };
+        let cost = gas.route_cost(RouteKind::TwoSwap);
+        assert_eq!(cost, U256::from(21_000_000_000u64) * 260_000);
+        // Flash swaps skip the lender's overhead, which borrowed cycles pay
+        let cycle = |flash_loan| RouteKind::MultiHop {
+            hops: 3,
+            flash_loan,
+        };
+        assert_eq!(
+            cycle(true).gas_units() - cycle(false).gas_units(),
+            FLASH_LOAN_GAS
+        );
+        assert!(RouteKind::FlashSwap.gas_units() < RouteKind::FlashLoan.gas_units());
+
+        // 0.00546 ETH at 2000 USDC
+        let cost_usdc = eth_to_token(cost, usdc, weth, &pools).unwrap();
+        assert_eq!(cost_usdc, U256::from(10_920_000));
+        assert_eq!(eth_to_token(cost, weth, weth, &pools), Some(cost));
+        assert_eq!(eth_to_token(cost, Address::zero(), weth, &pools), None);
+    }
+}
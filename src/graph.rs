@@ -0,0 +1,225 @@
+use crate::exchange::PoolAdapter;
+use crate::optimizer::{reserve_of, search_amount_in};
+use ethers::core::types::{Address, I256, U256};
+
+// Shortest and longest cycles searched; two hop round trips are covered by
+// `optimizer::best_trade`
+const MIN_CYCLE_HOPS: usize = 3;
+const MAX_CYCLE_HOPS: usize = 4;
+
+/// A directed swap through one pool. `pool` indexes the pools the graph was
+/// built from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub pool: usize,
+    pub token_in: Address,
+    pub token_out: Address,
+    // -ln(rate after fees), so profitable cycles have negative total weight
+    weight: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cycle {
+    pub hops: Vec<Edge>,
+}
+
+impl Cycle {
+    /// Sum of the hops' log weights, negative when spot prices promise a
+    /// profit.
+    pub fn weight(&self) -> f64 {
+        self.hops.iter().map(|hop| hop.weight).sum()
+    }
+
+    pub fn start_token(&self) -> Address {
+        self.hops[0].token_in
+    }
+}
+
+/// Every token pair of every pool, as a directed graph of log prices.
+pub struct TokenGraph {
+    tokens: Vec<Address>,
+    // Outgoing edges of each entry in `tokens`
+    edges: Vec<Vec<Edge>>,
+}
+
+impl TokenGraph {
+    pub fn new(pools: &[&dyn PoolAdapter]) -> Self {
+        let mut graph = Self {
+            tokens: vec![],
+            edges: vec![],
+        };
+        for (index, pool) in pools.iter().enumerate() {
+            let precision = U256::exp10(18);
+            let fee = pool.fee().min(precision);
+            for token_in in pool.tokens() {
+                for token_out in pool.tokens() {
+                    if token_in == token_out {
+                        continue;
+                    }
+                    let Some(price) = pool.spot_price(*token_in, *token_out) else {
+                        continue;
+                    };
+                    let rate = to_f64(price) / 1e18 * (1.0 - to_f64(fee) / 1e18);
+                    if rate <= 0.0 || !rate.is_finite() {
+                        continue;
+                    }
+                    let from = graph.token_index(*token_in);
+                    graph.token_index(*token_out);
+                    graph.edges[from].push(Edge {
+                        pool: index,
+                        token_in: *token_in,
+                        token_out: *token_out,
+                        weight: -rate.ln(),
+                    });
+                }
+            }
+        }
+        graph
+    }
+
+    fn token_index(&mut self, token: Address) -> usize {
+        match self.tokens.iter().position(|t| *t == token) {
+            Some(index) => index,
+            None => {
+                self.tokens.push(token);
+                self.edges.push(vec![]);
+                self.tokens.len() - 1
+            }
+        }
+    }
+
+    /// Cycles of 3 to 4 hops through distinct tokens and pools whose log
+    /// weights sum below zero. Each cycle is reported once, starting from its
+    /// lowest indexed token.
+    pub fn negative_cycles(&self) -> Vec<Cycle> {
+        let mut cycles = vec![];
+        for start in 0..self.tokens.len() {
+            let mut path = vec![];
+            self.extend_cycles(start, start, &mut path, &mut cycles);
+        }
+        cycles
+    }
+
+    fn extend_cycles(
+        &self,
+        start: usize,
+        current: usize,
+        path: &mut Vec<Edge>,
+        cycles: &mut Vec<Cycle>,
+    ) {
+        for edge in &self.edges[current] {
+            if path.iter().any(|hop| hop.pool == edge.pool) {
+                continue;
+            }
+            let next = self.index_of(edge.token_out);
+            if next == start {
+                if path.len() + 1 >= MIN_CYCLE_HOPS {
+                    let mut hops = path.clone();
+                    hops.push(*edge);
+                    let cycle = Cycle { hops };
+                    if cycle.weight() < 0.0 {
+                        cycles.push(cycle);
+                    }
+                }
+                continue;
+            }
+            // Only visit tokens after the start so rotations aren't repeated
+            let visited = path.iter().any(|hop| hop.token_in == edge.token_out);
+            if next < start || visited || path.len() + 1 >= MAX_CYCLE_HOPS {
+                continue;
+            }
+            path.push(*edge);
+            self.extend_cycles(start, next, path, cycles);
+            path.pop();
+        }
+    }
+
+    fn index_of(&self, token: Address) -> usize {
+        self.tokens
+            .iter()
+            .position(|t| *t == token)
+            .expect("edges only point at known tokens")
+    }
+}
+
+// Output of routing `amount_in` through every hop of the cycle
+fn cycle_output(pools: &[&dyn PoolAdapter], cycle: &Cycle, amount_in: U256) -> Option<U256> {
+    cycle.hops.iter().try_fold(amount_in, |amount, hop| {
+        pools[hop.pool].quote_exact_in(hop.token_in, hop.token_out, amount)
+    })
+}
+
+/// Simulates a candidate cycle against exact pool math. Returns the profit
+/// maximizing input of the start token and the profit, or None if the log
+/// prices were too optimistic.
+pub fn simulate_cycle(pools: &[&dyn PoolAdapter], cycle: &Cycle) -> Option<(U256, U256)> {
+    let profit = |amount_in: U256| match cycle_output(pools, cycle, amount_in) {
+        Some(amount_out) => I256::from_raw(amount_out) - I256::from_raw(amount_in),
+        None => I256::MIN,
+    };
+    let first = cycle.hops.first()?;
+    let high = reserve_of(pools[first.pool], first.token_in)?;
+    let amount_in = search_amount_in(profit, high);
+    let profit = profit(amount_in);
+    if profit <= I256::zero() {
+        return None;
+    }
+    Some((amount_in, profit.into_raw()))
+}
+
+fn to_f64(value: U256) -> f64 {
+    value.to_string().parse::<f64>().unwrap_or(f64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uniswap_v2::UniswapV2Pool;
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    fn v2_pool(exchange: &str, tokens: (u64, u64), reserves: (u64, u64)) -> UniswapV2Pool {
+        let eth = U256::exp10(18);
+        UniswapV2Pool::with_state(
+            exchange,
+            Address::zero(),
+            vec![address(tokens.0), address(tokens.1)],
+            vec![eth * reserves.0, eth * reserves.1],
+            30,
+        )
+    }
+
+    #[test]
+    fn test_triangular_cycle() {
+        // 1 ETH buys 100 LINK buys 2000 USDC buys 1.05 ETH
+        let eth_link = v2_pool("Sushiswap", (1, 2), (1000, 100_000));
+        let link_usdc = v2_pool("Balancer", (2, 3), (100_000, 2_000_000));
+        let usdc_eth = v2_pool("UniswapV2", (3, 1), (1_900_000, 1000));
+        let pools: Vec<&dyn PoolAdapter> = vec![&eth_link, &link_usdc, &usdc_eth];
+
+        let cycles = TokenGraph::new(&pools).negative_cycles();
+        assert_eq!(cycles.len(), 1);
+        let cycle = &cycles[0];
+        assert_eq!(cycle.start_token(), address(1));
+        let route: Vec<usize> = cycle.hops.iter().map(|hop| hop.pool).collect();
+        assert_eq!(route, vec![0, 1, 2]);
+
+        let (amount_in, profit) = simulate_cycle(&pools, cycle).unwrap();
+        assert!(amount_in > U256::zero());
+        assert_eq!(
+            cycle_output(&pools, cycle, amount_in).unwrap() - amount_in,
+            profit
+        );
+    }
+
+    #[test]
+    fn test_no_cycle_when_prices_agree() {
+        let eth_link = v2_pool("Sushiswap", (1, 2), (1000, 100_000));
+        let link_usdc = v2_pool("Balancer", (2, 3), (100_000, 2_000_000));
+        let usdc_eth = v2_pool("UniswapV2", (3, 1), (2_000_000, 1000));
+        let pools: Vec<&dyn PoolAdapter> = vec![&eth_link, &link_usdc, &usdc_eth];
+        assert!(TokenGraph::new(&pools).negative_cycles().is_empty());
+    }
+}
@@ -1,6 +1,27 @@
 pub mod balancer;
+pub mod balancer_math;
 pub mod client;
 pub mod config;
 pub mod contract_interfaces;
+pub mod curve;
+pub mod curve_math;
+pub mod discovery;
+pub mod events;
+pub mod exchange;
+pub mod executor;
+pub mod fetcher;
+pub mod funding;
+pub mod gas;
+pub mod graph;
+pub mod numeraire;
+pub mod optimizer;
+pub mod price;
+pub mod report;
+pub mod rpc;
+pub mod simulation;
+pub mod submitter;
+pub mod tokens;
 pub mod uniswap_v2;
+pub mod uniswap_v3;
 pub mod utils;
+pub mod wallet;
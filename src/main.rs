@@ -1,75 +1,605 @@
-use arbitrage_bot::client::ArbClient;
-use arbitrage_bot::utils::{check_arbitrage_opportunity, create_trade_amount_range, get_reserves};
-use ethers::core::types::U256;
+use arbitrage_bot::client::{ArbClient, BlockSource, CommonPairs, Transport};
+use arbitrage_bot::config::{
+    CHAINLINK_ETH_USD_ADDRESS, MAINNET_USDC_ADDRESS, MAINNET_WETH_ADDRESS,
+};
+use arbitrage_bot::discovery::{mainnet_sources, Discovery};
+use arbitrage_bot::events::EventTracker;
+use arbitrage_bot::exchange::{ExchangeRegistry, PoolAdapter, PoolState};
+use arbitrage_bot::executor::{Executor, FlashSwap, Route};
+use arbitrage_bot::fetcher::StateFetcher;
+use arbitrage_bot::funding::{cheapest, AaveV2, BalancerLender, FundingSource, FundingState};
+use arbitrage_bot::gas::{eth_to_token, GasPrice, RouteKind};
+use arbitrage_bot::graph::{simulate_cycle, TokenGraph};
+use arbitrage_bot::numeraire::{Numeraire, UsdSource, Valuation};
+use arbitrage_bot::optimizer::{best_trade, Trade};
+use arbitrage_bot::report::{
+    describe_cycle, describe_opportunity, print_calldata, print_cycle, print_discarded,
+    print_discovered, print_divergence, print_mismatch, print_opportunity, print_quotes,
+    print_ranking, print_retry, print_submitted, print_summary, print_tx_status, print_warning,
+};
+use arbitrage_bot::rpc::{connect_failover, connect_quorum};
+use arbitrage_bot::simulation::{simulate, Simulation};
+use arbitrage_bot::submitter::Submitter;
+use arbitrage_bot::tokens::TokenRegistry;
+use arbitrage_bot::utils::{
+    check_arbitrage_opportunity, get_reserves, ArbitrageOpportunity, TradeCosts,
+};
+use arbitrage_bot::wallet::KeySource;
+use ethers::{
+    contract::builders::ContractCall,
+    core::types::{transaction::eip2718::TypedTransaction, Address, U256},
+    providers::{Http, Middleware, Provider, StreamExt, Ws},
+    utils::parse_ether,
+};
 use eyre::Result;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Retry delays after RPC errors in watch mode
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// Per request limit before failing over to the next endpoint
+const RPC_TIMEOUT: Duration = Duration::from_secs(10);
+// WETH a discovered pool must hold, unless given after `discover`
+const MIN_DISCOVERY_LIQUIDITY: &str = "10";
+// Net profit in ETH an opportunity must beat, unless MIN_PROFIT_ETH is set
+const MIN_PROFIT: &str = "0";
+// How much less than quoted each executor swap may buy
+const EXECUTOR_SLIPPAGE_BPS: u32 = 50;
+// How far a simulated profit may be from the predicted one before the
+// opportunity is flagged rather than executed
+const SIMULATION_TOLERANCE_BPS: u32 = 100;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let exchanges = vec!["Sushiswap", "UniswapV2", "Pancakeswap", "Balancer"];
-    let exchanges_vec: Vec<String> = exchanges.iter().map(|s| String::from(*s)).collect();
+    match Transport::from_env()? {
+        Transport::Http(url) => run(Arc::new(Provider::<Http>::try_from(url)?)).await?,
+        Transport::Ws(url) => run(Arc::new(Provider::<Ws>::connect(url).await?)).await?,
+        Transport::Ipc(path) => run(Arc::new(Provider::connect_ipc(path).await?)).await?,
+        Transport::Failover(urls) => {
+            run(Arc::new(connect_failover(&urls, RPC_TIMEOUT).await?)).await?
+        }
+        Transport::Quorum(urls, quorum) => {
+            run(Arc::new(connect_quorum(&urls, quorum, RPC_TIMEOUT).await?)).await?
+        }
+    }
+    Ok(())
+}
+
+async fn run<M: BlockSource + 'static>(client: Arc<M>) -> Result<()> {
     let network = 1;
-    let arb_client = ArbClient::new(network, exchanges_vec).await?;
-    let common_pairs = arb_client.get_common_pairs(&exchanges)?;
-    let mut arb_opportunities: Vec<String> = vec![];
-
-    let alert_threshold = U256::from(10); // pbs to alert
-    for (symbol_pair, exchange_addresses) in common_pairs {
-        let (symbol_a, symbol_b) = symbol_pair;
-
-        let mut prices_and_reserves_left = vec![];
-        let mut prices_and_reserves_right = vec![];
-        let mut decimals_a = 0;
-        let mut decimals_b = 0;
-
-        for (exchange, address) in exchange_addresses {
-            let (left, right, reserve_a, reserve_b, token_a_decimal, token_b_decimal) =
-                get_reserves(&arb_client.client, address, exchange.as_str()).await?;
-            decimals_a = token_a_decimal;
-            decimals_b = token_b_decimal;
-            prices_and_reserves_left.push((exchange.clone(), left, reserve_a, reserve_b));
-            prices_and_reserves_right.push((exchange.clone(), right, reserve_a, reserve_b));
+    let arb_client = ArbClient::new(client, network, ExchangeRegistry::default());
+    let tokens = Arc::new(TokenRegistry::load(format!(
+        "./src/data/{network}/tokens.json"
+    ))?);
+    if std::env::args().nth(1).as_deref() == Some("discover") {
+        let min_liquidity = std::env::args()
+            .nth(2)
+            .unwrap_or_else(|| MIN_DISCOVERY_LIQUIDITY.to_string());
+        return discover(&arb_client, tokens, parse_ether(min_liquidity)?).await;
+    }
+    if std::env::args().nth(1).as_deref() == Some("validate") {
+        for mismatch in arb_client.validate_pairs(&tokens).await? {
+            print_mismatch(&mismatch);
         }
+        return Ok(());
+    }
+    // ETH is priced in USD by Chainlink, or the USDC pools if it fails
+    let mut usd_sources = vec![UsdSource::Chainlink(CHAINLINK_ETH_USD_ADDRESS.parse()?)];
+    usd_sources.extend(
+        tokens
+            .get(MAINNET_USDC_ADDRESS.parse()?)
+            .map(UsdSource::Pool),
+    );
+    // Pools are loaded once, then grouped by the pairs they trade
+    let mut all_pools = arb_client.get_all_pools()?;
+    let fetcher = StateFetcher::new(arb_client.client.clone(), tokens.clone()).await?;
+    let block_number = arb_client.client.get_block_number().await?.as_u64();
+    let mut pools: Vec<&mut Box<dyn PoolState<_>>> = all_pools.iter_mut().collect();
+    fetcher.refresh(&mut pools, block_number).await?;
+    let common_pairs = arb_client.get_common_pairs(&all_pools, &tokens).await?;
+    let mut scanner = Scanner {
+        common_pairs,
+        all_pools,
+        fetcher,
+        events: EventTracker::new(),
+        tokens,
+        weth: MAINNET_WETH_ADDRESS.parse()?,
+        min_profit: parse_ether(
+            std::env::var("MIN_PROFIT_ETH").unwrap_or_else(|_| MIN_PROFIT.to_string()),
+        )?,
+        numeraire: match std::env::var("NUMERAIRE") {
+            Ok(numeraire) => numeraire.parse()?,
+            Err(_) => Numeraire::default(),
+        },
+        usd_sources,
+        funding: vec![
+            Box::new(AaveV2::new(arb_client.client.clone()).await?),
+            Box::new(BalancerLender::new(arb_client.client.clone()).await?),
+        ],
+        executor: match std::env::var("EXECUTOR_ADDRESS") {
+            Ok(address) => Some(Executor::new(address.parse()?, arb_client.client.clone()).await?),
+            Err(_) => None,
+        },
+        submitter: match KeySource::from_env()? {
+            Some(key) => {
+                let chain_id = arb_client.client.get_chainid().await?.as_u64();
+                let wallet = key.wallet(chain_id)?;
+                Some(Submitter::new(arb_client.client.clone(), wallet))
+            }
+            None => None,
+        },
+        arb_client,
+    };
+    // Executor calls are owner only, so any other signer would fail every gas
+    // estimate
+    if let (Some(executor), Some(submitter)) = (&scanner.executor, &scanner.submitter) {
+        if submitter.address() != executor.owner() {
+            return Err(eyre::eyre!(
+                "Signer {:?} doesn't own executor {:?}, {:?} does",
+                submitter.address(),
+                executor.address(),
+                executor.owner()
+            ));
+        }
+    }
 
-        // Determine the trade amount range for token A and token B
-        let trade_amount_range_a = create_trade_amount_range(decimals_a);
-        let trade_amount_range_b = create_trade_amount_range(decimals_b);
-
-        let mut trade_amount_a = trade_amount_range_a.0;
-        let mut trade_amount_b = trade_amount_range_b.0;
-        let mut arbs = 0;
-
-        while trade_amount_a <= trade_amount_range_a.1 && trade_amount_b <= trade_amount_range_b.1 {
-            if check_arbitrage_opportunity(
-                (&symbol_a, &symbol_b),
-                &prices_and_reserves_left[..],
-                alert_threshold,
-                trade_amount_a.clone(),
-                true,
-                decimals_a,
-                decimals_b,
-            ) {
-                arbs += 1
+    match std::env::args().nth(1).as_deref() {
+        Some("watch") => watch(&mut scanner).await?,
+        _ => {
+            let block_number = scanner.arb_client.client.get_block_number().await?.as_u64();
+            scanner.scan(block_number).await?;
+        }
+    }
+    Ok(())
+}
+
+// Regenerates the config files of every exchange that lists its pools on
+// chain, keeping pools with at least `min_liquidity` WETH
+async fn discover<M: Middleware + 'static>(
+    arb_client: &ArbClient<M>,
+    tokens: Arc<TokenRegistry>,
+    min_liquidity: U256,
+) -> Result<()> {
+    let discovery = Discovery::new(arb_client.client.clone(), tokens, min_liquidity).await?;
+    for (exchange, source) in mainnet_sources()? {
+        let pairs = discovery.discover(source).await?;
+        arb_client.write_pairs(exchange, &pairs)?;
+        print_discovered(exchange, pairs.len());
+    }
+    Ok(())
+}
+
+// The executor call running `opportunity` and the profit it should leave in
+// the contract. The trade has to at least pay for its gas. Two V2 pairs lend
+// to each other, otherwise the lender it was costed with funds the route
+fn opportunity_call<M: Middleware + 'static>(
+    executor: &Executor<M>,
+    opportunity: &ArbitrageOpportunity,
+    pools: &[&dyn PoolAdapter],
+) -> Result<(ContractCall<M, U256>, U256)> {
+    // Only trades costed as flash swaps go without a lender
+    let Some(lender) = opportunity.lender else {
+        let flash_swap = FlashSwap::from_opportunity(
+            opportunity,
+            pools,
+            EXECUTOR_SLIPPAGE_BPS,
+            opportunity.gas_cost,
+        )?;
+        return Ok((executor.flash_swap(&flash_swap), flash_swap.expected_profit));
+    };
+    let route = Route::from_opportunity(
+        opportunity,
+        pools,
+        EXECUTOR_SLIPPAGE_BPS,
+        opportunity.gas_cost,
+    )?;
+    let call = executor.execute(&route, opportunity.amount_in, lender)?;
+    Ok((call, opportunity.gross_profit - opportunity.loan_fee))
+}
+
+// Rescans on every new block until stopped, backing off while the RPC fails
+async fn watch<M: BlockSource + 'static>(scanner: &mut Scanner<M>) -> Result<()> {
+    let client = scanner.arb_client.client.clone();
+    let mut backoff = MIN_BACKOFF;
+    let mut last_block = 0;
+    loop {
+        let mut blocks = match client.new_blocks().await {
+            Ok(blocks) => blocks,
+            Err(error) => {
+                print_retry(format!("Failed to watch blocks: {error}"), backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
             }
-            if check_arbitrage_opportunity(
-                (&symbol_a, &symbol_b),
-                &prices_and_reserves_right[..],
-                alert_threshold,
-                trade_amount_b.clone(),
-                false,
-                decimals_a,
-                decimals_b,
-            ) {
-                arbs += 1
+        };
+
+        while let Some(block_number) = blocks.next().await {
+            // Blocks that arrived while the last scan ran are already stale
+            if block_number <= last_block {
+                continue;
             }
+            let result = scanner.scan(block_number).await;
 
-            trade_amount_a += trade_amount_range_a.2;
-            trade_amount_b += trade_amount_range_b.2;
+            match result {
+                Ok(()) => {
+                    last_block = block_number;
+                    backoff = MIN_BACKOFF;
+                }
+                Err(error) => {
+                    print_retry(format!("Scan failed: {error}"), backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
         }
-        arb_opportunities.push(format!(
-            "Arbitrage opportunities for {}-{} pool: {}",
-            symbol_a, symbol_b, arbs
-        ));
+        print_warning("Block stream ended, resubscribing");
+    }
+}
+
+// Pools and what's known about them, kept between scans
+struct Scanner<M: Middleware> {
+    arb_client: ArbClient<M>,
+    common_pairs: CommonPairs,
+    all_pools: Vec<Box<dyn PoolState<M>>>,
+    fetcher: StateFetcher<M>,
+    events: EventTracker,
+    tokens: Arc<TokenRegistry>,
+    // Gas is paid in ETH and priced through WETH pools
+    weth: Address,
+    // Net profit in wei an opportunity has to beat
+    min_profit: U256,
+    // What profits are ranked in, set by NUMERAIRE
+    numeraire: Numeraire,
+    usd_sources: Vec<UsdSource>,
+    // Flash loan providers the executor can borrow from
+    funding: Vec<Box<dyn FundingState>>,
+    // The deployed FlashLoanArbitrage contract, set by EXECUTOR_ADDRESS
+    executor: Option<Executor<M>>,
+    // Sends executor calls instead of printing them, set by PRIVATE_KEY or
+    // KEYSTORE_PATH
+    submitter: Option<Submitter<M>>,
+}
+
+impl<M: Middleware + 'static> Scanner<M> {
+    // Who executor calls are simulated from: the signer sending them, or
+    // else the owner they're printed for
+    fn sender(&self, executor: &Executor<M>) -> Address {
+        self.submitter
+            .as_ref()
+            .map_or(executor.owner(), |submitter| submitter.address())
+    }
+
+    // Submits a confirmed executor call through the signer, or prints its
+    // calldata when there is none
+    async fn send(
+        &self,
+        tx: &TypedTransaction,
+        gas_price: &GasPrice,
+        block_number: u64,
+        what: &str,
+    ) {
+        match (&self.submitter, &self.executor) {
+            (Some(submitter), _) => match submitter.submit(tx, gas_price, block_number).await {
+                Ok(hash) => print_submitted(hash, submitter.address()),
+                Err(error) => print_warning(format!("Can't submit {what}: {error}")),
+            },
+            (None, Some(executor)) => print_calldata(executor.address(), tx),
+            (None, None) => {}
+        }
+    }
+
+    // Refreshes every pool and reports the opportunities at `block_number`
+    async fn scan(&mut self, block_number: u64) -> Result<()> {
+        let client = &self.arb_client.client;
+        let mut pools: Vec<&mut Box<dyn PoolState<M>>> = self.all_pools.iter_mut().collect();
+        // Pools that couldn't be brought to this block sit it out
+        let failed = if self.events.sync(client, &mut pools, block_number).await? {
+            // Pools followed through their logs are already up to date
+            let mut polled: Vec<&mut Box<dyn PoolState<M>>> = pools
+                .into_iter()
+                .filter(|pool| pool.event_source().is_none())
+                .collect();
+            self.fetcher.refresh(&mut polled, block_number).await?
+        } else {
+            let failed = self.fetcher.refresh(&mut pools, block_number).await?;
+            self.events.seed(client, block_number).await?;
+            failed
+        };
+
+        let gas_price = GasPrice::fetch(client, block_number).await?;
+        if let Some(submitter) = &self.submitter {
+            for (hash, status) in submitter.poll(&gas_price, block_number).await? {
+                print_tx_status(hash, status);
+            }
+        }
+        let current = |pool: &dyn PoolAdapter| !failed.contains(&pool.address());
+        let all_pools: Vec<&dyn PoolAdapter> = self
+            .all_pools
+            .iter()
+            .map(|pool| pool.as_ref() as &dyn PoolAdapter)
+            .filter(|pool| current(*pool))
+            .collect();
+        let mut loan_tokens: Vec<Address> = all_pools
+            .iter()
+            .flat_map(|pool| pool.tokens().to_vec())
+            .collect();
+        loan_tokens.sort();
+        loan_tokens.dedup();
+        for source in self.funding.iter_mut() {
+            source.refresh(&loan_tokens, block_number).await?;
+        }
+        let funding: Vec<&dyn FundingSource> = self
+            .funding
+            .iter()
+            .map(|source| source.as_ref() as &dyn FundingSource)
+            .collect();
+        // Gas, flash loan fee and minimum profit of selling `amount` of
+        // `token` through a route, in that token. Flash swaps borrow from
+        // their own pair for free. Other two swap trades borrow from the
+        // cheapest lender when one can lend, or are paid for from the bot's
+        // own balance. None if no pool prices the token in ETH
+        let costs = |route: RouteKind, token: Address, amount: U256| {
+            let loan = match route {
+                RouteKind::FlashSwap => None,
+                _ => cheapest(&funding, token, amount),
+            };
+            let route = match (route, loan) {
+                (RouteKind::TwoSwap, Some(_)) => RouteKind::FlashLoan,
+                (RouteKind::MultiHop { hops, .. }, Some(_)) => RouteKind::MultiHop {
+                    hops,
+                    flash_loan: true,
+                },
+                _ => route,
+            };
+            let gas_cost = gas_price.route_cost(route);
+            let gas_cost = eth_to_token(gas_cost, token, self.weth, &all_pools);
+            let min_profit = eth_to_token(self.min_profit, token, self.weth, &all_pools);
+            if gas_cost.is_none() {
+                print_warning(format!(
+                    "No WETH pool prices gas in {}",
+                    self.tokens.symbol(token)
+                ));
+            }
+            let costs = TradeCosts {
+                gas_cost: gas_cost?,
+                loan_fee: loan.map(|(_, fee)| fee).unwrap_or_default(),
+                lender: loan.map(|(lender, _)| lender),
+            };
+            Some((costs, min_profit?))
+        };
+        let valuation = Valuation::fetch(
+            client,
+            self.numeraire,
+            self.weth,
+            &self.usd_sources,
+            &all_pools,
+            block_number,
+        )
+        .await?;
+        // Every opportunity's net profit in the numeraire
+        let mut ranked = vec![];
+
+        let mut arb_opportunities = vec![];
+
+        for ((address_a, address_b), pools) in self.common_pairs.iter() {
+            // Resolved when the pools were loaded
+            let (Some(token_a), Some(token_b)) =
+                (self.tokens.get(*address_a), self.tokens.get(*address_b))
+            else {
+                continue;
+            };
+            // Empty or unpriceable pools are left out of this block's trades
+            let mut priced: Vec<&dyn PoolAdapter> = vec![];
+            let mut prices_left = vec![];
+            let mut prices_right = vec![];
+            let pools = pools
+                .iter()
+                .map(|index| self.all_pools[*index].as_ref() as &dyn PoolAdapter)
+                .filter(|pool| current(*pool));
+            for pool in pools {
+                match get_reserves(pool, &token_a, &token_b) {
+                    Ok((left, right, _, _)) => {
+                        priced.push(pool);
+                        prices_left.push(left);
+                        prices_right.push(right);
+                    }
+                    Err(error) => print_warning(format!("Skipping pool: {error}")),
+                }
+            }
+            let pools = priced;
+            let prices_and_pools_left: Vec<(U256, &dyn PoolAdapter)> =
+                prices_left.into_iter().zip(pools.iter().copied()).collect();
+            let prices_and_pools_right: Vec<(U256, &dyn PoolAdapter)> = prices_right
+                .into_iter()
+                .zip(pools.iter().copied())
+                .collect();
+
+            let mut opportunities = vec![];
+            // Executed the way `opportunity_call` will
+            let two_swap = |trade: &Trade| {
+                if FlashSwap::supports(trade.buy, trade.sell) {
+                    RouteKind::FlashSwap
+                } else {
+                    RouteKind::TwoSwap
+                }
+            };
+
+            // Size each direction at its most profitable amount
+            if let Some(trade) = best_trade(&pools, token_a.address, token_b.address) {
+                let trade_amount_a = trade.amount_in;
+                print_quotes(
+                    (&token_a, &token_b),
+                    &prices_and_pools_left,
+                    trade_amount_a,
+                    true,
+                );
+                if let Some((costs, min_profit)) =
+                    costs(two_swap(&trade), token_a.address, trade_amount_a)
+                {
+                    opportunities.extend(check_arbitrage_opportunity(
+                        (&token_a, &token_b),
+                        &trade,
+                        costs,
+                        min_profit,
+                        true,
+                        block_number,
+                    ));
+                }
+            }
+            if let Some(trade) = best_trade(&pools, token_b.address, token_a.address) {
+                let trade_amount_b = trade.amount_in;
+                print_quotes(
+                    (&token_a, &token_b),
+                    &prices_and_pools_right,
+                    trade_amount_b,
+                    false,
+                );
+                if let Some((costs, min_profit)) =
+                    costs(two_swap(&trade), token_b.address, trade_amount_b)
+                {
+                    opportunities.extend(check_arbitrage_opportunity(
+                        (&token_a, &token_b),
+                        &trade,
+                        costs,
+                        min_profit,
+                        false,
+                        block_number,
+                    ));
+                }
+            }
+            // Reverted simulations aren't reported
+            let mut reported = vec![];
+            for opportunity in opportunities {
+                let mut simulation = None;
+                let mut call = None;
+                if let Some(executor) = &self.executor {
+                    match opportunity_call(executor, &opportunity, &all_pools) {
+                        Ok((executor_call, predicted)) => {
+                            // A failed eth_call leaves the opportunity
+                            // unsimulated, and so unexecuted, rather than
+                            // ending the scan
+                            simulation = simulate(
+                                client.as_ref(),
+                                &executor_call.tx,
+                                self.sender(executor),
+                                block_number,
+                                predicted,
+                                SIMULATION_TOLERANCE_BPS,
+                            )
+                            .await
+                            .inspect_err(|error| {
+                                print_warning(format!("Can't simulate opportunity: {error}"))
+                            })
+                            .ok();
+                            call = Some(executor_call);
+                        }
+                        Err(error) => print_warning(format!("Can't execute opportunity: {error}")),
+                    }
+                }
+                if let Some(Simulation::Reverted(reason)) = &simulation {
+                    print_discarded(&describe_opportunity(&opportunity), reason);
+                    continue;
+                }
+                print_opportunity(&opportunity);
+                if let Some(Simulation::Diverged { profit, predicted }) = simulation {
+                    print_divergence(profit, predicted, Some(&opportunity.token_in));
+                }
+                // Only what the chain agrees with is executed
+                if let (Some(call), Some(Simulation::Confirmed { .. })) = (&call, &simulation) {
+                    self.send(&call.tx, &gas_price, block_number, "opportunity")
+                        .await;
+                }
+                let token = opportunity.token_in.address;
+                if let Some(value) = valuation.value(token, opportunity.net_profit, &all_pools) {
+                    ranked.push((value, describe_opportunity(&opportunity)));
+                }
+                reported.push(opportunity);
+            }
+            arb_opportunities.push(((token_a, token_b), reported));
+        }
+        for ((token_a, token_b), opportunities) in &arb_opportunities {
+            print_summary((&token_a.symbol, &token_b.symbol), opportunities);
+        }
+
+        // Triangular and longer cycles across every configured pool
+        for cycle in TokenGraph::new(&all_pools).negative_cycles() {
+            let Some((amount_in, profit)) = simulate_cycle(&all_pools, &cycle) else {
+                continue;
+            };
+            let route = RouteKind::MultiHop {
+                hops: cycle.hops.len(),
+                flash_loan: false,
+            };
+            let Some((costs, min_profit)) = costs(route, cycle.start_token(), amount_in) else {
+                continue;
+            };
+            let Some(net_profit) = costs
+                .total()
+                .and_then(|total| profit.checked_sub(total))
+                .filter(|net| *net > min_profit)
+            else {
+                continue;
+            };
+            // Cycles are only simulated, and so executed, when a lender can
+            // fund them
+            let mut simulation = None;
+            let mut call = None;
+            if let (Some(executor), Some(lender)) = (&self.executor, costs.lender) {
+                let call_result = Route::from_cycle(
+                    &cycle,
+                    &all_pools,
+                    amount_in,
+                    EXECUTOR_SLIPPAGE_BPS,
+                    costs.gas_cost,
+                )
+                .and_then(|route| executor.execute(&route, amount_in, lender));
+                match call_result {
+                    Ok(executor_call) => {
+                        simulation = simulate(
+                            client.as_ref(),
+                            &executor_call.tx,
+                            self.sender(executor),
+                            block_number,
+                            profit - costs.loan_fee,
+                            SIMULATION_TOLERANCE_BPS,
+                        )
+                        .await
+                        .inspect_err(|error| {
+                            print_warning(format!("Can't simulate cycle: {error}"))
+                        })
+                        .ok();
+                        call = Some(executor_call);
+                    }
+                    Err(error) => print_warning(format!("Can't execute cycle: {error}")),
+                }
+            }
+            if let Some(Simulation::Reverted(reason)) = &simulation {
+                let route = describe_cycle(&self.tokens, &all_pools, &cycle);
+                print_discarded(&route, reason);
+                continue;
+            }
+            print_cycle(
+                &self.tokens,
+                &all_pools,
+                &cycle,
+                amount_in,
+                profit,
+                &costs,
+                block_number,
+            );
+            if let Some(Simulation::Diverged { profit, predicted }) = simulation {
+                let token = self.tokens.get(cycle.start_token());
+                print_divergence(profit, predicted, token.as_ref());
+            }
+            if let (Some(call), Some(Simulation::Confirmed { .. })) = (&call, &simulation) {
+                self.send(&call.tx, &gas_price, block_number, "cycle").await;
+            }
+            if let Some(value) = valuation.value(cycle.start_token(), net_profit, &all_pools) {
+                ranked.push((value, describe_cycle(&self.tokens, &all_pools, &cycle)));
+            }
+        }
+        print_ranking(&mut ranked, self.numeraire);
+        Ok(())
     }
-    arb_opportunities.iter().for_each(|s| println!("{}", s));
-    Ok(())
 }
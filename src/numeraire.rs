@@ -0,0 +1,229 @@
+use crate::contract_interfaces::IChainlinkAggregator;
+use crate::exchange::PoolAdapter;
+use crate::gas::{eth_to_token, reference_pool};
+use crate::report::print_warning;
+use crate::utils::Token;
+use ethers::{
+    core::types::{Address, I256, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Twice the ETH/USD feed's one hour heartbeat; an older answer means the feed
+// stopped updating
+const MAX_ANSWER_AGE: u64 = 2 * 3600;
+
+/// The currency profits in different tokens are compared in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Numeraire {
+    #[default]
+    Eth,
+    Usd,
+}
+
+impl FromStr for Numeraire {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_uppercase().as_str() {
+            "ETH" => Ok(Self::Eth),
+            "USD" => Ok(Self::Usd),
+            _ => Err(eyre::eyre!("Unknown numeraire {s}, expected ETH or USD")),
+        }
+    }
+}
+
+impl fmt::Display for Numeraire {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Eth => write!(f, "ETH"),
+            Self::Usd => write!(f, "USD"),
+        }
+    }
+}
+
+/// Where the USD price of ETH comes from.
+pub enum UsdSource {
+    /// A Chainlink ETH/USD aggregator.
+    Chainlink(Address),
+    /// The deepest WETH pool of a USD stablecoin.
+    Pool(Token),
+}
+
+/// Values amounts of any token in the numeraire at one block: tokens are
+/// priced in ETH by their deepest WETH pool, and ETH in USD by a Chainlink
+/// aggregator or a stablecoin pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Valuation {
+    pub numeraire: Numeraire,
+    weth: Address,
+    // Numeraire per ETH, 18 decimals
+    eth_price: U256,
+}
+
+impl Valuation {
+    pub fn eth(weth: Address) -> Self {
+        Self {
+            numeraire: Numeraire::Eth,
+            weth,
+            eth_price: U256::exp10(18),
+        }
+    }
+
+    pub fn usd(weth: Address, eth_price: U256) -> Self {
+        Self {
+            numeraire: Numeraire::Usd,
+            weth,
+            eth_price,
+        }
+    }
+
+    /// Prices ETH in the numeraire as of `block_number`. Each source in
+    /// `usd_sources` is tried in turn until one answers.
+    pub async fn fetch<M: Middleware + 'static>(
+        client: &Arc<M>,
+        numeraire: Numeraire,
+        weth: Address,
+        usd_sources: &[UsdSource],
+        pools: &[&dyn PoolAdapter],
+        block_number: u64,
+    ) -> Result<Self> {
+        if numeraire == Numeraire::Eth {
+            return Ok(Self::eth(weth));
+        }
+        for source in usd_sources {
+            let eth_price = match source {
+                UsdSource::Chainlink(aggregator) => {
+                    match chainlink_price(client, *aggregator, block_number).await {
+                        Ok(price) => Some(price),
+                        Err(error) => {
+                            print_warning(format!(
+                                "Chainlink aggregator {aggregator:?} failed: {error}"
+                            ));
+                            None
+                        }
+                    }
+                }
+                UsdSource::Pool(stablecoin) => pool_price(stablecoin, weth, pools),
+            };
+            if let Some(eth_price) = eth_price {
+                return Ok(Self::usd(weth, eth_price));
+            }
+        }
+        Err(eyre::eyre!("No source prices ETH in USD"))
+    }
+
+    /// Value of `amount` of `token` in the numeraire, 18 decimals. None if no
+    /// pool prices the token in ETH.
+    pub fn value(&self, token: Address, amount: U256, pools: &[&dyn PoolAdapter]) -> Option<U256> {
+        let wei = if token == self.weth {
+            amount
+        } else {
+            let price = reference_pool(token, self.weth, pools)?.spot_price(token, self.weth)?;
+            amount.checked_mul(price)? / U256::exp10(18)
+        };
+        Some(wei.checked_mul(self.eth_price)? / U256::exp10(18))
+    }
+}
+
+// Latest ETH/USD answer of a Chainlink aggregator, scaled to 18 decimals
+async fn chainlink_price<M: Middleware + 'static>(
+    client: &Arc<M>,
+    aggregator: Address,
+    block_number: u64,
+) -> Result<U256> {
+    let aggregator = IChainlinkAggregator::new(aggregator, client.clone());
+    let decimals = aggregator.decimals().block(block_number).call().await?;
+    let (_, answer, _, updated_at, _): (u128, I256, U256, U256, u128) = aggregator
+        .latest_round_data()
+        .block(block_number)
+        .call()
+        .await?;
+    let block = client
+        .get_block(block_number)
+        .await?
+        .ok_or_else(|| eyre::eyre!("Block {block_number} not found"))?;
+    check_fresh(updated_at, block.timestamp)?;
+    if answer <= I256::zero() || decimals > 18 {
+        return Err(eyre::eyre!("Unusable answer {answer}"));
+    }
+    Ok(answer.into_raw() * U256::exp10(18 - decimals as usize))
+}
+
+// Rejects an answer last updated at `updated_at` that's stale by `now`
+fn check_fresh(updated_at: U256, now: U256) -> Result<()> {
+    let age = now.saturating_sub(updated_at);
+    if age > U256::from(MAX_ANSWER_AGE) {
+        return Err(eyre::eyre!("Stale answer, last updated {age}s ago"));
+    }
+    Ok(())
+}
+
+// USD per ETH, 18 decimals, from the stablecoin's deepest WETH pool
+fn pool_price(stablecoin: &Token, weth: Address, pools: &[&dyn PoolAdapter]) -> Option<U256> {
+    let raw = eth_to_token(U256::exp10(18), stablecoin.address, weth, pools)?;
+    let scale = U256::exp10(18usize.checked_sub(stablecoin.decimals as usize)?);
+    raw.checked_mul(scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uniswap_v2::UniswapV2Pool;
+
+    #[test]
+    fn test_values_in_eth_and_usd() {
+        let weth = Address::from_low_u64_be(1);
+        let usdc = Token {
+            symbol: "USDC".to_string(),
+            address: Address::from_low_u64_be(2),
+            decimals: 6,
+        };
+        let link = Address::from_low_u64_be(3);
+        let eth = U256::exp10(18);
+        // 2000 USDC and 200 LINK per ETH
+        let usdc_eth = UniswapV2Pool::with_state(
+            "UniswapV2",
+            Address::zero(),
+            vec![usdc.address, weth],
+            vec![U256::from(2_000_000) * U256::exp10(6), eth * 1000],
+            30,
+        );
+        let link_eth = UniswapV2Pool::with_state(
+            "UniswapV2",
+            Address::zero(),
+            vec![link, weth],
+            vec![eth * 200_000, eth * 1000],
+            30,
+        );
+        let pools: Vec<&dyn PoolAdapter> = vec![&usdc_eth, &link_eth];
+
+        let in_eth = Valuation::eth(weth);
+        let eth_price = pool_price(&usdc, weth, &pools).unwrap();
+        assert_eq!(eth_price, eth * 2000);
+        let in_usd = Valuation::usd(weth, eth_price);
+
+        // 10 USDC and 1 LINK are both worth 0.005 ETH, 10 USD
+        let ten_usdc = U256::from(10_000_000);
+        assert_eq!(
+            in_eth.value(usdc.address, ten_usdc, &pools),
+            Some(eth / 200)
+        );
+        assert_eq!(in_eth.value(link, eth, &pools), Some(eth / 200));
+        assert_eq!(in_usd.value(usdc.address, ten_usdc, &pools), Some(eth * 10));
+        assert_eq!(in_usd.value(link, eth, &pools), Some(eth * 10));
+        assert_eq!(in_usd.value(weth, eth, &pools), Some(eth * 2000));
+        assert_eq!(in_usd.value(Address::zero(), eth, &pools), None);
+
+        assert_eq!("usd".parse::<Numeraire>().unwrap(), Numeraire::Usd);
+
+        // Chainlink answers older than the heartbeat allows aren't used
+        let now = U256::from(1_700_000_000);
+        assert!(check_fresh(now - 3600, now).is_ok());
+        assert!(check_fresh(now - MAX_ANSWER_AGE - 1, now).is_err());
+        assert!("EUR".parse::<Numeraire>().is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::exchange::PoolAdapter;
+use ethers::core::types::{Address, I256, U256};
+
+// Round trip profit of selling `amount_in` on `buy` and the proceeds back on
+// `sell`. Trades a pool can't quote count as the worst possible result.
+fn round_trip_profit(
+    buy: &dyn PoolAdapter,
+    sell: &dyn PoolAdapter,
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+) -> I256 {
+    let amount_out = buy
+        .quote_exact_in(token_in, token_out, amount_in)
+        .and_then(|amount| sell.quote_exact_in(token_out, token_in, amount));
+    match amount_out {
+        Some(amount_out) => I256::from_raw(amount_out) - I256::from_raw(amount_in),
+        None => I256::MIN,
+    }
+}
+
+pub(crate) fn reserve_of(pool: &dyn PoolAdapter, token: Address) -> Option<U256> {
+    let index = pool.tokens().iter().position(|t| *t == token)?;
+    pool.reserves().get(index).copied()
+}
+
+// Closed form optimum for two x*y=k pools, None if either pool isn't one.
+// Chained, they quote out = A * x / (B + C * x), so profit peaks where
+// A * B = (B + C * x)**2.
+fn constant_product_amount_in(
+    buy: &dyn PoolAdapter,
+    sell: &dyn PoolAdapter,
+    token_in: Address,
+    token_out: Address,
+) -> Option<U256> {
+    let buy_fee = U256::from(10000 - buy.constant_product_fee_bps()?);
+    let sell_fee = U256::from(10000 - sell.constant_product_fee_bps()?);
+    let (buy_in, buy_out) = (reserve_of(buy, token_in)?, reserve_of(buy, token_out)?);
+    let (sell_in, sell_out) = (reserve_of(sell, token_out)?, reserve_of(sell, token_in)?);
+
+    let bps = U256::from(10000);
+    let a = buy_fee
+        .checked_mul(sell_fee)?
+        .checked_mul(buy_out)?
+        .checked_mul(sell_out)?;
+    let b = buy_in.checked_mul(sell_in)?.checked_mul(bps * bps)?;
+    let c = buy_fee.checked_mul(
+        sell_in
+            .checked_mul(bps)?
+            .checked_add(sell_fee.checked_mul(buy_out)?)?,
+    )?;
+    let root = U256::try_from(a.full_mul(b).integer_sqrt()).ok()?;
+    if root <= b {
+        // Unprofitable at any size
+        return Some(U256::zero());
+    }
+    Some((root - b) / c)
+}
+
+// Ternary search for adapters without a closed form, over inputs up to
+// `high`. Assumes profit is unimodal in the input, which holds for every AMM
+// curve we support.
+pub(crate) fn search_amount_in(profit: impl Fn(U256) -> I256, high: U256) -> U256 {
+    let mut low = U256::zero();
+    let mut high = high;
+    while high - low > U256::from(2) {
+        let third = (high - low) / 3;
+        let (left, right) = (low + third, high - third);
+        if profit(left) < profit(right) {
+            low = left;
+        } else {
+            high = right;
+        }
+    }
+    let mut best = low;
+    let mut amount_in = low;
+    while amount_in < high {
+        amount_in += U256::one();
+        if profit(amount_in) > profit(best) {
+            best = amount_in;
+        }
+    }
+    best
+}
+
+/// Profit maximizing amount of `token_in` to sell on `buy` and buy back on
+/// `sell`, with the profit it makes. None if no amount is profitable.
+pub fn optimal_trade(
+    buy: &dyn PoolAdapter,
+    sell: &dyn PoolAdapter,
+    token_in: Address,
+    token_out: Address,
+) -> Option<(U256, U256)> {
+    let amount_in = match constant_product_amount_in(buy, sell, token_in, token_out) {
+        Some(amount_in) => amount_in,
+        None => {
+            // Selling more than the pool holds is never worth it
+            let high = reserve_of(buy, token_in)?;
+            search_amount_in(
+                |amount_in| round_trip_profit(buy, sell, token_in, token_out, amount_in),
+                high,
+            )
+        }
+    };
+    let profit = round_trip_profit(buy, sell, token_in, token_out, amount_in);
+    if profit <= I256::zero() {
+        return None;
+    }
+    Some((amount_in, profit.into_raw()))
+}
+
+/// A round trip selling `amount_in` on `buy` and the proceeds back on `sell`,
+/// for `profit` before costs.
+#[derive(Clone, Copy)]
+pub struct Trade<'a> {
+    pub buy: &'a dyn PoolAdapter,
+    pub sell: &'a dyn PoolAdapter,
+    pub amount_in: U256,
+    pub profit: U256,
+}
+
+/// Best `optimal_trade` between two different pools, which may be of the
+/// same exchange, e.g. two fee tiers.
+pub fn best_trade<'a>(
+    pools: &[&'a dyn PoolAdapter],
+    token_in: Address,
+    token_out: Address,
+) -> Option<Trade<'a>> {
+    let mut best: Option<Trade<'a>> = None;
+    for buy in pools {
+        for sell in pools {
+            if buy.address() == sell.address() {
+                continue;
+            }
+            if let Some((amount_in, profit)) = optimal_trade(*buy, *sell, token_in, token_out) {
+                if best.is_none_or(|best| profit > best.profit) {
+                    best = Some(Trade {
+                        buy: *buy,
+                        sell: *sell,
+                        amount_in,
+                        profit,
+                    });
+                }
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uniswap_v2::UniswapV2Pool;
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    fn v2_pool(exchange: &str, reserves: (U256, U256), fee_bps: u32) -> UniswapV2Pool {
+        UniswapV2Pool::with_state(
+            exchange,
+            Address::zero(),
+            vec![address(1), address(2)],
+            vec![reserves.0, reserves.1],
+            fee_bps,
+        )
+    }
+
+    #[test]
+    fn test_closed_form_is_the_optimum() {
+        let eth = U256::exp10(18);
+        // Token 1 buys 2040 of token 2 on the first pool, 2000 buy it back on the second
+        let buy = v2_pool("Buy", (eth * 1000, eth * 2_040_000), 30);
+        let sell = v2_pool("Sell", (eth * 1000, eth * 2_000_000), 25);
+
+        let (amount_in, profit) = optimal_trade(&buy, &sell, address(1), address(2)).unwrap();
+        assert!(amount_in > eth && amount_in < eth * 10);
+        for amount in [amount_in - eth / 100, amount_in + eth / 100] {
+            let other = round_trip_profit(&buy, &sell, address(1), address(2), amount);
+            assert!(other < I256::from_raw(profit));
+        }
+
+        // The numeric fallback lands on (almost) the same amount
+        let profit_of = |amount| round_trip_profit(&buy, &sell, address(1), address(2), amount);
+        let searched = search_amount_in(profit_of, eth * 1000);
+        let searched_profit = round_trip_profit(&buy, &sell, address(1), address(2), searched);
+        assert!(I256::from_raw(profit) - searched_profit <= I256::one());
+
+        // Going the other way loses money
+        assert_eq!(optimal_trade(&sell, &buy, address(1), address(2)), None);
+    }
+
+    #[test]
+    fn test_best_trade_pairs_different_pools() {
+        let eth = U256::exp10(18);
+        let pool = |address: Address, reserves: (U256, U256)| {
+            UniswapV2Pool::with_state(
+                "Same",
+                address,
+                vec![self::address(1), self::address(2)],
+                vec![reserves.0, reserves.1],
+                30,
+            )
+        };
+        // Two pools of one exchange still trade against each other
+        let buy = pool(address(10), (eth * 1000, eth * 2_040_000));
+        let sell = pool(address(11), (eth * 1000, eth * 2_000_000));
+        let trade = best_trade(&[&sell, &buy], address(1), address(2)).unwrap();
+        assert_eq!(trade.buy.address(), address(10));
+        assert_eq!(trade.sell.address(), address(11));
+        assert_eq!(
+            Some((trade.amount_in, trade.profit)),
+            optimal_trade(&buy, &sell, address(1), address(2))
+        );
+
+        // but a pool never trades against itself
+        assert!(best_trade(&[&buy], address(1), address(2)).is_none());
+    }
+}
@@ -0,0 +1,121 @@
+use ethers::core::types::{U256, U512};
+
+// Most decimals a token can have for its prices to be computed
+pub const MAX_DECIMALS: u8 = 36;
+
+/// Price of one whole base token in whole quote tokens, as an exact ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Price {
+    pub numerator: U256,
+    pub denominator: U256,
+}
+
+impl Price {
+    /// Price implied by exchanging `base_amount` raw units of the base token
+    /// for `quote_amount` raw units of the quote token, e.g. a pool's
+    /// reserves. None if an amount is zero or a token has more than
+    /// `MAX_DECIMALS` decimals.
+    pub fn from_amounts(
+        base_amount: U256,
+        base_decimals: u8,
+        quote_amount: U256,
+        quote_decimals: u8,
+    ) -> Option<Self> {
+        if base_amount.is_zero()
+            || quote_amount.is_zero()
+            || base_decimals > MAX_DECIMALS
+            || quote_decimals > MAX_DECIMALS
+        {
+            return None;
+        }
+        // (quote / 10^quote_decimals) / (base / 10^base_decimals), with only
+        // the larger scale applied so neither side grows needlessly
+        let (numerator, denominator) = if base_decimals >= quote_decimals {
+            let scale = U256::exp10((base_decimals - quote_decimals) as usize);
+            (quote_amount.checked_mul(scale)?, base_amount)
+        } else {
+            let scale = U256::exp10((quote_decimals - base_decimals) as usize);
+            (quote_amount, base_amount.checked_mul(scale)?)
+        };
+        Some(Self {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// Price of the quote token in the base token.
+    pub fn invert(&self) -> Self {
+        Self {
+            numerator: self.denominator,
+            denominator: self.numerator,
+        }
+    }
+
+    /// The price as an 18 decimal fixed point number, rounded down. None if
+    /// it doesn't fit in 256 bits.
+    pub fn to_fixed(&self) -> Option<U256> {
+        if self.denominator.is_zero() {
+            return None;
+        }
+        let scaled = self.numerator.full_mul(U256::exp10(18)) / U512::from(self.denominator);
+        U256::try_from(scaled).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(base: U256, base_decimals: u8, quote: U256, quote_decimals: u8) -> Price {
+        Price::from_amounts(base, base_decimals, quote, quote_decimals).unwrap()
+    }
+
+    #[test]
+    fn test_usdc_eth() {
+        // 20,000,000 USDC against 10,000 ETH
+        let usdc = U256::from(20_000_000u64) * U256::exp10(6);
+        let eth = U256::from(10_000u64) * U256::exp10(18);
+
+        // 6/18: one USDC buys 0.0005 ETH
+        let usdc_in_eth = price(usdc, 6, eth, 18);
+        assert_eq!(usdc_in_eth.to_fixed(), Some(U256::exp10(18) * 5 / 10_000));
+        // 18/6: one ETH buys 2000 USDC
+        let eth_in_usdc = price(eth, 18, usdc, 6);
+        assert_eq!(eth_in_usdc.to_fixed(), Some(U256::exp10(18) * 2000));
+        assert_eq!(usdc_in_eth.invert().to_fixed(), eth_in_usdc.to_fixed());
+    }
+
+    #[test]
+    fn test_wbtc_eth() {
+        // 8/18: 100 WBTC against 1,500 ETH
+        let wbtc = U256::from(100) * U256::exp10(8);
+        let eth = U256::from(1_500) * U256::exp10(18);
+        let wbtc_in_eth = price(wbtc, 8, eth, 18);
+        assert_eq!(wbtc_in_eth.to_fixed(), Some(U256::exp10(18) * 15));
+
+        let eth_in_wbtc = wbtc_in_eth.invert().to_fixed().unwrap();
+        // 1/15 WBTC, rounded down
+        assert_eq!(eth_in_wbtc, U256::exp10(18) / 15);
+    }
+
+    #[test]
+    fn test_same_decimals() {
+        // 18/18: 1,000 LINK against 5 ETH
+        let link = U256::from(1_000) * U256::exp10(18);
+        let eth = U256::from(5) * U256::exp10(18);
+        let link_in_eth = price(link, 18, eth, 18);
+        assert_eq!(link_in_eth.to_fixed(), Some(U256::exp10(18) / 200));
+        assert_eq!(link_in_eth.invert().to_fixed(), Some(U256::exp10(18) * 200));
+    }
+
+    #[test]
+    fn test_extreme_decimals() {
+        // 0/36 in both directions: one whole token of each, 1:1
+        let none = price(U256::one(), 0, U256::exp10(36), 36);
+        assert_eq!(none.to_fixed(), Some(U256::exp10(18)));
+        assert_eq!(none.invert().to_fixed(), Some(U256::exp10(18)));
+
+        assert_eq!(Price::from_amounts(U256::one(), 37, U256::one(), 18), None);
+        assert_eq!(Price::from_amounts(U256::zero(), 18, U256::one(), 18), None);
+    }
+}
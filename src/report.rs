@@ -0,0 +1,245 @@
+use crate::client::PairMismatch;
+use crate::exchange::PoolAdapter;
+use crate::funding::Lender;
+use crate::graph::Cycle;
+use crate::numeraire::Numeraire;
+use crate::submitter::TxStatus;
+use crate::tokens::TokenRegistry;
+use crate::utils::{calculate_price_impact, ArbitrageOpportunity, Token, TradeCosts};
+use ethers::{
+    core::types::{transaction::eip2718::TypedTransaction, Address, H256, U256},
+    utils::format_units,
+};
+use std::fmt::Display;
+use std::time::Duration;
+
+// Where a trade's funds come from, for display
+fn describe_lender(lender: Option<Lender>) -> String {
+    match lender {
+        Some(lender) => format!("flash loan from {lender}"),
+        None => "own balance".to_string(),
+    }
+}
+
+// Whole token amount for display, raw units if the decimals are unusual
+fn display_amount(amount: U256, token: &Token) -> String {
+    format_units(amount, token.decimals as u32).unwrap_or_else(|_| amount.to_string())
+}
+
+/// Prints how every pool quotes selling `trade_amount` of one side of the pair.
+/// `prices` are 18 decimal prices of one whole bought token in sold tokens.
+pub fn print_quotes(
+    token_pair: (&Token, &Token),
+    prices: &[(U256, &dyn PoolAdapter)],
+    trade_amount: U256,
+    token0_to_token1: bool,
+) {
+    let (selling, buying) = if token0_to_token1 {
+        (token_pair.0, token_pair.1)
+    } else {
+        (token_pair.1, token_pair.0)
+    };
+    for (price, pool) in prices {
+        let Some((price_impact, _)) =
+            calculate_price_impact(*pool, selling.address, buying.address, trade_amount)
+        else {
+            continue;
+        };
+        println!(
+            "Exchange: {} ({} -> {})| Fee: {} bps | Price Per {}: {} {} | Total Selling: {} {} | Price Impact: {} bps",
+            pool.exchange(),
+            selling.symbol,
+            buying.symbol,
+            pool.fee() / U256::exp10(14),
+            buying.symbol,
+            format_units(*price, 18).unwrap_or_else(|_| price.to_string()),
+            selling.symbol,
+            display_amount(trade_amount, selling),
+            selling.symbol,
+            price_impact
+        );
+    }
+}
+
+pub fn print_opportunity(opportunity: &ArbitrageOpportunity) {
+    let selling = &opportunity.token_in;
+    let buying = &opportunity.token_out;
+    println!(
+        "Trade Route: {} -> {} | {} {} -> {} {} -> {} {}",
+        opportunity.buy_exchange,
+        opportunity.sell_exchange,
+        display_amount(opportunity.amount_in, selling),
+        selling.symbol,
+        display_amount(opportunity.amount_intermediate, buying),
+        buying.symbol,
+        display_amount(opportunity.amount_out, selling),
+        selling.symbol,
+    );
+    println!(
+        "Arbitrage opportunity found at block {}! Net profit {} {} (gross {}, gas {}, loan fee {}, {}) | Sell {} for {} on {} ({:?}, Price Impact: {} bps), sell {} for {} on {} ({:?}, Price Impact: {} bps).\n",
+        opportunity.block_number,
+        display_amount(opportunity.net_profit, selling),
+        selling.symbol,
+        display_amount(opportunity.gross_profit, selling),
+        display_amount(opportunity.gas_cost, selling),
+        display_amount(opportunity.loan_fee, selling),
+        describe_lender(opportunity.lender),
+        selling.symbol,
+        buying.symbol,
+        opportunity.buy_exchange,
+        opportunity.buy_pool,
+        opportunity.buy_price_impact,
+        buying.symbol,
+        selling.symbol,
+        opportunity.sell_exchange,
+        opportunity.sell_pool,
+        opportunity.sell_price_impact,
+    );
+}
+
+/// Prints a simulated multi-hop cycle. Amounts are in the start token, raw
+/// units if its metadata isn't known; `profit` is before `costs`.
+pub fn print_cycle(
+    tokens: &TokenRegistry,
+    pools: &[&dyn PoolAdapter],
+    cycle: &Cycle,
+    amount_in: U256,
+    profit: U256,
+    costs: &TradeCosts,
+    block_number: u64,
+) {
+    let amount = |amount: U256| match tokens.get(cycle.start_token()) {
+        Some(token) => display_amount(amount, &token),
+        None => amount.to_string(),
+    };
+    println!(
+        "Cycle found at block {}! Net profit {} {} (gross {}, gas {}, loan fee {}, {}) for {} in | {}\n",
+        block_number,
+        amount(profit - costs.gas_cost - costs.loan_fee),
+        tokens.symbol(cycle.start_token()),
+        amount(profit),
+        amount(costs.gas_cost),
+        amount(costs.loan_fee),
+        describe_lender(costs.lender),
+        amount(amount_in),
+        describe_cycle(tokens, pools, cycle),
+    );
+}
+
+/// The hops of a cycle, one `IN -> OUT on Exchange` per pool.
+pub fn describe_cycle(tokens: &TokenRegistry, pools: &[&dyn PoolAdapter], cycle: &Cycle) -> String {
+    let route: Vec<String> = cycle
+        .hops
+        .iter()
+        .map(|hop| {
+            format!(
+                "{} -> {} on {}",
+                tokens.symbol(hop.token_in),
+                tokens.symbol(hop.token_out),
+                pools[hop.pool].exchange()
+            )
+        })
+        .collect();
+    route.join(" | ")
+}
+
+pub fn describe_opportunity(opportunity: &ArbitrageOpportunity) -> String {
+    format!(
+        "{} -> {} on {}, back on {} at block {}",
+        opportunity.token_in.symbol,
+        opportunity.token_out.symbol,
+        opportunity.buy_exchange,
+        opportunity.sell_exchange,
+        opportunity.block_number
+    )
+}
+
+/// Prints every opportunity of a scan by its net profit in the numeraire, most
+/// valuable first. Each entry is a value (18 decimals) and a description.
+pub fn print_ranking(ranked: &mut [(U256, String)], numeraire: Numeraire) {
+    if ranked.is_empty() {
+        return;
+    }
+    ranked.sort_by_key(|(value, _)| std::cmp::Reverse(*value));
+    println!("Opportunities by net profit in {numeraire}:");
+    for (rank, (value, description)) in ranked.iter().enumerate() {
+        println!(
+            "{}. {} {} | {}",
+            rank + 1,
+            format_units(*value, 18).unwrap_or_else(|_| value.to_string()),
+            numeraire,
+            description
+        );
+    }
+}
+
+/// Prints the transaction that would execute an opportunity.
+pub fn print_calldata(to: Address, tx: &TypedTransaction) {
+    let calldata = tx.data().cloned().unwrap_or_default();
+    println!("Execute with {to:?}: {calldata}\n");
+}
+
+/// Prints how far an opportunity's simulated profit is from the predicted
+/// one, in raw units if the token isn't known. Such opportunities aren't
+/// executed.
+pub fn print_divergence(profit: U256, predicted: U256, token: Option<&Token>) {
+    let amount = |amount: U256| match token {
+        Some(token) => display_amount(amount, token),
+        None => amount.to_string(),
+    };
+    let symbol = token.map_or("", |token| token.symbol.as_str());
+    println!(
+        "Simulation diverged: {} {symbol} profit on chain, {} {symbol} predicted. Not executing.\n",
+        amount(profit),
+        amount(predicted),
+    );
+}
+
+pub fn print_submitted(hash: H256, from: Address) {
+    println!("Submitted {hash:?} from {from:?}\n");
+}
+
+pub fn print_tx_status(hash: H256, status: TxStatus) {
+    match status {
+        TxStatus::Included { block_number } => {
+            println!("{hash:?} included at block {block_number}")
+        }
+        TxStatus::Reverted { block_number } => {
+            println!("{hash:?} reverted at block {block_number}")
+        }
+        TxStatus::Replaced => println!("{hash:?} replaced"),
+        TxStatus::Dropped => println!("{hash:?} dropped"),
+    }
+}
+
+pub fn print_summary(symbol_pair: (&str, &str), opportunities: &[ArbitrageOpportunity]) {
+    println!(
+        "Arbitrage opportunities for {}-{} pool: {}",
+        symbol_pair.0,
+        symbol_pair.1,
+        opportunities.len()
+    );
+}
+
+pub fn print_discovered(exchange: &str, pairs: usize) {
+    println!("{exchange}: {pairs} pairs");
+}
+
+pub fn print_mismatch(mismatch: &PairMismatch) {
+    println!("{mismatch}");
+}
+
+/// Prints a candidate dropped because its simulation reverted.
+pub fn print_discarded(route: &str, reason: &str) {
+    eprintln!("Discarding {route}: {reason}");
+}
+
+/// Prints why watching for blocks failed and when it's tried again.
+pub fn print_retry(error: impl Display, backoff: Duration) {
+    eprintln!("{error}. Retrying in {backoff:?}");
+}
+
+/// Prints a problem that doesn't stop the scan.
+pub fn print_warning(message: impl Display) {
+    eprintln!("{message}");
+}
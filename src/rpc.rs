@@ -0,0 +1,344 @@
+use crate::report::print_warning;
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, HttpClientError, JsonRpcClient, Provider, ProviderError, Quorum, QuorumProvider,
+    WeightedProvider,
+};
+use eyre::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How long a failed endpoint is skipped before it's tried again
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+tokio::task_local! {
+    // Set while the requests `with_quorum` wraps run
+    static QUORUM: ();
+}
+
+/// A transport whose errors tell the node's own answers, which another node
+/// would repeat, apart from failures worth trying the next endpoint for.
+pub trait FailoverTransport: JsonRpcClient {
+    fn is_node_error(error: &Self::Error) -> bool;
+}
+
+impl FailoverTransport for Http {
+    fn is_node_error(error: &HttpClientError) -> bool {
+        matches!(error, HttpClientError::JsonRpcError(_))
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint<C> {
+    url: String,
+    client: C,
+    // Skipped until then after a failure
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl<C> Endpoint<C> {
+    fn is_healthy(&self) -> bool {
+        let unhealthy_until = self.unhealthy_until.lock().unwrap();
+        unhealthy_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    fn mark(&self, healthy: bool) {
+        *self.unhealthy_until.lock().unwrap() = (!healthy).then(|| Instant::now() + COOLDOWN);
+    }
+}
+
+/// HTTP endpoints tried in order. An endpoint that errors or times out is
+/// skipped for a while and the request moves on to the next one; errors the
+/// node itself answers with (reverts, bad params) are returned as is.
+#[derive(Debug)]
+pub struct FailoverClient<C = Http> {
+    endpoints: Vec<Endpoint<C>>,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+pub enum FailoverError<E = HttpClientError> {
+    // A JSON-RPC error response, which another node would repeat
+    Rpc(E),
+    // Every endpoint failed; one message per endpoint
+    AllFailed(Vec<String>),
+}
+
+impl<E: fmt::Display> fmt::Display for FailoverError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rpc(error) => write!(f, "{error}"),
+            Self::AllFailed(errors) => {
+                write!(f, "All RPC endpoints failed: {}", errors.join("; "))
+            }
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for FailoverError<E> {}
+
+impl<E: std::error::Error + Send + Sync + 'static> From<FailoverError<E>> for ProviderError {
+    fn from(error: FailoverError<E>) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(error))
+    }
+}
+
+impl FailoverClient {
+    pub fn new(urls: &[String], timeout: Duration) -> Result<Self> {
+        let mut clients = vec![];
+        for url in urls {
+            let client = Http::from_str(url)
+                .map_err(|error| eyre::eyre!("Invalid RPC endpoint {url}: {error}"))?;
+            clients.push((url.clone(), client));
+        }
+        Ok(Self::with_clients(clients, timeout))
+    }
+}
+
+impl<C: FailoverTransport> FailoverClient<C> {
+    /// Fails over between `clients`, each named by its URL.
+    pub fn with_clients(clients: Vec<(String, C)>, timeout: Duration) -> Self {
+        let endpoints = clients
+            .into_iter()
+            .map(|(url, client)| Endpoint {
+                url,
+                client,
+                unhealthy_until: Mutex::new(None),
+            })
+            .collect();
+        Self { endpoints, timeout }
+    }
+
+    /// Asks every endpoint for its chain id and marks the ones that don't
+    /// answer. Fails if none do, or if they disagree about the chain.
+    pub async fn check_health(&self) -> Result<()> {
+        let mut chain_ids = vec![];
+        for endpoint in &self.endpoints {
+            let request = endpoint
+                .client
+                .request::<_, ethers::types::U256>("eth_chainId", ());
+            match tokio::time::timeout(self.timeout, request).await {
+                Ok(Ok(chain_id)) => {
+                    endpoint.mark(true);
+                    chain_ids.push(chain_id);
+                }
+                Ok(Err(error)) => {
+                    print_warning(format!(
+                        "RPC endpoint {} is unhealthy: {error}",
+                        endpoint.url
+                    ));
+                    endpoint.mark(false);
+                }
+                Err(_) => {
+                    print_warning(format!("RPC endpoint {} timed out", endpoint.url));
+                    endpoint.mark(false);
+                }
+            }
+        }
+        if chain_ids.is_empty() {
+            return Err(eyre::eyre!(
+                "None of the configured RPC endpoints is reachable"
+            ));
+        }
+        if chain_ids.iter().any(|chain_id| *chain_id != chain_ids[0]) {
+            return Err(eyre::eyre!("RPC endpoints are on different chains"));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<C: FailoverTransport> JsonRpcClient for FailoverClient<C>
+where
+    C::Error: Send + Sync + 'static,
+{
+    type Error = FailoverError<C::Error>;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        // Healthy endpoints first, the rest as a last resort
+        let (healthy, unhealthy): (Vec<&Endpoint<C>>, Vec<&Endpoint<C>>) = self
+            .endpoints
+            .iter()
+            .partition(|endpoint| endpoint.is_healthy());
+        let mut errors = vec![];
+        for endpoint in healthy.into_iter().chain(unhealthy) {
+            let request = endpoint.client.request(method, &params);
+            match tokio::time::timeout(self.timeout, request).await {
+                Ok(Ok(response)) => {
+                    endpoint.mark(true);
+                    return Ok(response);
+                }
+                Ok(Err(error)) if C::is_node_error(&error) => {
+                    endpoint.mark(true);
+                    return Err(FailoverError::Rpc(error));
+                }
+                Ok(Err(error)) => errors.push(format!("{}: {error}", endpoint.url)),
+                Err(_) => errors.push(format!("{}: timed out", endpoint.url)),
+            }
+            endpoint.mark(false);
+        }
+        Err(FailoverError::AllFailed(errors))
+    }
+}
+
+/// A provider that fails over between `urls`, checked before use.
+pub async fn connect_failover(
+    urls: &[String],
+    timeout: Duration,
+) -> Result<Provider<FailoverClient>> {
+    let client = FailoverClient::new(urls, timeout)?;
+    client.check_health().await?;
+    Ok(Provider::new(client))
+}
+
+/// Runs `future` with its requests answered by a quorum of endpoints, when
+/// the transport is `QuorumReads`.
+pub async fn with_quorum<F: Future>(future: F) -> F::Output {
+    QUORUM.scope((), future).await
+}
+
+/// Sends requests through `client`, except those made within `with_quorum`,
+/// which go to `quorum`. Keeps block polling, gas, nonces and sends from
+/// stalling whenever the endpoints are a block apart.
+#[derive(Debug)]
+pub struct QuorumReads<C = FailoverClient, Q = QuorumProvider<Http>> {
+    client: C,
+    quorum: Q,
+}
+
+impl<C, Q> QuorumReads<C, Q> {
+    pub fn new(client: C, quorum: Q) -> Self {
+        Self { client, quorum }
+    }
+}
+
+#[async_trait]
+impl<C: JsonRpcClient, Q: JsonRpcClient> JsonRpcClient for QuorumReads<C, Q> {
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        if QUORUM.try_with(|_| ()).is_ok() {
+            self.quorum
+                .request(method, params)
+                .await
+                .map_err(Into::into)
+        } else {
+            self.client
+                .request(method, params)
+                .await
+                .map_err(Into::into)
+        }
+    }
+}
+
+/// A provider failing over between `urls` like `connect_failover`, whose
+/// `with_quorum` reads only succeed once `quorum` of them agree.
+pub async fn connect_quorum(
+    urls: &[String],
+    quorum: usize,
+    timeout: Duration,
+) -> Result<Provider<QuorumReads>> {
+    let client = FailoverClient::new(urls, timeout)?;
+    client.check_health().await?;
+    let mut providers = vec![];
+    for url in urls {
+        let client = Http::from_str(url)
+            .map_err(|error| eyre::eyre!("Invalid RPC endpoint {url}: {error}"))?;
+        providers.push(WeightedProvider::new(client));
+    }
+    let quorum = QuorumProvider::new(Quorum::ProviderCount(quorum), providers);
+    Ok(Provider::new(QuorumReads::new(client, quorum)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{
+        core::types::{U256, U64},
+        providers::{Middleware, MockProvider},
+    };
+
+    impl FailoverTransport for MockProvider {
+        // Mocks only fail for running out of responses
+        fn is_node_error(_: &Self::Error) -> bool {
+            false
+        }
+    }
+
+    fn failover(mocks: &[&MockProvider]) -> Provider<FailoverClient<MockProvider>> {
+        let clients = mocks
+            .iter()
+            .enumerate()
+            .map(|(index, mock)| (format!("mock{index}"), (*mock).clone()))
+            .collect();
+        Provider::new(FailoverClient::with_clients(
+            clients,
+            Duration::from_secs(1),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_failover_skips_unhealthy_endpoints() {
+        let (first, second) = (MockProvider::new(), MockProvider::new());
+        let provider = failover(&[&first, &second]);
+
+        // The first endpoint fails, so the second one answers
+        second.push(U64::from(5)).unwrap();
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(5));
+
+        // and is asked first until the failed one cools down
+        first.push(U64::from(6)).unwrap();
+        second.push(U64::from(7)).unwrap();
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(7));
+
+        // which is still tried once nothing else answers
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(6));
+        assert!(provider.get_block_number().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_health() {
+        let (first, second) = (MockProvider::new(), MockProvider::new());
+        let provider = failover(&[&first, &second]);
+
+        // A silent endpoint is marked unhealthy and asked last
+        second.push(U256::one()).unwrap();
+        provider.as_ref().check_health().await.unwrap();
+        first.push(U64::from(1)).unwrap();
+        second.push(U64::from(2)).unwrap();
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(2));
+        first
+            .request::<_, U64>("eth_blockNumber", ())
+            .await
+            .unwrap();
+
+        // Endpoints on different chains, or none answering, fail the check
+        first.push(U256::one()).unwrap();
+        second.push(U256::from(5)).unwrap();
+        assert!(provider.as_ref().check_health().await.is_err());
+        assert!(provider.as_ref().check_health().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quorum_only_for_wrapped_reads() {
+        let (client, quorum) = (MockProvider::new(), MockProvider::new());
+        let provider = Provider::new(QuorumReads::new(client.clone(), quorum.clone()));
+        client.push(U64::from(1)).unwrap();
+        quorum.push(U64::from(2)).unwrap();
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(1));
+        let read = with_quorum(provider.get_block_number()).await;
+        assert_eq!(read.unwrap(), U64::from(2));
+    }
+}
@@ -0,0 +1,116 @@
+use ethers::{
+    abi::{decode, ParamType, Token},
+    core::types::{transaction::eip2718::TypedTransaction, Address, U256},
+    providers::Middleware,
+};
+use eyre::Result;
+
+/// What running an executor call with `eth_call` said about the profit
+/// predicted for it. Off-chain AMM math misses fee-on-transfer tokens, fees
+/// set wrong in the config and reserves that moved since they were read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Simulation {
+    /// Profit within the tolerance of the prediction.
+    Confirmed { profit: U256 },
+    /// Profit further than the tolerance from the prediction.
+    Diverged { profit: U256, predicted: U256 },
+    /// The call reverted, with the node's message.
+    Reverted(String),
+}
+
+impl Simulation {
+    /// Compares a simulated `profit` to `predicted`, allowing them to differ
+    /// by `tolerance_bps` of the prediction.
+    pub fn compare(profit: U256, predicted: U256, tolerance_bps: u32) -> Self {
+        let difference = if profit > predicted {
+            profit - predicted
+        } else {
+            predicted - profit
+        };
+        if difference * 10000 > predicted * tolerance_bps {
+            Self::Diverged { profit, predicted }
+        } else {
+            Self::Confirmed { profit }
+        }
+    }
+}
+
+/// Runs `tx` from `from` at `block_number` with `eth_call` and compares the
+/// profit it returns to `predicted`. Errors only when the node couldn't run
+/// the call at all.
+pub async fn simulate<M: Middleware>(
+    client: &M,
+    tx: &TypedTransaction,
+    from: Address,
+    block_number: u64,
+    predicted: U256,
+    tolerance_bps: u32,
+) -> Result<Simulation> {
+    let mut tx = tx.clone();
+    tx.set_from(from);
+    let output = match client.call(&tx, Some(block_number.into())).await {
+        Ok(output) => output,
+        Err(error) if is_revert(&error.to_string()) => {
+            return Ok(Simulation::Reverted(error.to_string()))
+        }
+        Err(error) => return Err(eyre::eyre!("Simulation failed: {error}")),
+    };
+    match decode(&[ParamType::Uint(256)], &output)?.pop() {
+        Some(Token::Uint(profit)) => Ok(Simulation::compare(profit, predicted, tolerance_bps)),
+        _ => Err(eyre::eyre!("Simulation returned {output}, not a profit")),
+    }
+}
+
+// Nodes word reverts differently ("execution reverted", "Reverted") but all
+// of them say so
+fn is_revert(message: &str) -> bool {
+    message.to_ascii_lowercase().contains("revert")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{abi::encode, core::types::Bytes, providers::Provider};
+
+    #[tokio::test]
+    async fn test_simulated_profit_against_prediction() {
+        let predicted = U256::from(10_000);
+        // 1% either way is close enough
+        assert_eq!(
+            Simulation::compare(U256::from(9_900), predicted, 100),
+            Simulation::Confirmed {
+                profit: U256::from(9_900)
+            }
+        );
+        assert_eq!(
+            Simulation::compare(U256::from(10_101), predicted, 100),
+            Simulation::Diverged {
+                profit: U256::from(10_101),
+                predicted
+            }
+        );
+
+        let (provider, mock) = Provider::mocked();
+        let output: Bytes = encode(&[Token::Uint(U256::from(5_000))]).into();
+        mock.push::<Bytes, _>(output).unwrap();
+        let simulation = simulate(
+            &provider,
+            &TypedTransaction::default(),
+            Address::zero(),
+            1,
+            predicted,
+            100,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            simulation,
+            Simulation::Diverged {
+                profit: U256::from(5_000),
+                predicted
+            }
+        );
+        assert!(is_revert("execution reverted: unprofitable"));
+        assert!(!is_revert("connection refused"));
+    }
+}
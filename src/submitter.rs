@@ -0,0 +1,464 @@
+use crate::gas::GasPrice;
+use crate::report::print_warning;
+use ethers::{
+    core::types::{
+        transaction::eip2718::TypedTransaction, Address, BlockNumber, Eip1559TransactionRequest,
+        H256, U256,
+    },
+    providers::Middleware,
+    signers::{LocalWallet, Signer},
+};
+use eyre::Result;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Blocks a transaction may stay pending before it's cancelled, as the
+// opportunity it was sent for is gone by then
+const STUCK_BLOCKS: u64 = 3;
+// Blocks a transaction the node no longer knows about is waited for before
+// it counts as dropped
+const DROPPED_BLOCKS: u64 = 12;
+// How much a replacement raises the fees of the transaction it replaces,
+// above the 10% nodes require
+const REPLACEMENT_BUMP_BPS: u64 = 1250;
+
+/// Hands out consecutive nonces to concurrent submissions, starting from the
+/// account's pending transaction count and reading it again once a nonce may
+/// have been left unused.
+pub struct NonceManager {
+    address: Address,
+    next: Mutex<Option<U256>>,
+}
+
+impl NonceManager {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            next: Mutex::new(None),
+        }
+    }
+
+    pub async fn reserve<M: Middleware>(&self, client: &M) -> Result<U256> {
+        let mut next = self.next.lock().await;
+        let nonce = match *next {
+            Some(nonce) => nonce,
+            None => client
+                .get_transaction_count(self.address, Some(BlockNumber::Pending.into()))
+                .await
+                .map_err(|error| eyre::eyre!("Can't read the account nonce: {error}"))?,
+        };
+        *next = Some(nonce + 1);
+        Ok(nonce)
+    }
+
+    /// Takes back `nonce` after its transaction failed to send. Unless it was
+    /// the last one handed out, the next nonce is read from the chain again.
+    pub async fn release(&self, nonce: U256) {
+        let mut next = self.next.lock().await;
+        *next = (*next == Some(nonce + 1)).then_some(nonce);
+    }
+
+    /// Reads the next nonce from the chain again, after a transaction was
+    /// dropped and left a gap.
+    pub async fn resync(&self) {
+        *self.next.lock().await = None;
+    }
+}
+
+/// What became of a submitted transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    /// Mined and succeeded.
+    Included { block_number: u64 },
+    /// Mined and reverted.
+    Reverted { block_number: u64 },
+    /// Another transaction with the same nonce was mined, including our own
+    /// cancellation.
+    Replaced,
+    /// No longer known to the node and never mined.
+    Dropped,
+}
+
+// A sent transaction that hasn't been resolved yet
+struct PendingTx {
+    hash: H256,
+    nonce: U256,
+    max_fee_per_gas: U256,
+    max_priority_fee_per_gas: U256,
+    sent_at: u64,
+    cancelled: bool,
+}
+
+/// Signs and sends arbitrage transactions from one account, then follows
+/// each until it's mined, replaced or dropped. Transactions still pending
+/// after a few blocks are cancelled with a higher paying self transfer.
+pub struct Submitter<M> {
+    client: Arc<M>,
+    wallet: LocalWallet,
+    nonces: NonceManager,
+    pending: Mutex<Vec<PendingTx>>,
+}
+
+impl<M: Middleware + 'static> Submitter<M> {
+    /// `wallet` has to sign for the chain `client` is on.
+    pub fn new(client: Arc<M>, wallet: LocalWallet) -> Self {
+        Self {
+            nonces: NonceManager::new(wallet.address()),
+            client,
+            wallet,
+            pending: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        self.wallet.address()
+    }
+
+    /// Sends `tx`'s call as an EIP-1559 transaction priced off `gas_price`,
+    /// with the gas it's estimated to use. Fails without sending if the
+    /// estimate reverts.
+    pub async fn submit(
+        &self,
+        tx: &TypedTransaction,
+        gas_price: &GasPrice,
+        block_number: u64,
+    ) -> Result<H256> {
+        let mut request = Eip1559TransactionRequest::new()
+            .from(self.address())
+            .max_fee_per_gas(gas_price.max_fee_per_gas())
+            .max_priority_fee_per_gas(gas_price.priority_fee);
+        request.to = tx.to().cloned();
+        request.data = tx.data().cloned();
+        request.value = tx.value().cloned();
+        let mut request = TypedTransaction::Eip1559(request);
+        let gas = self
+            .client
+            .estimate_gas(&request, None)
+            .await
+            .map_err(|error| eyre::eyre!("Gas estimate failed: {error}"))?;
+        request.set_gas(gas);
+
+        let nonce = self.nonces.reserve(self.client.as_ref()).await?;
+        request.set_nonce(nonce);
+        let hash = match self.send(request).await {
+            Ok(hash) => hash,
+            Err(error) => {
+                self.nonces.release(nonce).await;
+                return Err(error);
+            }
+        };
+        self.pending.lock().await.push(PendingTx {
+            hash,
+            nonce,
+            max_fee_per_gas: gas_price.max_fee_per_gas(),
+            max_priority_fee_per_gas: gas_price.priority_fee,
+            sent_at: block_number,
+            cancelled: false,
+        });
+        Ok(hash)
+    }
+
+    /// Checks every pending transaction as of `block_number`, returning those
+    /// resolved since the last poll. Stuck transactions are cancelled at
+    /// `gas_price`, or a bump over their own fees if that's higher.
+    pub async fn poll(
+        &self,
+        gas_price: &GasPrice,
+        block_number: u64,
+    ) -> Result<Vec<(H256, TxStatus)>> {
+        let mut pending = self.pending.lock().await;
+        let mined_nonce = self
+            .client
+            .get_transaction_count(self.address(), Some(BlockNumber::Latest.into()))
+            .await
+            .map_err(|error| eyre::eyre!("Can't read the account nonce: {error}"))?;
+
+        let mut resolved = vec![];
+        let mut still_pending = vec![];
+        for mut tx in pending.drain(..) {
+            // A failed lookup leaves the transaction for the next poll
+            match self
+                .check(&mut tx, mined_nonce, gas_price, block_number)
+                .await
+            {
+                Ok(Some(status)) => resolved.push((tx.hash, status)),
+                Ok(None) => still_pending.push(tx),
+                Err(error) => {
+                    print_warning(format!("Can't check {:?}: {error}", tx.hash));
+                    still_pending.push(tx);
+                }
+            }
+        }
+        *pending = still_pending;
+        Ok(resolved)
+    }
+
+    // What became of `tx`, None while it's still pending. Cancels it once
+    // it's stuck
+    async fn check(
+        &self,
+        tx: &mut PendingTx,
+        mined_nonce: U256,
+        gas_price: &GasPrice,
+        block_number: u64,
+    ) -> Result<Option<TxStatus>> {
+        let receipt = self
+            .client
+            .get_transaction_receipt(tx.hash)
+            .await
+            .map_err(|error| eyre::eyre!("Can't read the receipt: {error}"))?;
+        if let Some(receipt) = receipt {
+            let block_number = receipt.block_number.unwrap_or_default().as_u64();
+            return Ok(Some(match receipt.status.map(|status| status.as_u64()) {
+                Some(1) => TxStatus::Included { block_number },
+                _ => TxStatus::Reverted { block_number },
+            }));
+        }
+        if mined_nonce > tx.nonce {
+            return Ok(Some(TxStatus::Replaced));
+        }
+        let waited = block_number.saturating_sub(tx.sent_at);
+        if waited >= DROPPED_BLOCKS && self.is_unknown(tx.hash).await? {
+            // Later nonces wait on this one until it's reused
+            self.nonces.resync().await;
+            return Ok(Some(TxStatus::Dropped));
+        }
+        if waited >= STUCK_BLOCKS && !tx.cancelled {
+            match self.cancel(tx, gas_price).await {
+                Ok(_) => tx.cancelled = true,
+                Err(error) => print_warning(format!("Can't cancel {:?}: {error}", tx.hash)),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn is_unknown(&self, hash: H256) -> Result<bool> {
+        let tx = self
+            .client
+            .get_transaction(hash)
+            .await
+            .map_err(|error| eyre::eyre!("Can't look up {hash:?}: {error}"))?;
+        Ok(tx.is_none())
+    }
+
+    // Replaces `tx` with an empty transfer to ourselves at the same nonce
+    async fn cancel(&self, tx: &PendingTx, gas_price: &GasPrice) -> Result<H256> {
+        self.send(self.cancellation(tx, gas_price).into()).await
+    }
+
+    // The cancellation of `tx`, paying enough more than it to replace it and
+    // at least `gas_price`
+    fn cancellation(&self, tx: &PendingTx, gas_price: &GasPrice) -> Eip1559TransactionRequest {
+        let bump = |fee: U256| fee + fee * REPLACEMENT_BUMP_BPS / 10000;
+        let priority_fee = bump(tx.max_priority_fee_per_gas).max(gas_price.priority_fee);
+        let max_fee = bump(tx.max_fee_per_gas)
+            .max(gas_price.max_fee_per_gas())
+            .max(priority_fee);
+        Eip1559TransactionRequest::new()
+            .from(self.address())
+            .to(self.address())
+            .value(0)
+            .gas(21_000)
+            .nonce(tx.nonce)
+            .max_fee_per_gas(max_fee)
+            .max_priority_fee_per_gas(priority_fee)
+    }
+
+    async fn send(&self, mut request: TypedTransaction) -> Result<H256> {
+        request.set_chain_id(self.wallet.chain_id());
+        let signature = self.wallet.sign_transaction(&request).await?;
+        let pending = self
+            .client
+            .send_raw_transaction(request.rlp_signed(&signature))
+            .await
+            .map_err(|error| eyre::eyre!("Sending failed: {error}"))?;
+        Ok(pending.tx_hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{
+        core::types::{Transaction, TransactionReceipt, U64},
+        providers::{MockProvider, Provider},
+        utils::parse_units,
+    };
+
+    fn gwei(amount: &str) -> U256 {
+        parse_units(amount, "gwei").unwrap().into()
+    }
+
+    fn gas_price(base_fee: &str) -> GasPrice {
+        GasPrice {
+            base_fee: gwei(base_fee),
+            priority_fee: gwei("1"),
+        }
+    }
+
+    fn hash(n: u64) -> H256 {
+        H256::from_low_u64_be(n)
+    }
+
+    fn receipt(status: u64) -> Option<TransactionReceipt> {
+        Some(TransactionReceipt {
+            block_number: Some(101.into()),
+            status: Some(status.into()),
+            ..Default::default()
+        })
+    }
+
+    // Signs with the first default Anvil and Hardhat account
+    fn submitter() -> (Submitter<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        let wallet: LocalWallet =
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+                .parse()
+                .unwrap();
+        (
+            Submitter::new(Arc::new(provider), wallet.with_chain_id(1u64)),
+            mock,
+        )
+    }
+
+    // Submits a transaction at block 100 that the node accepts as `hash`.
+    // The mock answers the last pushed response first
+    async fn submit(
+        submitter: &Submitter<Provider<MockProvider>>,
+        mock: &MockProvider,
+        hash: H256,
+        nonce: Option<u64>,
+    ) {
+        mock.push(hash).unwrap();
+        if let Some(nonce) = nonce {
+            mock.push(U256::from(nonce)).unwrap();
+        }
+        mock.push(U256::from(100_000)).unwrap();
+        let sent = submitter
+            .submit(&TypedTransaction::default(), &gas_price("10"), 100)
+            .await
+            .unwrap();
+        assert_eq!(sent, hash);
+    }
+
+    #[tokio::test]
+    async fn test_nonces_survive_concurrency_and_drops() {
+        let (provider, mock) = Provider::mocked();
+        let nonces = NonceManager::new(Address::zero());
+        mock.push(U256::from(7)).unwrap();
+
+        // Concurrent submissions get consecutive nonces from one lookup
+        let (a, b) = tokio::join!(nonces.reserve(&provider), nonces.reserve(&provider));
+        let mut reserved = vec![a.unwrap(), b.unwrap()];
+        reserved.sort();
+        assert_eq!(reserved, vec![U256::from(7), U256::from(8)]);
+
+        // The last nonce is reused after its transaction failed to send
+        nonces.release(U256::from(8)).await;
+        assert_eq!(nonces.reserve(&provider).await.unwrap(), U256::from(8));
+
+        // Releasing an earlier one, or a drop, means reading the chain again
+        nonces.release(U256::from(7)).await;
+        mock.push(U256::from(7)).unwrap();
+        assert_eq!(nonces.reserve(&provider).await.unwrap(), U256::from(7));
+        nonces.resync().await;
+        mock.push(U256::from(9)).unwrap();
+        assert_eq!(nonces.reserve(&provider).await.unwrap(), U256::from(9));
+    }
+
+    #[tokio::test]
+    async fn test_poll_resolves_mined_and_replaced() {
+        let (submitter, mock) = submitter();
+        submit(&submitter, &mock, hash(1), Some(7)).await;
+        submit(&submitter, &mock, hash(2), None).await;
+        submit(&submitter, &mock, hash(3), None).await;
+
+        // Nonces 7 and 8 were mined as sent, and 9 as something else
+        mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+        mock.push(receipt(0)).unwrap();
+        mock.push(receipt(1)).unwrap();
+        mock.push(U256::from(10)).unwrap();
+        let resolved = submitter.poll(&gas_price("10"), 101).await.unwrap();
+        assert_eq!(
+            resolved,
+            vec![
+                (hash(1), TxStatus::Included { block_number: 101 }),
+                (hash(2), TxStatus::Reverted { block_number: 101 }),
+                (hash(3), TxStatus::Replaced),
+            ]
+        );
+        assert!(submitter.pending.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_poll_cancels_stuck_and_drops_unknown() {
+        let (submitter, mock) = submitter();
+        submit(&submitter, &mock, hash(1), Some(7)).await;
+        let pending = || async {
+            mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+            mock.push(U256::from(7)).unwrap();
+        };
+
+        // Still waiting before STUCK_BLOCKS
+        pending().await;
+        assert!(submitter
+            .poll(&gas_price("10"), 102)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Cancelled once it's stuck, and only once
+        mock.push(hash(2)).unwrap();
+        pending().await;
+        assert!(submitter
+            .poll(&gas_price("10"), 103)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(submitter.pending.lock().await[0].cancelled);
+        // A second cancellation would take this block number instead
+        mock.push(U64::from(42)).unwrap();
+        pending().await;
+        assert!(submitter
+            .poll(&gas_price("10"), 104)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            submitter.client.get_block_number().await.unwrap(),
+            U64::from(42)
+        );
+
+        // Unknown to the node after DROPPED_BLOCKS, which frees its nonce
+        mock.push::<Option<Transaction>, _>(None).unwrap();
+        pending().await;
+        let resolved = submitter.poll(&gas_price("10"), 112).await.unwrap();
+        assert_eq!(resolved, vec![(hash(1), TxStatus::Dropped)]);
+        assert_eq!(*submitter.nonces.next.lock().await, None);
+        assert!(submitter.client.get_block_number().await.is_err());
+    }
+
+    #[test]
+    fn test_cancellation_outbids_the_cancelled() {
+        let (submitter, _mock) = submitter();
+        let tx = PendingTx {
+            hash: hash(1),
+            nonce: U256::from(7),
+            max_fee_per_gas: gwei("21"),
+            max_priority_fee_per_gas: gwei("1"),
+            sent_at: 100,
+            cancelled: false,
+        };
+
+        // 12.5% over the cancelled transaction's fees
+        let request = submitter.cancellation(&tx, &gas_price("10"));
+        assert_eq!(request.max_fee_per_gas, Some(gwei("23.625")));
+        assert_eq!(request.max_priority_fee_per_gas, Some(gwei("1.125")));
+        assert_eq!(request.nonce, Some(U256::from(7)));
+        assert_eq!(request.to, Some(submitter.address().into()));
+
+        // or the current price if the base fee rose further
+        let request = submitter.cancellation(&tx, &gas_price("20"));
+        assert_eq!(request.max_fee_per_gas, Some(gwei("41")));
+    }
+}
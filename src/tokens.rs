@@ -0,0 +1,174 @@
+use crate::contract_interfaces::IERC20;
+use crate::utils::{decode, Token};
+use ethers::{
+    contract::{Multicall, MulticallVersion},
+    core::types::Address,
+    providers::Middleware,
+};
+use eyre::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// Placeholder Curve and others use for native ETH, which has no contract
+pub const ETH_ADDRESS: &str = "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE";
+
+/// Symbol and decimals of every token seen so far, keyed by address. Starts
+/// from a JSON token list, looks up unknown tokens on chain and writes them
+/// back to the list.
+#[derive(Debug, Default)]
+pub struct TokenRegistry {
+    // Where the list is persisted; None keeps it in memory
+    path: Option<PathBuf>,
+    tokens: Mutex<HashMap<Address, Token>>,
+}
+
+impl TokenRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the token list at `path`, starting empty if there's none yet.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let list: Vec<Token> = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(error) => return Err(error.into()),
+        };
+        let mut registry = Self::default().with_tokens(list);
+        registry.path = Some(path);
+        Ok(registry)
+    }
+
+    pub fn with_tokens(self, tokens: impl IntoIterator<Item = Token>) -> Self {
+        self.tokens
+            .lock()
+            .unwrap()
+            .extend(tokens.into_iter().map(|token| (token.address, token)));
+        self
+    }
+
+    pub fn get(&self, address: Address) -> Option<Token> {
+        self.tokens.lock().unwrap().get(&address).cloned()
+    }
+
+    /// Symbol of a known token, or its address.
+    pub fn symbol(&self, address: Address) -> String {
+        self.get(address)
+            .map(|token| token.symbol)
+            .unwrap_or_else(|| format!("{address:?}"))
+    }
+
+    /// Metadata of `addresses`, in order. Unknown tokens are fetched in one
+    /// multicall and saved.
+    pub async fn resolve<M: Middleware + 'static>(
+        &self,
+        client: &Arc<M>,
+        addresses: &[Address],
+    ) -> Result<Vec<Token>> {
+        let eth = ETH_ADDRESS.parse::<Address>()?;
+        let mut missing: Vec<Address> = addresses
+            .iter()
+            .filter(|address| self.get(**address).is_none())
+            .copied()
+            .collect();
+        missing.sort();
+        missing.dedup();
+
+        if !missing.is_empty() {
+            let mut multicall: Multicall<M> = Multicall::new(client.clone(), None)
+                .await?
+                .version(MulticallVersion::Multicall3);
+            for address in &missing {
+                let contract = IERC20::new(*address, client.clone());
+                multicall
+                    .add_call(contract.symbol(), true)
+                    .add_call(contract.decimals(), true);
+            }
+            let results = multicall.call_raw().await?;
+
+            let mut found = vec![];
+            for (address, calls) in missing.iter().zip(results.chunks(2)) {
+                let symbol: Option<String> = decode(calls[0].clone());
+                let decimals: Option<u8> = decode(calls[1].clone());
+                let (symbol, decimals) = match (symbol, decimals) {
+                    (_, None) if *address == eth => ("ETH".to_string(), 18),
+                    // Some older tokens return their symbol as bytes32
+                    (symbol, Some(decimals)) => {
+                        (symbol.unwrap_or_else(|| format!("{address:?}")), decimals)
+                    }
+                    (_, None) => {
+                        return Err(eyre::eyre!("Failed to fetch decimals of {address:?}"))
+                    }
+                };
+                found.push(Token {
+                    symbol,
+                    address: *address,
+                    decimals,
+                });
+            }
+            self.tokens
+                .lock()
+                .unwrap()
+                .extend(found.into_iter().map(|token| (token.address, token)));
+            self.save()?;
+        }
+
+        addresses
+            .iter()
+            .map(|address| {
+                self.get(*address)
+                    .ok_or_else(|| eyre::eyre!("Unknown token {address:?}"))
+            })
+            .collect()
+    }
+
+    /// Writes the list back, sorted by symbol so diffs stay small.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut list: Vec<Token> = self.tokens.lock().unwrap().values().cloned().collect();
+        list.sort_by(|a, b| (&a.symbol, a.address).cmp(&(&b.symbol, b.address)));
+        fs::write(path, serde_json::to_string_pretty(&list)? + "\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_save_token_list() {
+        let path = std::env::temp_dir().join(format!("tokens-{}.json", std::process::id()));
+        let usdc = Token {
+            symbol: "USDC".to_string(),
+            address: Address::from_low_u64_be(1),
+            decimals: 6,
+        };
+
+        let registry = TokenRegistry::load(&path).unwrap();
+        assert!(registry.get(usdc.address).is_none());
+        let registry = registry.with_tokens([usdc.clone()]);
+        registry.save().unwrap();
+
+        let registry = TokenRegistry::load(&path).unwrap();
+        assert_eq!(registry.get(usdc.address), Some(usdc.clone()));
+        assert_eq!(registry.symbol(usdc.address), "USDC");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_mainnet_token_list() {
+        let registry = TokenRegistry::load("./src/data/1/tokens.json").unwrap();
+        let usdc = registry
+            .get(crate::config::MAINNET_USDC_ADDRESS.parse().unwrap())
+            .unwrap();
+        assert_eq!((usdc.symbol.as_str(), usdc.decimals), ("USDC", 6));
+        let eth = registry.get(ETH_ADDRESS.parse().unwrap()).unwrap();
+        assert_eq!(eth.decimals, 18);
+    }
+}
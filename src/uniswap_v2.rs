@@ -1,21 +1,217 @@
-use crate::contract_interfaces::IUniswapV2Pair;
+use crate::contract_interfaces::{IUniswapV2Pair, SyncFilter};
+use crate::exchange::{Exchange, PoolAdapter, PoolState};
+use crate::executor::StepKind;
+use crate::tokens::TokenRegistry;
+use crate::utils::{calc_amount, calc_amount_in, decode};
+use async_trait::async_trait;
 use ethers::{
-    contract::{Multicall, MulticallVersion},
-    core::types::{Address, U256},
-    providers::{Http, Provider},
+    abi::{RawLog, Token},
+    contract::{EthEvent, Multicall, MulticallVersion},
+    core::types::{Address, Log, H256, U256},
+    providers::Middleware,
 };
 use eyre::Result;
 use std::sync::Arc;
 
-pub async fn uniswap_v2_pair(
-    client: &Arc<Provider<Http>>,
+/// Uniswap V2 and its forks (Sushiswap, Pancakeswap, ...).
+pub struct UniswapV2 {
+    name: String,
+    // Fee of pools whose config doesn't set one
+    default_fee_bps: u32,
+}
+
+impl UniswapV2 {
+    pub fn new(name: &str, default_fee_bps: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            default_fee_bps,
+        }
+    }
+}
+
+impl<M: Middleware + 'static> Exchange<M> for UniswapV2 {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn pool(&self, address: Address, fee_bps: Option<u32>) -> Box<dyn PoolState<M>> {
+        let fee_bps = fee_bps.unwrap_or(self.default_fee_bps);
+        Box::new(UniswapV2Pool::new(&self.name, address, fee_bps))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UniswapV2Pool {
+    exchange: String,
+    address: Address,
+    tokens: Vec<Address>,
+    reserves: Vec<U256>,
+    fee_bps: u32,
+}
+
+impl UniswapV2Pool {
+    pub fn new(exchange: &str, address: Address, fee_bps: u32) -> Self {
+        Self {
+            exchange: exchange.to_string(),
+            address,
+            tokens: vec![],
+            reserves: vec![],
+            fee_bps,
+        }
+    }
+
+    pub fn with_state(
+        exchange: &str,
+        address: Address,
+        tokens: Vec<Address>,
+        reserves: Vec<U256>,
+        fee_bps: u32,
+    ) -> Self {
+        Self {
+            exchange: exchange.to_string(),
+            address,
+            tokens,
+            reserves,
+            fee_bps,
+        }
+    }
+
+    // token0 -> token1 if true, token1 -> token0 if false
+    fn direction(&self, token_in: Address, token_out: Address) -> Option<bool> {
+        match self.tokens[..] {
+            [token0, token1] if token_in == token0 && token_out == token1 => Some(true),
+            [token0, token1] if token_in == token1 && token_out == token0 => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> PoolState<M> for UniswapV2Pool {
+    async fn fetch_state(
+        &mut self,
+        client: &Arc<M>,
+        _tokens: &TokenRegistry,
+        block_number: u64,
+    ) -> Result<()> {
+        let (tokens, reserves) = uniswap_v2_pair(client, self.address, block_number).await?;
+        self.tokens = tokens;
+        self.reserves = reserves;
+        Ok(())
+    }
+
+    fn add_state_calls(&self, client: &Arc<M>, multicall: &mut Multicall<M>) -> Result<usize> {
+        let contract = IUniswapV2Pair::new(self.address, client.clone());
+        multicall.add_call(contract.get_reserves(), true);
+        Ok(1)
+    }
+
+    fn apply_state(&mut self, results: &[Token]) -> Result<bool> {
+        let (reserve0, reserve1, _): (u128, u128, u32) = results
+            .first()
+            .cloned()
+            .and_then(decode)
+            .ok_or_else(|| eyre::eyre!("Failed to fetch reserves of {:?}", self.address))?;
+        self.reserves = vec![U256::from(reserve0), U256::from(reserve1)];
+        Ok(true)
+    }
+
+    fn event_source(&self) -> Option<(Address, Vec<H256>)> {
+        Some((self.address, vec![SyncFilter::signature()]))
+    }
+
+    fn apply_log(&mut self, log: &Log) -> Result<bool> {
+        if log.address != self.address || log.topics.first() != Some(&SyncFilter::signature()) {
+            return Ok(false);
+        }
+        let sync = SyncFilter::decode_log(&RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        })?;
+        self.reserves = vec![U256::from(sync.reserve_0), U256::from(sync.reserve_1)];
+        Ok(true)
+    }
+
+    fn restore_reserves(&mut self, reserves: Vec<U256>) {
+        self.reserves = reserves;
+    }
+}
+
+impl PoolAdapter for UniswapV2Pool {
+    fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn tokens(&self) -> &[Address] {
+        &self.tokens
+    }
+
+    fn reserves(&self) -> &[U256] {
+        &self.reserves
+    }
+
+    fn fee(&self) -> U256 {
+        U256::exp10(14) * self.fee_bps
+    }
+
+    fn constant_product_fee_bps(&self) -> Option<u32> {
+        Some(self.fee_bps)
+    }
+
+    fn executor_step(&self) -> Option<StepKind> {
+        Some(StepKind::UniswapV2 {
+            fee_bps: self.fee_bps,
+        })
+    }
+
+    fn quote_exact_in(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+    ) -> Option<U256> {
+        let token0_to_token1 = self.direction(token_in, token_out)?;
+        Some(calc_amount(
+            amount_in,
+            self.reserves[0],
+            self.reserves[1],
+            self.fee_bps,
+            token0_to_token1,
+        ))
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_out: U256,
+    ) -> Option<U256> {
+        let token0_to_token1 = self.direction(token_in, token_out)?;
+        calc_amount_in(
+            amount_out,
+            self.reserves[0],
+            self.reserves[1],
+            self.fee_bps,
+            token0_to_token1,
+        )
+    }
+}
+
+pub async fn uniswap_v2_pair<M: Middleware + 'static>(
+    client: &Arc<M>,
     contract_address: Address,
+    block_number: u64,
 ) -> Result<(Vec<Address>, Vec<U256>)> {
     let mut tokens: Vec<Address> = vec![];
     let mut reserves: Vec<U256> = vec![];
-    let mut multicall: Multicall<Provider<Http>> = Multicall::new(client.clone(), None)
+    let mut multicall: Multicall<M> = Multicall::new(client.clone(), None)
         .await?
-        .version(MulticallVersion::Multicall3);
+        .version(MulticallVersion::Multicall3)
+        .block(block_number);
 
     let contract = IUniswapV2Pair::new(contract_address, client.clone());
     let token_res: ((bool, Address), (bool, Address)) = multicall
@@ -25,7 +221,7 @@ pub async fn uniswap_v2_pair(
         .await?;
     tokens.push(token_res.0 .1);
     tokens.push(token_res.1 .1);
-    let reserve_res: (u128, u128, u32) = contract.get_reserves().call().await?;
+    let reserve_res: (u128, u128, u32) = contract.get_reserves().block(block_number).call().await?;
     reserves.push(U256::from(reserve_res.0));
     reserves.push(U256::from(reserve_res.1));
 
@@ -0,0 +1,803 @@
+use crate::contract_interfaces::IUniswapV3Pool;
+use crate::exchange::{Exchange, PoolAdapter, PoolState};
+use crate::tokens::TokenRegistry;
+use crate::utils::decode;
+use async_trait::async_trait;
+use ethers::{
+    abi::{Token, Tokenizable},
+    contract::{Multicall, MulticallVersion},
+    core::types::{Address, U256, U512},
+    providers::Middleware,
+};
+use eyre::Result;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = 887272;
+
+// Words of the tick bitmap loaded on each side of the current tick
+const TICK_BITMAP_WORDS: i32 = 2;
+// Ticks fetched per multicall
+const TICK_BATCH_SIZE: usize = 100;
+
+// Return values of slot0() and ticks(tick)
+type Slot0 = (U256, i32, u16, u16, u16, u8, bool);
+type TickInfo = (u128, i128, U256, U256, i64, U256, u32, bool);
+
+pub struct UniswapV3 {
+    name: String,
+}
+
+impl UniswapV3 {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+}
+
+impl<M: Middleware + 'static> Exchange<M> for UniswapV3 {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn pool(&self, address: Address, _fee_bps: Option<u32>) -> Box<dyn PoolState<M>> {
+        Box::new(UniswapV3Pool::new(&self.name, address))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UniswapV3Pool {
+    exchange: String,
+    address: Address,
+    tokens: Vec<Address>,
+    // Virtual reserves at the current price
+    reserves: Vec<U256>,
+    // Fee in hundredths of a bip (3000 = 0.3%)
+    fee: u32,
+    tick_spacing: i32,
+    sqrt_price_x96: U256,
+    tick: i32,
+    liquidity: u128,
+    // Initialized ticks and their liquidityNet
+    ticks: BTreeMap<i32, i128>,
+    // Ticks covered by the loaded bitmap words; swaps leaving it can't be quoted
+    tick_range: (i32, i32),
+    // First and last loaded bitmap words
+    bitmap_words: (i32, i32),
+}
+
+impl UniswapV3Pool {
+    pub fn new(exchange: &str, address: Address) -> Self {
+        Self {
+            exchange: exchange.to_string(),
+            address,
+            tokens: vec![],
+            reserves: vec![],
+            fee: 0,
+            tick_spacing: 1,
+            sqrt_price_x96: U256::zero(),
+            tick: 0,
+            liquidity: 0,
+            ticks: BTreeMap::new(),
+            tick_range: (MIN_TICK, MAX_TICK),
+            bitmap_words: (0, -1),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_state(
+        exchange: &str,
+        address: Address,
+        tokens: Vec<Address>,
+        fee: u32,
+        sqrt_price_x96: U256,
+        tick: i32,
+        liquidity: u128,
+        ticks: BTreeMap<i32, i128>,
+    ) -> Self {
+        let mut pool = Self::new(exchange, address);
+        pool.tokens = tokens;
+        pool.fee = fee;
+        pool.sqrt_price_x96 = sqrt_price_x96;
+        pool.tick = tick;
+        pool.liquidity = liquidity;
+        pool.ticks = ticks;
+        pool.reserves = virtual_reserves(sqrt_price_x96, liquidity);
+        pool
+    }
+
+    fn bitmap_len(&self) -> usize {
+        let (first_word, last_word) = self.bitmap_words;
+        (last_word - first_word + 1).max(0) as usize
+    }
+
+    fn set_bitmap_words(&mut self, first_word: i32, last_word: i32) {
+        let spacing = self.tick_spacing;
+        let (min_word, max_word) = (
+            MIN_TICK.div_euclid(spacing) >> 8,
+            MAX_TICK.div_euclid(spacing) >> 8,
+        );
+        self.bitmap_words = (first_word, last_word);
+        self.tick_range = (
+            if first_word == min_word {
+                MIN_TICK
+            } else {
+                (first_word << 8) * spacing
+            },
+            if last_word == max_word {
+                MAX_TICK
+            } else {
+                ((last_word << 8) + 255) * spacing
+            },
+        );
+    }
+
+    // token0 -> token1 if true, token1 -> token0 if false
+    fn direction(&self, token_in: Address, token_out: Address) -> Option<bool> {
+        match self.tokens[..] {
+            [token0, token1] if token_in == token0 && token_out == token1 => Some(true),
+            [token0, token1] if token_in == token1 && token_out == token0 => Some(false),
+            _ => None,
+        }
+    }
+
+    // Simulates UniswapV3Pool.swap against the loaded ticks. Returns the amount
+    // out for exact input swaps and the amount in (fees included) otherwise, or
+    // None if the swap would run past the loaded ticks or the price limit.
+    fn swap(&self, zero_for_one: bool, amount: U256, exact_in: bool) -> Option<U256> {
+        if self.sqrt_price_x96.is_zero() {
+            return None;
+        }
+        let limit = if zero_for_one {
+            min_sqrt_ratio() + 1
+        } else {
+            max_sqrt_ratio() - 1
+        };
+
+        let mut remaining = amount;
+        let mut calculated = U256::zero();
+        let mut sqrt_price = self.sqrt_price_x96;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+
+        while !remaining.is_zero() {
+            let next = if zero_for_one {
+                self.ticks.range(..=tick).next_back()
+            } else {
+                self.ticks.range(tick + 1..).next()
+            };
+            let (tick_next, initialized) = match next {
+                Some((tick_next, _)) => (*tick_next, true),
+                None if zero_for_one => (self.tick_range.0, false),
+                None => (self.tick_range.1, false),
+            };
+            let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+            let sqrt_price_next = sqrt_ratio_at_tick(tick_next);
+            let target = if (zero_for_one && sqrt_price_next < limit)
+                || (!zero_for_one && sqrt_price_next > limit)
+            {
+                limit
+            } else {
+                sqrt_price_next
+            };
+
+            let (sqrt_price_after, amount_in, amount_out, fee_amount) =
+                compute_swap_step(sqrt_price, target, liquidity, remaining, exact_in, self.fee)?;
+            sqrt_price = sqrt_price_after;
+            if exact_in {
+                remaining -= amount_in + fee_amount;
+                calculated += amount_out;
+            } else {
+                remaining -= amount_out;
+                calculated += amount_in + fee_amount;
+            }
+
+            if remaining.is_zero() {
+                break;
+            }
+            if sqrt_price != sqrt_price_next || !initialized {
+                return None;
+            }
+            let liquidity_net = self.ticks[&tick_next];
+            liquidity = if zero_for_one {
+                add_delta(liquidity, -liquidity_net)?
+            } else {
+                add_delta(liquidity, liquidity_net)?
+            };
+            tick = if zero_for_one {
+                tick_next - 1
+            } else {
+                tick_next
+            };
+        }
+
+        Some(calculated)
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> PoolState<M> for UniswapV3Pool {
+    async fn fetch_state(
+        &mut self,
+        client: &Arc<M>,
+        _tokens: &TokenRegistry,
+        block_number: u64,
+    ) -> Result<()> {
+        let contract = IUniswapV3Pool::new(self.address, client.clone());
+        let mut multicall: Multicall<M> = Multicall::new(client.clone(), None)
+            .await?
+            .version(MulticallVersion::Multicall3)
+            .block(block_number);
+
+        let res: (
+            (bool, Address),
+            (bool, Address),
+            (bool, u32),
+            (bool, i32),
+            (bool, u128),
+            (bool, Slot0),
+        ) = multicall
+            .add_call(contract.token_0(), false)
+            .add_call(contract.token_1(), false)
+            .add_call(contract.fee(), false)
+            .add_call(contract.tick_spacing(), false)
+            .add_call(contract.liquidity(), false)
+            .add_call(contract.slot_0(), false)
+            .call()
+            .await?;
+        self.tokens = vec![res.0 .1, res.1 .1];
+        self.fee = res.2 .1;
+        self.tick_spacing = res.3 .1;
+        self.liquidity = res.4 .1;
+        self.sqrt_price_x96 = res.5 .1 .0;
+        self.tick = res.5 .1 .1;
+        self.reserves = virtual_reserves(self.sqrt_price_x96, self.liquidity);
+
+        // Scan the tick bitmap around the current tick for initialized ticks
+        let (first_word, last_word) = bitmap_window(self.tick, self.tick_spacing);
+        multicall.clear_calls();
+        for word_pos in first_word..=last_word {
+            multicall.add_call(contract.tick_bitmap(word_pos as i16), false);
+        }
+        let mut bitmaps = vec![];
+        for token in multicall.call_raw().await? {
+            let (_, bitmap) = <(bool, U256)>::from_token(token)?;
+            bitmaps.push(bitmap);
+        }
+        let initialized_ticks = initialized_ticks(first_word, &bitmaps, self.tick_spacing);
+
+        self.ticks.clear();
+        for batch in initialized_ticks.chunks(TICK_BATCH_SIZE) {
+            multicall.clear_calls();
+            for tick in batch {
+                multicall.add_call(contract.ticks(*tick), false);
+            }
+            for (tick, token) in batch.iter().zip(multicall.call_raw().await?) {
+                let (_, info) = <(bool, TickInfo)>::from_token(token)?;
+                self.ticks.insert(*tick, info.1);
+            }
+        }
+
+        self.set_bitmap_words(first_word, last_word);
+
+        Ok(())
+    }
+
+    fn add_state_calls(&self, client: &Arc<M>, multicall: &mut Multicall<M>) -> Result<usize> {
+        let contract = IUniswapV3Pool::new(self.address, client.clone());
+        let (first_word, last_word) = self.bitmap_words;
+        multicall
+            .add_call(contract.slot_0(), true)
+            .add_call(contract.liquidity(), true);
+        for word_pos in first_word..=last_word {
+            multicall.add_call(contract.tick_bitmap(word_pos as i16), true);
+        }
+        for tick in self.ticks.keys() {
+            multicall.add_call(contract.ticks(*tick), true);
+        }
+        Ok(2 + self.bitmap_len() + self.ticks.len())
+    }
+
+    fn apply_state(&mut self, results: &[Token]) -> Result<bool> {
+        let mut results = results.iter().cloned();
+        let mut next = || results.next().unwrap_or(Token::Bool(false));
+        let error = || eyre::eyre!("Failed to fetch state of {:?}", self.address);
+
+        let slot0: Slot0 = decode(next()).ok_or_else(error)?;
+        let liquidity: u128 = decode(next()).ok_or_else(error)?;
+        let mut bitmaps = vec![];
+        for _ in 0..self.bitmap_len() {
+            bitmaps.push(decode::<U256>(next()).ok_or_else(error)?);
+        }
+        let mut liquidity_net = vec![];
+        for _ in 0..self.ticks.len() {
+            let info: TickInfo = decode(next()).ok_or_else(error)?;
+            liquidity_net.push(info.1);
+        }
+
+        // Ticks were (un)initialized or the price left the loaded words
+        let (first_word, _) = self.bitmap_words;
+        let initialized_ticks = initialized_ticks(first_word, &bitmaps, self.tick_spacing);
+        if bitmap_window(slot0.1, self.tick_spacing) != self.bitmap_words
+            || !initialized_ticks.iter().eq(self.ticks.keys())
+        {
+            return Ok(false);
+        }
+
+        self.sqrt_price_x96 = slot0.0;
+        self.tick = slot0.1;
+        self.liquidity = liquidity;
+        self.reserves = virtual_reserves(self.sqrt_price_x96, self.liquidity);
+        for (net, liquidity_net) in self.ticks.values_mut().zip(liquidity_net) {
+            *net = liquidity_net;
+        }
+        Ok(true)
+    }
+}
+
+impl PoolAdapter for UniswapV3Pool {
+    fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn tokens(&self) -> &[Address] {
+        &self.tokens
+    }
+
+    fn reserves(&self) -> &[U256] {
+        &self.reserves
+    }
+
+    fn fee(&self) -> U256 {
+        U256::from(self.fee) * U256::exp10(12)
+    }
+
+    fn spot_price(&self, token_in: Address, token_out: Address) -> Option<U256> {
+        let precision = U256::exp10(18);
+        let sqrt_price = self.sqrt_price_x96;
+        if self.direction(token_in, token_out)? {
+            mul_div(mul_div(sqrt_price, sqrt_price, q96())?, precision, q96())
+        } else {
+            mul_div(mul_div(q96(), precision, sqrt_price)?, q96(), sqrt_price)
+        }
+    }
+
+    fn quote_exact_in(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+    ) -> Option<U256> {
+        let zero_for_one = self.direction(token_in, token_out)?;
+        self.swap(zero_for_one, amount_in, true)
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_out: U256,
+    ) -> Option<U256> {
+        let zero_for_one = self.direction(token_in, token_out)?;
+        self.swap(zero_for_one, amount_out, false)
+    }
+}
+
+// Bitmap words loaded around `tick`
+fn bitmap_window(tick: i32, spacing: i32) -> (i32, i32) {
+    let min_word = MIN_TICK.div_euclid(spacing) >> 8;
+    let max_word = MAX_TICK.div_euclid(spacing) >> 8;
+    let word = tick.div_euclid(spacing) >> 8;
+    (
+        (word - TICK_BITMAP_WORDS).max(min_word),
+        (word + TICK_BITMAP_WORDS).min(max_word),
+    )
+}
+
+// Ticks flagged in consecutive bitmap words starting at `first_word`
+fn initialized_ticks(first_word: i32, bitmaps: &[U256], spacing: i32) -> Vec<i32> {
+    let mut ticks = vec![];
+    for (word_pos, bitmap) in (first_word..).zip(bitmaps) {
+        for bit in 0..256 {
+            if bitmap.bit(bit) {
+                ticks.push(((word_pos << 8) + bit as i32) * spacing);
+            }
+        }
+    }
+    ticks
+}
+
+fn q96() -> U256 {
+    U256::one() << 96
+}
+
+pub fn min_sqrt_ratio() -> U256 {
+    U256::from(4295128739u64)
+}
+
+pub fn max_sqrt_ratio() -> U256 {
+    U256::from_dec_str("1461446703485210103287273052203988822378723970342").unwrap()
+}
+
+fn virtual_reserves(sqrt_price_x96: U256, liquidity: u128) -> Vec<U256> {
+    let liquidity = U256::from(liquidity);
+    if sqrt_price_x96.is_zero() {
+        return vec![U256::zero(), U256::zero()];
+    }
+    vec![
+        mul_div(liquidity, q96(), sqrt_price_x96).unwrap_or_default(),
+        mul_div(liquidity, sqrt_price_x96, q96()).unwrap_or_default(),
+    ]
+}
+
+fn add_delta(liquidity: u128, delta: i128) -> Option<u128> {
+    if delta < 0 {
+        liquidity.checked_sub(delta.unsigned_abs())
+    } else {
+        liquidity.checked_add(delta as u128)
+    }
+}
+
+// FullMath.mulDiv
+fn mul_div(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    U256::try_from(a.full_mul(b) / U512::from(denominator)).ok()
+}
+
+// FullMath.mulDivRoundingUp
+fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    let result = mul_div(a, b, denominator)?;
+    if (a.full_mul(b) % U512::from(denominator)).is_zero() {
+        Some(result)
+    } else {
+        result.checked_add(U256::one())
+    }
+}
+
+// UnsafeMath.divRoundingUp
+fn div_rounding_up(a: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let (quotient, remainder) = a.div_mod(denominator);
+    Some(if remainder.is_zero() {
+        quotient
+    } else {
+        quotient + 1
+    })
+}
+
+// TickMath.getSqrtRatioAtTick
+pub fn sqrt_ratio_at_tick(tick: i32) -> U256 {
+    const FACTORS: [(u32, u128); 19] = [
+        (0x2, 0xfff97272373d413259a46990580e213a),
+        (0x4, 0xfff2e50f5f656932ef12357cf3c7fdcc),
+        (0x8, 0xffe5caca7e10e4e61c3624eaa0941cd0),
+        (0x10, 0xffcb9843d60f6159c9db58835c926644),
+        (0x20, 0xff973b41fa98c081472e6896dfb254c0),
+        (0x40, 0xff2ea16466c96a3843ec78b326b52861),
+        (0x80, 0xfe5dee046a99a2a811c461f1969c3053),
+        (0x100, 0xfcbe86c7900a88aedcffc83b479aa3a4),
+        (0x200, 0xf987a7253ac413176f2b074cf7815e54),
+        (0x400, 0xf3392b0822b70005940c7a398e4b70f3),
+        (0x800, 0xe7159475a2c29b7443b29c7fa6e889d9),
+        (0x1000, 0xd097f3bdfd2022b8845ad8f792aa5825),
+        (0x2000, 0xa9f746462d870fdf8a65dc1f90e061e5),
+        (0x4000, 0x70d869a156d2a1b890bb3df62baf32f7),
+        (0x8000, 0x31be135f97d08fd981231505542fcfa6),
+        (0x10000, 0x9aa508b5b7a84e1c677de54f3e99bc9),
+        (0x20000, 0x5d6af8dedb81196699c329225ee604),
+        (0x40000, 0x2216e584f5fa1ea926041bedfe98),
+        (0x80000, 0x48a170391f7dc42444e8fa2),
+    ];
+    let abs_tick = tick.unsigned_abs();
+    assert!(abs_tick <= MAX_TICK as u32, "tick out of range");
+
+    let mut ratio = if abs_tick & 0x1 != 0 {
+        U256::from(0xfffcb933bd6fad37aa2d162d1a594001u128)
+    } else {
+        U256::one() << 128
+    };
+    for (mask, factor) in FACTORS {
+        if abs_tick & mask != 0 {
+            ratio = (ratio * U256::from(factor)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    let rounding = if (ratio & U256::from(u32::MAX)).is_zero() {
+        0
+    } else {
+        1
+    };
+    (ratio >> 32) + rounding
+}
+
+// SqrtPriceMath.getAmount0Delta
+fn amount0_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> Option<U256> {
+    let (sqrt_a, sqrt_b) = if sqrt_a > sqrt_b {
+        (sqrt_b, sqrt_a)
+    } else {
+        (sqrt_a, sqrt_b)
+    };
+    if sqrt_a.is_zero() {
+        return None;
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let numerator2 = sqrt_b - sqrt_a;
+    if round_up {
+        div_rounding_up(mul_div_rounding_up(numerator1, numerator2, sqrt_b)?, sqrt_a)
+    } else {
+        Some(mul_div(numerator1, numerator2, sqrt_b)? / sqrt_a)
+    }
+}
+
+// SqrtPriceMath.getAmount1Delta
+fn amount1_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> Option<U256> {
+    let (sqrt_a, sqrt_b) = if sqrt_a > sqrt_b {
+        (sqrt_b, sqrt_a)
+    } else {
+        (sqrt_a, sqrt_b)
+    };
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), sqrt_b - sqrt_a, q96())
+    } else {
+        mul_div(U256::from(liquidity), sqrt_b - sqrt_a, q96())
+    }
+}
+
+// SqrtPriceMath.getNextSqrtPriceFromAmount0RoundingUp
+fn next_sqrt_price_from_amount0(
+    sqrt_price: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Option<U256> {
+    if amount.is_zero() {
+        return Some(sqrt_price);
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let (product, overflow) = amount.overflowing_mul(sqrt_price);
+    if add {
+        if !overflow {
+            let (denominator, overflow) = numerator1.overflowing_add(product);
+            if !overflow {
+                return mul_div_rounding_up(numerator1, sqrt_price, denominator);
+            }
+        }
+        div_rounding_up(numerator1, (numerator1 / sqrt_price).checked_add(amount)?)
+    } else {
+        if overflow || numerator1 <= product {
+            return None;
+        }
+        mul_div_rounding_up(numerator1, sqrt_price, numerator1 - product)
+    }
+}
+
+// SqrtPriceMath.getNextSqrtPriceFromAmount1RoundingDown
+fn next_sqrt_price_from_amount1(
+    sqrt_price: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Option<U256> {
+    let liquidity = U256::from(liquidity);
+    let max_u160 = (U256::one() << 160) - 1;
+    if add {
+        let quotient = if amount <= max_u160 {
+            (amount << 96).checked_div(liquidity)?
+        } else {
+            mul_div(amount, q96(), liquidity)?
+        };
+        sqrt_price.checked_add(quotient)
+    } else {
+        let quotient = if amount <= max_u160 {
+            div_rounding_up(amount << 96, liquidity)?
+        } else {
+            mul_div_rounding_up(amount, q96(), liquidity)?
+        };
+        if sqrt_price <= quotient {
+            return None;
+        }
+        Some(sqrt_price - quotient)
+    }
+}
+
+// SwapMath.computeSwapStep, returning (sqrt price after, amount in, amount out, fee amount)
+fn compute_swap_step(
+    sqrt_price: U256,
+    sqrt_price_target: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    exact_in: bool,
+    fee_pips: u32,
+) -> Option<(U256, U256, U256, U256)> {
+    let zero_for_one = sqrt_price >= sqrt_price_target;
+    let fee = U256::from(fee_pips);
+    let one_minus_fee = U256::from(1_000_000 - fee_pips);
+
+    let mut amount_in = U256::zero();
+    let mut amount_out = U256::zero();
+    let sqrt_price_next = if exact_in {
+        let amount_remaining_less_fee =
+            mul_div(amount_remaining, one_minus_fee, U256::from(1_000_000))?;
+        amount_in = if zero_for_one {
+            amount0_delta(sqrt_price_target, sqrt_price, liquidity, true)?
+        } else {
+            amount1_delta(sqrt_price, sqrt_price_target, liquidity, true)?
+        };
+        if amount_remaining_less_fee >= amount_in {
+            sqrt_price_target
+        } else if zero_for_one {
+            next_sqrt_price_from_amount0(sqrt_price, liquidity, amount_remaining_less_fee, true)?
+        } else {
+            next_sqrt_price_from_amount1(sqrt_price, liquidity, amount_remaining_less_fee, true)?
+        }
+    } else {
+        amount_out = if zero_for_one {
+            amount1_delta(sqrt_price_target, sqrt_price, liquidity, false)?
+        } else {
+            amount0_delta(sqrt_price, sqrt_price_target, liquidity, false)?
+        };
+        if amount_remaining >= amount_out {
+            sqrt_price_target
+        } else if zero_for_one {
+            next_sqrt_price_from_amount1(sqrt_price, liquidity, amount_remaining, false)?
+        } else {
+            next_sqrt_price_from_amount0(sqrt_price, liquidity, amount_remaining, false)?
+        }
+    };
+
+    let max = sqrt_price_target == sqrt_price_next;
+    if zero_for_one {
+        if !max || !exact_in {
+            amount_in = amount0_delta(sqrt_price_next, sqrt_price, liquidity, true)?;
+        }
+        if !max || exact_in {
+            amount_out = amount1_delta(sqrt_price_next, sqrt_price, liquidity, false)?;
+        }
+    } else {
+        if !max || !exact_in {
+            amount_in = amount1_delta(sqrt_price, sqrt_price_next, liquidity, true)?;
+        }
+        if !max || exact_in {
+            amount_out = amount0_delta(sqrt_price, sqrt_price_next, liquidity, false)?;
+        }
+    }
+
+    if !exact_in && amount_out > amount_remaining {
+        amount_out = amount_remaining;
+    }
+
+    let fee_amount = if exact_in && sqrt_price_next != sqrt_price_target {
+        amount_remaining - amount_in
+    } else {
+        mul_div_rounding_up(amount_in, fee, one_minus_fee)?
+    };
+
+    Some((sqrt_price_next, amount_in, amount_out, fee_amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{MockProvider, Provider};
+
+    fn pool(liquidity: u128, ticks: &[(i32, i128)]) -> UniswapV3Pool {
+        UniswapV3Pool::with_state(
+            "UniswapV3",
+            Address::zero(),
+            vec![Address::from_low_u64_be(1), Address::from_low_u64_be(2)],
+            3000,
+            q96(),
+            0,
+            liquidity,
+            ticks.iter().cloned().collect(),
+        )
+    }
+
+    #[test]
+    fn test_sqrt_ratio_at_tick() {
+        assert_eq!(sqrt_ratio_at_tick(0), q96());
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK), min_sqrt_ratio());
+        assert_eq!(sqrt_ratio_at_tick(MAX_TICK), max_sqrt_ratio());
+        assert!(sqrt_ratio_at_tick(-1) < q96());
+        assert!(sqrt_ratio_at_tick(1) > q96());
+    }
+
+    #[test]
+    fn test_swap_within_a_single_range() {
+        let liquidity = 10u128.pow(24);
+        let pool = pool(
+            liquidity,
+            &[(-60000, liquidity as i128), (60000, -(liquidity as i128))],
+        );
+        let (token0, token1) = (pool.tokens[0], pool.tokens[1]);
+        let amount_in = U256::exp10(18);
+
+        // At price 1 with deep liquidity the output is the input less the 0.3% fee
+        let amount_out = pool.quote_exact_in(token0, token1, amount_in).unwrap();
+        assert!(amount_out < U256::exp10(15) * 997);
+        assert!(amount_out > U256::exp10(15) * 996);
+
+        // Exact output for the same amount needs at most the original input
+        let required_in = pool.quote_exact_out(token0, token1, amount_out).unwrap();
+        assert!(required_in <= amount_in);
+        assert!(amount_in - required_in <= U256::one());
+    }
+
+    #[test]
+    fn test_swap_crosses_initialized_ticks() {
+        let liquidity = 10u128.pow(20);
+        let deep = pool(liquidity, &[(-120, 0), (-60, 0), (MIN_TICK, 0)]);
+        let shallow = pool(liquidity, &[(-60, (liquidity / 2) as i128), (MIN_TICK, 0)]);
+        let (token0, token1) = (deep.tokens[0], deep.tokens[1]);
+
+        // Large enough to push the price below tick -60
+        let amount_in = U256::exp10(18);
+        let deep_out = deep.quote_exact_in(token0, token1, amount_in).unwrap();
+        let shallow_out = shallow.quote_exact_in(token0, token1, amount_in).unwrap();
+        assert!(shallow_out < deep_out);
+    }
+
+    #[test]
+    fn test_swap_past_loaded_ticks_is_not_quoted() {
+        let liquidity = 10u128.pow(18);
+        let mut pool = pool(liquidity, &[]);
+        pool.tick_range = (-600, 600);
+        let (token0, token1) = (pool.tokens[0], pool.tokens[1]);
+
+        assert!(pool
+            .quote_exact_in(token0, token1, U256::exp10(14))
+            .is_some());
+        assert!(pool
+            .quote_exact_in(token0, token1, U256::exp10(18))
+            .is_none());
+    }
+
+    #[test]
+    fn test_apply_state_reloads_when_ticks_change() {
+        let liquidity = 10u128.pow(20);
+        let mut pool = pool(liquidity, &[(-60, 5), (60, -5)]);
+        let (first_word, last_word) = bitmap_window(0, 1);
+        pool.set_bitmap_words(first_word, last_word);
+
+        let ok = |value: Token| Token::Tuple(vec![Token::Bool(true), value]);
+        let results = |ticks: &[i32]| {
+            let slot0: Slot0 = (q96(), 0, 0, 0, 0, 0, true);
+            let mut results = vec![ok(slot0.into_token()), ok((liquidity * 2).into_token())];
+            for word_pos in first_word..=last_word {
+                let mut bitmap = U256::zero();
+                for tick in ticks.iter().filter(|tick| *tick >> 8 == word_pos) {
+                    bitmap |= U256::one() << (tick - (word_pos << 8)) as usize;
+                }
+                results.push(ok(bitmap.into_token()));
+            }
+            for net in [7i128, -7] {
+                let info: TickInfo = (0, net, U256::zero(), U256::zero(), 0, U256::zero(), 0, true);
+                results.push(ok(info.into_token()));
+            }
+            results
+        };
+
+        // Any provider; applying results doesn't touch the network
+        let mut apply = |results: Vec<Token>| {
+            PoolState::<Provider<MockProvider>>::apply_state(&mut pool, &results).unwrap()
+        };
+        assert!(apply(results(&[-60, 60])));
+        // A newly initialized tick needs a full reload
+        assert!(!apply(results(&[-60, 0, 60])));
+        assert_eq!(pool.liquidity, liquidity * 2);
+        assert_eq!(pool.ticks[&-60], 7);
+    }
+}
@@ -1,288 +1,299 @@
-use crate::balancer::balancer_pair;
-use crate::contract_interfaces::{IUniswapV2Pair, IERC20};
-use crate::uniswap_v2::uniswap_v2_pair;
+use crate::exchange::PoolAdapter;
+use crate::funding::Lender;
+use crate::optimizer::Trade;
+use crate::price::Price;
 use ethers::{
-    contract::{Multicall, MulticallVersion},
+    abi::{Token as AbiToken, Tokenizable},
     core::types::{Address, U256},
-    providers::{Http, Provider},
     utils::hex::FromHex,
 };
 use eyre::Result;
-use serde::Deserialize;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use std::fs::File;
 use std::io::Read;
-use std::sync::Arc;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {}
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Pair {
     pub pair: Vec<String>,
     pub address: Address,
+    // Swap fee in basis points, when it differs from the exchange default
+    #[serde(
+        default,
+        deserialize_with = "deserialize_fee_bps",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub fee_bps: Option<u32>,
 }
 
-pub async fn _get_symbols(
-    client: &Arc<Provider<Http>>,
-    contract_address: Address,
-) -> Result<(String, String)> {
-    let contract = IUniswapV2Pair::new(contract_address, client.clone());
+// Rejects fees of 100% or more, which the swap math can't price
+fn deserialize_fee_bps<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u32>, D::Error> {
+    let fee_bps = Option::<u32>::deserialize(deserializer)?;
+    match fee_bps {
+        Some(fee_bps) if fee_bps >= 10000 => Err(D::Error::custom(format!(
+            "fee_bps {fee_bps} is not below 10000"
+        ))),
+        _ => Ok(fee_bps),
+    }
+}
 
-    let token_a = IERC20::new(contract.token_0().call().await?, client.clone());
-    let token_b = IERC20::new(contract.token_1().call().await?, client.clone());
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub symbol: String,
+    pub address: Address,
+    pub decimals: u8,
+}
 
-    let mut multicall: Multicall<Provider<Http>> = Multicall::new(client.clone(), None)
-        .await?
-        .version(MulticallVersion::Multicall3);
-    multicall
-        .add_call(token_a.symbol(), false)
-        .add_call(token_b.symbol(), false);
-    let return_data: (String, String) = multicall.call().await?;
+/// A profitable round trip: sell `token_in` for `token_out` on the buy pool,
+/// then sell the proceeds back for `token_in` on the sell pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArbitrageOpportunity {
+    pub token_in: Token,
+    pub token_out: Token,
+    pub buy_exchange: String,
+    pub buy_pool: Address,
+    pub sell_exchange: String,
+    pub sell_pool: Address,
+    pub amount_in: U256,
+    // `token_out` received from the buy pool
+    pub amount_intermediate: U256,
+    pub amount_out: U256,
+    // amount_out - amount_in, before gas
+    pub gross_profit: U256,
+    // Gas of executing the trade, in `token_in`
+    pub gas_cost: U256,
+    // Flash loan fee of borrowing `amount_in`, and who lends it. No lender
+    // means the trade is paid for from the bot's own balance
+    pub loan_fee: U256,
+    pub lender: Option<Lender>,
+    pub net_profit: U256,
+    // Price impacts in bps
+    pub buy_price_impact: U256,
+    pub sell_price_impact: U256,
+    pub block_number: u64,
+}
 
-    Ok((return_data.0, return_data.1))
+/// What a trade pays besides its swaps, in the token it sells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TradeCosts {
+    pub gas_cost: U256,
+    pub loan_fee: U256,
+    pub lender: Option<Lender>,
 }
 
-// 1 token0 = x token1
-pub async fn get_reserves(
-    client: &Arc<Provider<Http>>,
-    contract_address: Address,
-    exchange: &str,
-) -> Result<(U256, U256, U256, U256, u8, u8)> {
-    let token_0: Address;
-    let token_1: Address;
-    let reserves_0: U256;
-    let reserves_1: U256;
-    match exchange {
-        "UniswapV2" | "Sushiswap" | "Pancakeswap" => {
-            let (tokens, reserves) = uniswap_v2_pair(client, contract_address).await?;
-            token_0 = tokens[0];
-            token_1 = tokens[1];
-            reserves_0 = reserves[0];
-            reserves_1 = reserves[1];
-        }
-        "Balancer" => {
-            let (tokens, reserves) = balancer_pair(client, contract_address).await?;
-            token_0 = tokens[0];
-            token_1 = tokens[1];
-            reserves_0 = reserves[0];
-            reserves_1 = reserves[1];
-        }
-        _ => {
-            return Err(eyre::eyre!("Exchange not supported"));
-        }
+impl TradeCosts {
+    pub fn total(&self) -> Option<U256> {
+        self.gas_cost.checked_add(self.loan_fee)
     }
+}
 
-    let token_a = IERC20::new(token_0, client.clone());
-    let token_b = IERC20::new(token_1, client.clone());
-
-    let decimals_a = token_a.decimals().call().await? as u8;
-    let decimals_b = token_b.decimals().call().await? as u8;
+// Decodes a Multicall3 `(success, value)` result, None if the call reverted
+pub fn decode<T: Tokenizable>(token: AbiToken) -> Option<T> {
+    let (success, value) = <(bool, AbiToken)>::from_token(token).ok()?;
+    if !success {
+        return None;
+    }
+    T::from_token(value).ok()
+}
 
-    let precision = U256::exp10(18);
-    let diviser = U256::from(10).pow((18 - decimals_b + decimals_a).into());
-    let current_price_left: U256;
-    let current_price_right: U256;
+// Config files list ETH where pools hold WETH
+fn symbol_matches(configured: &str, on_chain: &str) -> bool {
+    configured.eq_ignore_ascii_case(on_chain)
+        || (configured.eq_ignore_ascii_case("ETH") && on_chain.eq_ignore_ascii_case("WETH"))
+}
 
-    if decimals_a < 18 {
-        current_price_right = (reserves_1 * diviser) / reserves_0;
-    } else {
-        current_price_right = (reserves_1 * precision) / reserves_0;
+/// Addresses of the two pool tokens a config entry's symbol pair names,
+/// lowest address first. None unless each symbol names exactly one of the
+/// pool's tokens and they're different tokens.
+pub fn match_pair(declared: &[String], tokens: &[Token]) -> Option<(Address, Address)> {
+    let find = |symbol: &str| {
+        let mut matches = tokens
+            .iter()
+            .filter(|token| symbol_matches(symbol, &token.symbol));
+        match (matches.next(), matches.next()) {
+            (Some(token), None) => Some(token.address),
+            _ => None,
+        }
+    };
+    let [symbol_a, symbol_b] = declared else {
+        return None;
+    };
+    let (a, b) = (find(symbol_a)?, find(symbol_b)?);
+    match a.cmp(&b) {
+        std::cmp::Ordering::Less => Some((a, b)),
+        std::cmp::Ordering::Greater => Some((b, a)),
+        std::cmp::Ordering::Equal => None,
     }
+}
 
-    if decimals_b < 18 {
-        current_price_left = (reserves_0 * diviser) / reserves_1;
-    } else {
-        current_price_left = (reserves_0 * precision) / reserves_1;
+// Prices of one whole token_b in token_a (left) and one whole token_a in
+// token_b (right), as 18 decimal fixed point numbers, and the pair's reserves
+pub fn get_reserves(
+    pool: &dyn PoolAdapter,
+    token_a: &Token,
+    token_b: &Token,
+) -> Result<(U256, U256, U256, U256)> {
+    let reserve_of = |token: &Token| {
+        pool.tokens()
+            .iter()
+            .position(|t| *t == token.address)
+            .map(|index| pool.reserves()[index])
+            .unwrap_or_default()
+    };
+    let reserves_0 = reserve_of(token_a);
+    let reserves_1 = reserve_of(token_b);
+    if reserves_0.is_zero() || reserves_1.is_zero() {
+        return Err(eyre::eyre!("Pool {:?} has no liquidity", pool.address()));
     }
 
+    let price = Price::from_amounts(reserves_0, token_a.decimals, reserves_1, token_b.decimals)
+        .ok_or_else(|| eyre::eyre!("Can't price the tokens of pool {:?}", pool.address()))?;
+    let overflow = || eyre::eyre!("Price of pool {:?} out of range", pool.address());
+    let current_price_right = price.to_fixed().ok_or_else(overflow)?;
+    let current_price_left = price.invert().to_fixed().ok_or_else(overflow)?;
+
     Ok((
         current_price_left,
         current_price_right,
         reserves_0,
         reserves_1,
-        decimals_a,
-        decimals_b,
     ))
 }
 
+// Prices `trade` as found by `optimizer::best_trade`. token0_to_token1 sells
+// the pair's first token for its second. `costs` and `min_profit` are in the
+// token sold.
 pub fn check_arbitrage_opportunity(
-    token_pair: (&str, &str),
-    prices: &[(String, U256, U256, U256)],
-    threshold: U256,
-    trade_amount: U256,
+    token_pair: (&Token, &Token),
+    trade: &Trade,
+    costs: TradeCosts,
+    min_profit: U256,
     token0_to_token1: bool,
-    decimals_a: u8,
-    decimals_b: u8,
-) -> bool {
-    let mut buy_opportunity: Option<(String, U256, U256, U256)> = None;
-    let mut sell_opportunity: Option<(String, U256, U256, U256)> = None;
-    let selling = if token0_to_token1 {
-        (token_pair.0, decimals_a)
+    block_number: u64,
+) -> Option<ArbitrageOpportunity> {
+    let (token_in, token_out) = if token0_to_token1 {
+        (token_pair.0, token_pair.1)
     } else {
-        (token_pair.1, decimals_b)
+        (token_pair.1, token_pair.0)
     };
-    let buying = if token0_to_token1 {
-        (token_pair.1, decimals_b)
-    } else {
-        (token_pair.0, decimals_a)
-    };
-
-    for (exchange, price, reserve_a, reserve_b) in prices {
-        let calc_prices =
-            calculate_price_impact(trade_amount, *reserve_a, *reserve_b, token0_to_token1);
-        if let Some((price_impact, execution_price)) = calc_prices {
-            println!(
-                "Exchange: {} ({} -> {})| Price Per {}: {} {} ({} dec) | Total Selling: {} {} ({} dec) | Price Impact: {} bps",
-                exchange,
-                selling.0,
-                buying.0,
-                buying.0,
-                price,
-                selling.0,
-                selling.1,
-                trade_amount,
-                selling.0,
-                selling.1,
-                price_impact
-            );
-
-            if buy_opportunity
-                .as_ref()
-                .map_or(true, |(_, _, current_best_impact, _)| {
-                    price_impact < *current_best_impact
-                })
-            {
-                buy_opportunity = Some((exchange.clone(), *price, price_impact, execution_price));
-            }
-            if sell_opportunity
-                .as_ref()
-                .map_or(true, |(_, _, current_best_impact, _)| {
-                    price_impact > *current_best_impact
-                })
-            {
-                sell_opportunity = Some((exchange.clone(), *price, price_impact, execution_price));
-            }
-        }
+    let (buy_pool, sell_pool, trade_amount) = (trade.buy, trade.sell, trade.amount_in);
+    if buy_pool.address() == sell_pool.address() {
+        return None;
     }
-
-    if let (
-        Some((buy_exchange, buy_price, buy_impact, _buy_execution_price)),
-        Some((sell_exchange, sell_price, sell_impact, _sell_execution_price)),
-    ) = (buy_opportunity, sell_opportunity)
-    {
-        if buy_exchange == sell_exchange {
-            println!("No arbitrage opportunity found between different exchanges.\n");
-            return false;
-        }
-        let buy_exchange_reserves = prices
-            .iter()
-            .find(|(exchange, _, _, _)| exchange == &buy_exchange)
-            .map(|(exchange, _, reserve_a, reserve_b)| (exchange, *reserve_a, *reserve_b))
-            .unwrap();
-        let sell_exchange_reserves = prices
-            .iter()
-            .find(|(exchange, _, _, _)| exchange == &sell_exchange)
-            .map(|(exchange, _, reserve_a, reserve_b)| (exchange, *reserve_a, *reserve_b))
-            .unwrap();
-
-        let amount_out_buy = calc_amount(
-            trade_amount,
-            buy_exchange_reserves.1,
-            buy_exchange_reserves.2,
-            token0_to_token1,
-        );
-
-        let amount_out_sell = calc_amount(
-            amount_out_buy,
-            sell_exchange_reserves.1,
-            sell_exchange_reserves.2,
-            !token0_to_token1,
-        );
-        println!(
-            "Trade Route: {} -> {} | {} {} -> {} {} -> {} {}",
-            buy_exchange_reserves.0,
-            sell_exchange_reserves.0,
-            trade_amount,
-            selling.0,
-            amount_out_buy,
-            buying.0,
-            amount_out_sell,
-            selling.0,
-        );
-
-        if amount_out_sell > trade_amount {
-            let arbitrage_profit = amount_out_sell - trade_amount;
-            if arbitrage_profit >= threshold {
-                let p = wei_to_eth(arbitrage_profit);
-                println!(
-                    "Arbitrage opportunity found! Profit {} ETH | Buy {} with {} from {} for price {} (Price Impact: {}), sell {} for {} to {} for price {} (Price Impact: {}).",
-                    p,
-                    selling.0,
-                    buying.0,
-                    buy_exchange,
-                    buy_price,
-                    buy_impact,
-                    selling.0,
-                    buying.0,
-                    sell_exchange,
-                    sell_price,
-                    sell_impact
-                );
-                true
-            } else {
-                println!("Arbitrage opportunity found, but profit is below the threshold.");
-                false
-            }
-        } else {
-            println!("No arbitrage opportunity found with a positive profit.\n");
-            false
-        }
-    } else {
-        println!("No arbitrage opportunity found.\n");
-        false
+    let (buy_price_impact, _) =
+        calculate_price_impact(buy_pool, token_in.address, token_out.address, trade_amount)?;
+
+    let amount_intermediate =
+        buy_pool.quote_exact_in(token_in.address, token_out.address, trade_amount)?;
+    let amount_out =
+        sell_pool.quote_exact_in(token_out.address, token_in.address, amount_intermediate)?;
+    let gross_profit = amount_out.checked_sub(trade_amount)?;
+    let net_profit = gross_profit.checked_sub(costs.total()?)?;
+    if net_profit.is_zero() || net_profit <= min_profit {
+        return None;
     }
+    let (sell_price_impact, _) = calculate_price_impact(
+        sell_pool,
+        token_out.address,
+        token_in.address,
+        amount_intermediate,
+    )?;
+
+    Some(ArbitrageOpportunity {
+        token_in: token_in.clone(),
+        token_out: token_out.clone(),
+        buy_exchange: buy_pool.exchange().to_string(),
+        buy_pool: buy_pool.address(),
+        sell_exchange: sell_pool.exchange().to_string(),
+        sell_pool: sell_pool.address(),
+        amount_in: trade_amount,
+        amount_intermediate,
+        amount_out,
+        gross_profit,
+        gas_cost: costs.gas_cost,
+        loan_fee: costs.loan_fee,
+        lender: costs.lender,
+        net_profit,
+        buy_price_impact,
+        sell_price_impact,
+        block_number,
+    })
 }
 
-fn calc_amount(amount: U256, reserve0: U256, reserve1: U256, token0_to_token1: bool) -> U256 {
-    let in_amount_fee_adjusted = amount * U256::from(997);
+// Constant product getAmountOut with a `fee_bps` swap fee
+pub fn calc_amount(
+    amount: U256,
+    reserve0: U256,
+    reserve1: U256,
+    fee_bps: u32,
+    token0_to_token1: bool,
+) -> U256 {
+    let in_amount_fee_adjusted = amount * U256::from(10000 - fee_bps);
     let (numerator, denominator) = if token0_to_token1 {
         (
             in_amount_fee_adjusted * reserve1,
-            reserve0 * U256::from(1000) + in_amount_fee_adjusted,
+            reserve0 * U256::from(10000) + in_amount_fee_adjusted,
         )
     } else {
         (
             in_amount_fee_adjusted * reserve0,
-            reserve1 * U256::from(1000) + in_amount_fee_adjusted,
+            reserve1 * U256::from(10000) + in_amount_fee_adjusted,
         )
     };
 
-    let out_amount = numerator / denominator;
-
-    out_amount
+    numerator / denominator
 }
 
-fn calculate_price_impact(
+// Inverse of calc_amount: input needed to receive `amount` out, rounded up.
+pub fn calc_amount_in(
     amount: U256,
-    reserve_a: U256,
-    reserve_b: U256,
+    reserve0: U256,
+    reserve1: U256,
+    fee_bps: u32,
     token0_to_token1: bool,
+) -> Option<U256> {
+    let (reserve_in, reserve_out) = if token0_to_token1 {
+        (reserve0, reserve1)
+    } else {
+        (reserve1, reserve0)
+    };
+    if amount >= reserve_out {
+        return None;
+    }
+
+    let numerator = reserve_in * amount * U256::from(10000);
+    let denominator = (reserve_out - amount) * U256::from(10000 - fee_bps);
+
+    Some(numerator / denominator + 1)
+}
+
+// Price impact in bps and execution price (18 decimals) of selling `amount`
+pub fn calculate_price_impact(
+    pool: &dyn PoolAdapter,
+    token_in: Address,
+    token_out: Address,
+    amount: U256,
 ) -> Option<(U256, U256)> {
-    if amount.is_zero() || reserve_a.is_zero() || reserve_b.is_zero() {
+    if amount.is_zero() {
         return None;
     }
 
-    // Calculate mid price (without any trade)
-    let mid_price = if token0_to_token1 {
-        reserve_b * U256::exp10(18) / reserve_a
-    } else {
-        reserve_a * U256::exp10(18) / reserve_b
-    };
+    // Calculate mid price (without any trade), net of the pool's fee so the
+    // impact is slippage alone
+    let precision = U256::exp10(18);
+    let mid_price = pool.spot_price(token_in, token_out)? * (precision - pool.fee()) / precision;
+    if mid_price.is_zero() {
+        return None;
+    }
 
     // Calculate out amount after the trade
-    let out_amount = calc_amount(amount, reserve_a, reserve_b, token0_to_token1);
+    let out_amount = pool.quote_exact_in(token_in, token_out, amount)?;
 
     // Calculate execution price (with slippage)
-    let execution_price = out_amount * U256::exp10(18) / amount;
+    let execution_price = out_amount * precision / amount;
 
     // Calculate price impact
     let price_impact = if execution_price > mid_price {
@@ -294,13 +305,6 @@ fn calculate_price_impact(
     Some((price_impact, execution_price))
 }
 
-pub fn wei_to_eth(wei: U256) -> f64 {
-    let divisor = U256::exp10(18);
-    let wei_f64 = wei.to_string().parse::<f64>().unwrap();
-    let divisor_f64 = divisor.to_string().parse::<f64>().unwrap();
-    wei_f64 / divisor_f64
-}
-
 pub fn _read_exchanges_from_file(
     exchanges: Vec<&str>,
     network: u16,
@@ -321,14 +325,6 @@ pub fn _read_exchanges_from_file(
     Ok(pools)
 }
 
-pub fn create_trade_amount_range(token_decimals: u8) -> (U256, U256, U256) {
-    let trade_amount = U256::from(1) * U256::exp10(token_decimals.into());
-    let max_trade_amount = U256::from(3) * U256::exp10(token_decimals.into());
-    let trade_amount_step = U256::from(1) * U256::exp10((token_decimals).into());
-
-    (trade_amount, max_trade_amount, trade_amount_step)
-}
-
 pub fn bytes32_from_hex(hex: &str) -> [u8; 32] {
     let bytes: Vec<u8> = FromHex::from_hex(hex).unwrap();
     let mut array = [0u8; 32];
@@ -341,70 +337,212 @@ pub fn bytes32_from_hex(hex: &str) -> [u8; 32] {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::uniswap_v2::UniswapV2Pool;
+
+    fn tokens() -> (Token, Token) {
+        let token = |symbol: &str, address: u64| Token {
+            symbol: symbol.to_string(),
+            address: Address::from_low_u64_be(address),
+            decimals: 18,
+        };
+        (token("TokenA", 1), token("TokenB", 2))
+    }
+
+    fn v2_pool(exchange: &str, address: u64, reserve_a: u64, reserve_b: u64) -> UniswapV2Pool {
+        UniswapV2Pool::with_state(
+            exchange,
+            Address::from_low_u64_be(address),
+            vec![Address::from_low_u64_be(1), Address::from_low_u64_be(2)],
+            vec![U256::from(reserve_a), U256::from(reserve_b)],
+            30,
+        )
+    }
 
     #[test]
     fn test_arbitrage_opportunity() {
-        let token_pair = ("TokenA", "TokenB");
-        let prices = vec![
-            (
-                String::from("Exchange1"),
-                U256::from(100),
-                U256::from(1000),
-                U256::from(1000),
-            ),
-            (
-                String::from("Exchange2"),
-                U256::from(200),
-                U256::from(1000),
-                U256::from(500),
-            ),
-        ];
-        let threshold = U256::from(1);
+        let (token_a, token_b) = tokens();
+        let exchange1 = v2_pool("Exchange1", 10, 1000, 1000);
+        let exchange2 = v2_pool("Exchange2", 11, 1000, 500);
         let trade_amount = U256::from(10);
+        let trade = Trade {
+            buy: &exchange1,
+            sell: &exchange2,
+            amount_in: trade_amount,
+            profit: U256::zero(),
+        };
         let token0_to_token1 = true;
 
         let op = check_arbitrage_opportunity(
-            token_pair,
-            &prices,
-            threshold,
-            trade_amount,
+            (&token_a, &token_b),
+            &trade,
+            TradeCosts::default(),
+            U256::zero(),
             token0_to_token1,
-            18,
-            18,
+            1,
         );
-        assert_eq!(op, true);
+        let op = op.unwrap();
+        assert_eq!(op.token_in, token_a);
+        assert_eq!(op.buy_exchange, "Exchange1");
+        assert_eq!(op.sell_exchange, "Exchange2");
+        assert_eq!(op.amount_in, trade_amount);
+        assert_eq!(op.amount_out - op.amount_in, op.gross_profit);
+        assert_eq!(op.net_profit, op.gross_profit);
+        assert_eq!(op.block_number, 1);
+
+        // Gas eating the whole profit, or leaving less than the minimum
+        let check = |gas_cost: U256, min_profit: U256| {
+            let costs = TradeCosts {
+                gas_cost,
+                ..TradeCosts::default()
+            };
+            check_arbitrage_opportunity(
+                (&token_a, &token_b),
+                &trade,
+                costs,
+                min_profit,
+                token0_to_token1,
+                1,
+            )
+        };
+        assert_eq!(check(op.gross_profit, U256::zero()), None);
+        assert_eq!(check(U256::one(), op.gross_profit - 1), None);
+        let op = check(U256::one(), U256::zero()).unwrap();
+        assert_eq!(op.net_profit, op.gross_profit - 1);
+
+        // A flash loan premium comes out of the profit too
+        let costs = TradeCosts {
+            gas_cost: U256::one(),
+            loan_fee: U256::one(),
+            lender: Some(Lender::AaveV2),
+        };
+        let op = check_arbitrage_opportunity(
+            (&token_a, &token_b),
+            &trade,
+            costs,
+            U256::zero(),
+            token0_to_token1,
+            1,
+        )
+        .unwrap();
+        assert_eq!(op.net_profit, op.gross_profit - 2);
+        assert_eq!(op.lender, Some(Lender::AaveV2));
     }
 
     #[test]
     fn test_no_arbitrage_opportunity() {
-        let token_pair = ("TokenA", "TokenB");
-        let prices = vec![
-            (
-                String::from("Exchange1"),
-                U256::from(100),
-                U256::from(1000),
-                U256::from(1000),
-            ),
-            (
-                String::from("Exchange2"),
-                U256::from(100),
-                U256::from(1000),
-                U256::from(1000),
-            ),
-        ];
-        let threshold = U256::from(1);
+        let (token_a, token_b) = tokens();
+        let exchange1 = v2_pool("Exchange1", 10, 1000, 1000);
+        let exchange2 = v2_pool("Exchange2", 11, 1000, 1000);
         let trade_amount = U256::from(10);
+        let trade = Trade {
+            buy: &exchange1,
+            sell: &exchange2,
+            amount_in: trade_amount,
+            profit: U256::zero(),
+        };
         let token0_to_token1 = true;
 
         let op = check_arbitrage_opportunity(
-            token_pair,
-            &prices,
-            threshold,
-            trade_amount,
+            (&token_a, &token_b),
+            &trade,
+            TradeCosts::default(),
+            U256::zero(),
             token0_to_token1,
-            18,
-            18,
+            1,
+        );
+        assert_eq!(op, None);
+    }
+
+    #[test]
+    fn test_get_reserves_usdc_prices() {
+        let (mut usdc, eth) = tokens();
+        usdc.decimals = 6;
+        // 2,000,000 USDC against 1,000 ETH
+        let pool = UniswapV2Pool::with_state(
+            "UniswapV2",
+            Address::zero(),
+            vec![usdc.address, eth.address],
+            vec![U256::from(2_000_000_000_000u64), U256::exp10(21)],
+            30,
+        );
+
+        let (left, right, _, _) = get_reserves(&pool, &usdc, &eth).unwrap();
+        assert_eq!(left, U256::exp10(18) * 2000);
+        assert_eq!(right, U256::exp10(18) / 2000);
+    }
+
+    #[test]
+    fn test_match_pair() {
+        let (token_a, token_b) = tokens();
+        let weth = Token {
+            symbol: "WETH".to_string(),
+            address: Address::from_low_u64_be(3),
+            decimals: 18,
+        };
+        let pair = |a: &str, b: &str| vec![a.to_string(), b.to_string()];
+        let pool = vec![weth.clone(), token_a.clone()];
+
+        // Either order, and ETH standing in for WETH, name the same pair
+        let expected = Some((token_a.address, weth.address));
+        assert_eq!(match_pair(&pair("TokenA", "ETH"), &pool), expected);
+        assert_eq!(match_pair(&pair("ETH", "TokenA"), &pool), expected);
+
+        // A mislabelled entry
+        assert_eq!(match_pair(&pair("TokenB", "ETH"), &pool), None);
+        assert_eq!(match_pair(&pair("TokenA", "TokenA"), &pool), None);
+
+        // Two pool tokens sharing a symbol
+        let twin = Token {
+            symbol: "TokenA".to_string(),
+            ..token_b
+        };
+        assert_eq!(
+            match_pair(&pair("TokenA", "ETH"), &[weth, token_a, twin]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pair_fee_must_be_below_100_percent() {
+        let entry = |fee: &str| {
+            let json = format!(
+                r#"{{"pair": ["LINK", "ETH"], "address": "0x0000000000000000000000000000000000000001"{fee}}}"#
+            );
+            serde_json::from_str::<Pair>(&json)
+        };
+        assert_eq!(entry("").unwrap().fee_bps, None);
+        assert_eq!(entry(r#", "fee_bps": 25"#).unwrap().fee_bps, Some(25));
+        assert!(entry(r#", "fee_bps": 10000"#).is_err());
+    }
+
+    #[test]
+    fn test_calc_amount_in_inverts_calc_amount() {
+        let reserve0 = U256::exp10(21);
+        let reserve1 = U256::from(2) * U256::exp10(24);
+        let amount_out = U256::exp10(20);
+
+        let amount_in = calc_amount_in(amount_out, reserve0, reserve1, 30, false).unwrap();
+        assert!(calc_amount(amount_in, reserve0, reserve1, 30, false) >= amount_out);
+        assert!(calc_amount(amount_in - 1, reserve0, reserve1, 30, false) < amount_out);
+        assert_eq!(calc_amount_in(reserve1, reserve0, reserve1, 30, true), None);
+    }
+
+    #[test]
+    fn test_calc_amount_matches_get_amount_out() {
+        let reserve0 = U256::from(123_456_789_000u64);
+        let reserve1 = U256::exp10(24);
+        let amount = U256::from(987_654_321u64);
+
+        // UniswapV2Library.getAmountOut
+        let uniswap = amount * 997 * reserve1 / (reserve0 * 1000 + amount * 997);
+        assert_eq!(calc_amount(amount, reserve0, reserve1, 30, true), uniswap);
+
+        // Pancakeswap on Ethereum charges 0.25%
+        let pancakeswap = amount * 9975 * reserve1 / (reserve0 * 10000 + amount * 9975);
+        assert_eq!(
+            calc_amount(amount, reserve0, reserve1, 25, true),
+            pancakeswap
         );
-        assert_eq!(op, false);
+        assert!(pancakeswap > uniswap);
     }
 }
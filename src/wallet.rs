@@ -0,0 +1,104 @@
+use ethers::signers::{LocalWallet, Signer};
+use eyre::Result;
+use std::env;
+use std::fmt;
+
+/// Where the key signing arbitrage transactions comes from: a hex
+/// `PRIVATE_KEY`, or a JSON keystore at `KEYSTORE_PATH` decrypted with
+/// `KEYSTORE_PASSWORD`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum KeySource {
+    PrivateKey(String),
+    Keystore { path: String, password: String },
+}
+
+// Keeps keys and passwords out of logs
+impl fmt::Debug for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PrivateKey(_) => write!(f, "PrivateKey(..)"),
+            Self::Keystore { path, .. } => write!(f, "Keystore {{ path: {path:?}, .. }}"),
+        }
+    }
+}
+
+impl KeySource {
+    /// None if neither a key nor a keystore is configured.
+    pub fn from_vars(
+        private_key: Option<String>,
+        keystore_path: Option<String>,
+        keystore_password: Option<String>,
+    ) -> Result<Option<Self>> {
+        match (private_key, keystore_path, keystore_password) {
+            (Some(_), Some(_), _) => Err(eyre::eyre!(
+                "Set either PRIVATE_KEY or KEYSTORE_PATH, not both"
+            )),
+            (Some(key), None, _) => Ok(Some(Self::PrivateKey(key))),
+            (None, Some(path), Some(password)) => Ok(Some(Self::Keystore { path, password })),
+            (None, Some(_), None) => Err(eyre::eyre!(
+                "KEYSTORE_PATH needs KEYSTORE_PASSWORD to decrypt it"
+            )),
+            (None, None, _) => Ok(None),
+        }
+    }
+
+    pub fn from_env() -> Result<Option<Self>> {
+        Self::from_vars(
+            env::var("PRIVATE_KEY").ok(),
+            env::var("KEYSTORE_PATH").ok(),
+            env::var("KEYSTORE_PASSWORD").ok(),
+        )
+    }
+
+    /// The wallet signing for `chain_id`.
+    pub fn wallet(&self, chain_id: u64) -> Result<LocalWallet> {
+        let wallet = match self {
+            Self::PrivateKey(key) => key
+                .trim()
+                .trim_start_matches("0x")
+                .parse::<LocalWallet>()
+                .map_err(|_| eyre::eyre!("PRIVATE_KEY is not a hex private key"))?,
+            Self::Keystore { path, password } => LocalWallet::decrypt_keystore(path, password)
+                .map_err(|error| eyre::eyre!("Can't decrypt keystore {path}: {error}"))?,
+        };
+        Ok(wallet.with_chain_id(chain_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::core::types::Address;
+
+    #[test]
+    fn test_key_source() {
+        let some = |value: &str| Some(value.to_string());
+        assert_eq!(
+            KeySource::from_vars(None, None, some("password")).unwrap(),
+            None
+        );
+        assert!(KeySource::from_vars(None, some("key.json"), None).is_err());
+        assert!(KeySource::from_vars(some("0x01"), some("key.json"), None).is_err());
+        assert_eq!(
+            KeySource::from_vars(None, some("key.json"), some("password")).unwrap(),
+            Some(KeySource::Keystore {
+                path: "key.json".to_string(),
+                password: "password".to_string()
+            })
+        );
+
+        // The first default Anvil and Hardhat account
+        let key = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let source = KeySource::from_vars(some(key), None, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(format!("{source:?}"), "PrivateKey(..)");
+        let wallet = source.wallet(1).unwrap();
+        let address: Address = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+            .parse()
+            .unwrap();
+        assert_eq!(wallet.address(), address);
+        assert_eq!(wallet.chain_id(), 1);
+        assert!(KeySource::PrivateKey("nope".to_string()).wallet(1).is_err());
+    }
+}